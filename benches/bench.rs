@@ -1,8 +1,13 @@
-use neardup;
-
 use rand::Rng;
 
-use criterion::{criterion_group, criterion_main, Criterion};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+/// Length of the document scanned by `n_sweep_benchmark`.
+const SWEEP_DOC_LEN: usize = 10_000;
+/// Length of the query of `n_sweep_benchmark`, which must be at least twice the largest n of the sweep.
+const SWEEP_QUERY_LEN: usize = 100;
+/// n-gram sizes compared by `n_sweep_benchmark`.
+const SWEEP_N: [usize; 10] = [2, 5, 10, 20, 25, 30, 35, 40, 45, 50];
 
 fn criterion_benchmark(c: &mut Criterion) {
     let threshold = 0.6;
@@ -24,9 +29,9 @@ fn criterion_benchmark(c: &mut Criterion) {
 
     let sub_doc = &doc[24..24 + query_len];
     queries.push(sub_doc.to_vec());
-    for i in 0..5 {
+    for _ in 0..5 {
         let mut copy_sub_doc = sub_doc.to_vec();
-        for j in 0..5 {
+        for _ in 0..5 {
             let random_idx = rng.gen_range(0..query_len);
             copy_sub_doc[random_idx] = 0;
         }
@@ -36,25 +41,19 @@ fn criterion_benchmark(c: &mut Criterion) {
     c.bench_function("has_doc_duplicate", |b| {
         b.iter(|| {
             let ngram = neardup::ngram(&queries[0], n);
-            neardup::has_doc_duplicate(doc.clone(), &queries[0], &ngram, threshold as f64, n)
+            neardup::has_doc_duplicate(&doc, &queries[0], &ngram, threshold, n)
         })
     });
     c.bench_function("has_doc_duplicate_rolling", |b| {
         b.iter(|| {
             let ngram = neardup::ngram_rolling(&queries[0], n);
-            neardup::has_doc_duplicate_rolling(
-                doc.clone(),
-                &queries[0],
-                &ngram,
-                threshold as f64,
-                n,
-            )
+            neardup::has_doc_duplicate_rolling(&doc, &queries[0], &ngram, threshold, n)
         })
     });
     // c.bench_function("has_doc_duplicate_naive", |b| {
     //     b.iter(|| {
     //         neardup::has_doc_duplicate_naive(
-    //             doc.clone(),
+    //             &doc,
     //             &queries[0],
     //             args.threshold as f64,
     //             args.n,
@@ -63,5 +62,431 @@ fn criterion_benchmark(c: &mut Criterion) {
     // });
 }
 
-criterion_group!(benches, criterion_benchmark);
+/// Benchmark single vs double rolling hash on a document whose n-grams collide with the query under a single rolling hash,
+/// printing the number of inner-loop invocations of each.
+fn collision_benchmark(c: &mut Criterion) {
+    let threshold = 0.6;
+    let n = 3;
+    let query_len = 50;
+    let mut rng = rand::thread_rng();
+    let query = (0..query_len)
+        .map(|_| rng.gen_range(31..50254))
+        .collect::<Vec<i32>>();
+
+    // (a, b + 1, c - 31) has the same hash as (a, b, c) under base 31
+    let mut doc = Vec::new();
+    while doc.len() < 2048 {
+        let i = rng.gen_range(0..query_len - n);
+        doc.extend_from_slice(&[query[i], query[i + 1] + 1, query[i + 2] - 31]);
+    }
+
+    let ngram = neardup::ngram_rolling(&query, n);
+    let ngram_double = neardup::ngram_rolling_double(&query, n);
+
+    // the number of candidate spans compared in the inner loop with each hash
+    let single = neardup::has_doc_duplicate_rolling_stats(&doc, &query, &ngram, threshold, n);
+    let double =
+        neardup::has_doc_duplicate_rolling_double_stats(&doc, &query, &ngram_double, threshold, n);
+    println!(
+        "inner-loop invocations: single hash {}, double hash {}",
+        single.jaccard_calls, double.jaccard_calls
+    );
+    assert!(double.jaccard_calls < single.jaccard_calls);
+
+    c.bench_function("has_doc_duplicate_rolling_collision", |b| {
+        b.iter(|| neardup::has_doc_duplicate_rolling(&doc, &query, &ngram, threshold, n))
+    });
+    c.bench_function("has_doc_duplicate_rolling_double_collision", |b| {
+        b.iter(|| {
+            neardup::has_doc_duplicate_rolling_double(&doc, &query, &ngram_double, threshold, n)
+        })
+    });
+}
+
+/// Benchmark the frequency vector strategies on a query that hits many n-grams of the document without matching:
+/// rebuilding both frequency vectors per span, reusing the query profile, and updating the span incrementally.
+fn incremental_benchmark(c: &mut Criterion) {
+    let threshold = 0.6;
+    let n = 10;
+    let mut rng = rand::thread_rng();
+    let doc = (0..2048)
+        .map(|_| rng.gen_range(0..50254))
+        .collect::<Vec<i32>>();
+    // a 50-token query stitched from five scattered 10-token chunks of the doc
+    let query = (0..5)
+        .flat_map(|i| doc[i * 400..i * 400 + 10].to_vec())
+        .collect::<Vec<i32>>();
+    let ngram = neardup::ngram(&query, n);
+
+    c.bench_function("has_doc_duplicate_scattered", |b| {
+        b.iter(|| neardup::has_doc_duplicate(&doc, &query, &ngram, threshold, n))
+    });
+    c.bench_function("has_doc_duplicate_with_weighted_jaccard_scattered", |b| {
+        b.iter(|| {
+            neardup::has_doc_duplicate_with(
+                &doc,
+                &query,
+                &ngram,
+                threshold,
+                n,
+                neardup::weighted_jaccard,
+            )
+        })
+    });
+    c.bench_function("has_doc_duplicate_incremental_scattered", |b| {
+        b.iter(|| neardup::has_doc_duplicate_incremental(&doc, &query, &ngram, threshold, n))
+    });
+}
+
+/// Benchmark scanning one document with many queries, borrowing the document vs cloning it per query.
+fn many_queries_benchmark(c: &mut Criterion) {
+    let threshold = 0.6;
+    let n = 10;
+    let query_len = 50;
+    let query_num = 1000;
+    let mut rng = rand::thread_rng();
+    let doc = (0..2048)
+        .map(|_| rng.gen_range(0..50254))
+        .collect::<Vec<i32>>();
+    let queries = (0..query_num)
+        .map(|_| {
+            (0..query_len)
+                .map(|_| rng.gen_range(0..50254))
+                .collect::<Vec<i32>>()
+        })
+        .collect::<Vec<Vec<i32>>>();
+    let ngrams = queries
+        .iter()
+        .map(|query| neardup::ngram(query, n))
+        .collect::<Vec<_>>();
+
+    c.bench_function("has_doc_duplicate_many_queries_clone", |b| {
+        b.iter(|| {
+            queries
+                .iter()
+                .zip(&ngrams)
+                .filter(|(query, ngram)| {
+                    neardup::has_doc_duplicate(&doc.clone(), query, ngram, threshold, n)
+                })
+                .count()
+        })
+    });
+    c.bench_function("has_doc_duplicate_many_queries_borrow", |b| {
+        b.iter(|| {
+            queries
+                .iter()
+                .zip(&ngrams)
+                .filter(|(query, ngram)| {
+                    neardup::has_doc_duplicate(&doc, query, ngram, threshold, n)
+                })
+                .count()
+        })
+    });
+}
+
+/// Benchmark many queries against one 100k-token document, scanning it per query or looking up a `DocIndex` built once.
+fn doc_index_benchmark(c: &mut Criterion) {
+    let threshold = 0.6;
+    let n = 10;
+    let query_len = 50;
+    let query_num = 1000;
+    let mut rng = rand::thread_rng();
+    let doc = (0..100_000)
+        .map(|_| rng.gen_range(0..50254))
+        .collect::<Vec<i32>>();
+    let queries = (0..query_num)
+        .map(|i| {
+            // every tenth query is copied from the document
+            if i % 10 == 0 {
+                let start = rng.gen_range(0..doc.len() - query_len);
+                return doc[start..start + query_len].to_vec();
+            }
+            (0..query_len)
+                .map(|_| rng.gen_range(0..50254))
+                .collect::<Vec<i32>>()
+        })
+        .collect::<Vec<Vec<i32>>>();
+    let ngrams = queries
+        .iter()
+        .map(|query| neardup::ngram(query, n))
+        .collect::<Vec<_>>();
+
+    c.bench_function("doc_index_many_queries_scan", |b| {
+        b.iter(|| {
+            queries
+                .iter()
+                .zip(&ngrams)
+                .filter(|(query, ngram)| {
+                    neardup::has_doc_duplicate(&doc, query, ngram, threshold, n)
+                })
+                .count()
+        })
+    });
+    let index = neardup::DocIndex::new(&doc, n);
+    c.bench_function("doc_index_many_queries_index", |b| {
+        b.iter(|| {
+            queries
+                .iter()
+                .zip(&ngrams)
+                .filter(|(query, ngram)| index.contains(query, ngram, threshold))
+                .count()
+        })
+    });
+    c.bench_function("doc_index_build", |b| {
+        b.iter(|| neardup::DocIndex::new(&doc, n))
+    });
+}
+
+/// Benchmark the serial and parallel scan of a 1M-token document.
+fn par_benchmark(c: &mut Criterion) {
+    let threshold = 0.6;
+    let n = 10;
+    let query_len = 50;
+    let mut rng = rand::thread_rng();
+    let doc = (0..1_000_000)
+        .map(|_| rng.gen_range(0..50254))
+        .collect::<Vec<i32>>();
+    let query = (0..query_len)
+        .map(|_| rng.gen_range(0..50254))
+        .collect::<Vec<i32>>();
+    let ngram = neardup::ngram_rolling(&query, n);
+
+    c.bench_function("has_doc_duplicate_rolling_1m", |b| {
+        b.iter(|| neardup::has_doc_duplicate_rolling(&doc, &query, &ngram, threshold, n))
+    });
+    c.bench_function("has_doc_duplicate_par_1m", |b| {
+        b.iter(|| neardup::has_doc_duplicate_par(&doc, &query, &ngram, threshold, n))
+    });
+}
+
+/// Benchmark building the n-gram set of a 100k-token text with fxhash and rolling hash for small and large n.
+fn ngram_benchmark(c: &mut Criterion) {
+    let mut rng = rand::thread_rng();
+    let text = (0..100_000)
+        .map(|_| rng.gen_range(0..50254))
+        .collect::<Vec<i32>>();
+    for n in [10, 500] {
+        c.bench_function(&format!("ngram_n{}", n), |b| {
+            b.iter(|| neardup::ngram(&text, n))
+        });
+        c.bench_function(&format!("ngram_rolling_n{}", n), |b| {
+            b.iter(|| neardup::ngram_rolling(&text, n))
+        });
+    }
+}
+
+/// Benchmark the exact-match fast path of has_doc_duplicate against the jaccard loop at threshold 1.0.
+fn exact_benchmark(c: &mut Criterion) {
+    let threshold = 1.0;
+    let n = 10;
+    let mut rng = rand::thread_rng();
+    let doc = (0..2048)
+        .map(|_| rng.gen_range(0..50254))
+        .collect::<Vec<i32>>();
+    // a 100-token query sharing many n-grams with the doc but not matching it exactly
+    let mut query = doc[1000..1100].to_vec();
+    query[50] = -1;
+    let ngram = neardup::ngram(&query, n);
+
+    c.bench_function("has_doc_duplicate_exact", |b| {
+        b.iter(|| neardup::has_doc_duplicate(&doc, &query, &ngram, threshold, n))
+    });
+    c.bench_function("has_doc_duplicate_with_weighted_jaccard_exact", |b| {
+        b.iter(|| {
+            neardup::has_doc_duplicate_with(
+                &doc,
+                &query,
+                &ngram,
+                threshold,
+                n,
+                neardup::weighted_jaccard,
+            )
+        })
+    });
+}
+
+/// Benchmark computing the n-grams of a 1M-token query serially and in parallel.
+fn ngram_par_benchmark(c: &mut Criterion) {
+    let n = 10;
+    let mut rng = rand::thread_rng();
+    let text = (0..1_000_000)
+        .map(|_| rng.gen_range(0..50254))
+        .collect::<Vec<i32>>();
+    c.bench_function("ngram_1m", |b| b.iter(|| neardup::ngram(&text, n)));
+    c.bench_function("ngram_par_1m", |b| b.iter(|| neardup::ngram_par(&text, n)));
+}
+
+/// Benchmark building the n-grams of 30k 50-token queries serially and with `ngram_batch_par`.
+fn ngram_batch_par_benchmark(c: &mut Criterion) {
+    let n = 10;
+    let mut rng = rand::thread_rng();
+    let queries = (0..30000)
+        .map(|_| {
+            (0..50)
+                .map(|_| rng.gen_range(0..50254))
+                .collect::<Vec<i32>>()
+        })
+        .collect::<Vec<Vec<i32>>>();
+    c.bench_function("ngram_batch_30k", |b| {
+        b.iter(|| {
+            queries
+                .iter()
+                .map(|query| neardup::ngram(query, n))
+                .collect::<Vec<_>>()
+        })
+    });
+    c.bench_function("ngram_batch_par_30k", |b| {
+        b.iter(|| neardup::ngram_batch_par(&queries, n))
+    });
+}
+
+/// Benchmark weighted jaccard with HashMap counters, sorted merging, and dense Vec counters between a 50-token query and every span of a 2048-token doc.
+fn dense_benchmark(c: &mut Criterion) {
+    let mut rng = rand::thread_rng();
+    let doc = (0..2048)
+        .map(|_| rng.gen_range(0..50254))
+        .collect::<Vec<i32>>();
+    let query = doc[1000..1050].to_vec();
+    let mut vocabulary = neardup::Vocabulary::new();
+    let dense_doc = vocabulary.encode(&doc);
+    let dense_query = vocabulary.encode(&query);
+
+    c.bench_function("weighted_jaccard_spans", |b| {
+        b.iter(|| {
+            doc.windows(query.len())
+                .map(|span| neardup::weighted_jaccard(&query, span))
+                .sum::<f64>()
+        })
+    });
+    c.bench_function("weighted_jaccard_sorted_spans", |b| {
+        b.iter(|| {
+            doc.windows(query.len())
+                .map(|span| neardup::weighted_jaccard_sorted(&query, span))
+                .sum::<f64>()
+        })
+    });
+    c.bench_function("weighted_jaccard_dense_spans", |b| {
+        b.iter(|| {
+            dense_doc
+                .windows(dense_query.len())
+                .map(|span| neardup::weighted_jaccard_dense(&dense_query, span, vocabulary.len()))
+                .sum::<f64>()
+        })
+    });
+}
+
+/// Benchmark weighted jaccard with HashMap counters against byte histograms between a 100-byte query and every span of a 2048-byte DNA-like doc.
+fn u8_benchmark(c: &mut Criterion) {
+    let mut rng = rand::thread_rng();
+    let doc = (0..2048)
+        .map(|_| b"ACGT"[rng.gen_range(0..4)])
+        .collect::<Vec<u8>>();
+    let query = doc[1000..1100].to_vec();
+
+    c.bench_function("weighted_jaccard_bytes_spans", |b| {
+        b.iter(|| {
+            doc.windows(query.len())
+                .map(|span| neardup::weighted_jaccard(&query, span))
+                .sum::<f64>()
+        })
+    });
+    c.bench_function("weighted_jaccard_u8_spans", |b| {
+        b.iter(|| {
+            doc.windows(query.len())
+                .map(|span| neardup::weighted_jaccard_u8(&query, span))
+                .sum::<f64>()
+        })
+    });
+}
+
+/// Benchmark fxhash against rolling hash over a sweep of n, timing the query n-gram build separately from the document scan.
+fn n_sweep_benchmark(c: &mut Criterion) {
+    let threshold = 0.6;
+    let mut rng = rand::thread_rng();
+    let mut doc = (0..SWEEP_DOC_LEN)
+        .map(|_| rng.gen_range(0..50254))
+        .collect::<Vec<i32>>();
+    let query = (0..SWEEP_QUERY_LEN)
+        .map(|_| rng.gen_range(0..50254))
+        .collect::<Vec<i32>>();
+    // plant a copy of the query at the end of the doc with 10 tokens of its first half replaced,
+    // so that the n-grams of its second half hit for every n and the scan finds the match only at the end
+    let mut copy = query.clone();
+    for _ in 0..10 {
+        copy[rng.gen_range(0..SWEEP_QUERY_LEN / 2)] = rng.gen_range(0..50254);
+    }
+    doc[SWEEP_DOC_LEN - SWEEP_QUERY_LEN..].copy_from_slice(&copy);
+
+    let mut group = c.benchmark_group("ngram_build_sweep");
+    for n in SWEEP_N {
+        group.bench_with_input(BenchmarkId::new("fx", n), &n, |b, &n| {
+            b.iter(|| neardup::ngram(&query, n))
+        });
+        group.bench_with_input(BenchmarkId::new("rolling", n), &n, |b, &n| {
+            b.iter(|| neardup::ngram_rolling(&query, n))
+        });
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("scan_sweep");
+    for n in SWEEP_N {
+        let ngram = neardup::ngram(&query, n);
+        assert!(neardup::has_doc_duplicate(
+            &doc, &query, &ngram, threshold, n
+        ));
+        group.bench_with_input(BenchmarkId::new("fx", n), &n, |b, &n| {
+            b.iter(|| neardup::has_doc_duplicate(&doc, &query, &ngram, threshold, n))
+        });
+        let ngram = neardup::ngram_rolling(&query, n);
+        group.bench_with_input(BenchmarkId::new("rolling", n), &n, |b, &n| {
+            b.iter(|| neardup::has_doc_duplicate_rolling(&doc, &query, &ngram, threshold, n))
+        });
+    }
+    group.finish();
+}
+
+/// Benchmark the sampled estimate of has_doc_duplicate_approx against the exact similarity of has_doc_duplicate
+/// on a 500-token query over a small vocabulary, which hits n-grams all over the doc before a perturbed copy at its end.
+fn approx_benchmark(c: &mut Criterion) {
+    let threshold = 0.7;
+    let n = 2;
+    let mut rng = rand::thread_rng();
+    let query = (0..500)
+        .map(|_| rng.gen_range(0..200))
+        .collect::<Vec<i32>>();
+    let mut copy = query.clone();
+    for _ in 0..25 {
+        copy[rng.gen_range(0..query.len())] = rng.gen_range(0..200);
+    }
+    let doc = [(0..10_000).map(|_| rng.gen_range(0..200)).collect(), copy].concat();
+    let ngram = neardup::ngram(&query, n);
+    assert!(neardup::has_doc_duplicate(
+        &doc, &query, &ngram, threshold, n
+    ));
+
+    c.bench_function("has_doc_duplicate_small_vocabulary", |b| {
+        b.iter(|| neardup::has_doc_duplicate(&doc, &query, &ngram, threshold, n))
+    });
+    c.bench_function("has_doc_duplicate_approx_small_vocabulary", |b| {
+        b.iter(|| neardup::has_doc_duplicate_approx(&doc, &query, &ngram, threshold, n, 64))
+    });
+}
+
+criterion_group!(
+    benches,
+    criterion_benchmark,
+    collision_benchmark,
+    incremental_benchmark,
+    many_queries_benchmark,
+    doc_index_benchmark,
+    par_benchmark,
+    ngram_benchmark,
+    exact_benchmark,
+    ngram_par_benchmark,
+    ngram_batch_par_benchmark,
+    dense_benchmark,
+    n_sweep_benchmark,
+    u8_benchmark,
+    approx_benchmark
+);
 criterion_main!(benches);
@@ -0,0 +1,74 @@
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use rand::Rng;
+
+/// Number of files searched by `file_parallelism_benchmark`.
+const FILE_NUM: usize = 16;
+/// Number of documents of each file.
+const DOC_NUM: usize = 200;
+/// Length of each document.
+const DOC_LEN: usize = 500;
+
+/// Write `FILE_NUM` small `.jsonl.gz` files of random documents and a query file to `dir`.
+fn write_corpus(dir: &Path) {
+    let mut rng = rand::thread_rng();
+    fs::create_dir_all(dir.join("docs")).unwrap();
+    for i in 0..FILE_NUM {
+        let path = dir.join("docs").join(format!("sample-{:05}.jsonl.gz", i));
+        let mut encoder = GzEncoder::new(fs::File::create(path).unwrap(), Compression::default());
+        for _ in 0..DOC_NUM {
+            let token_ids = (0..DOC_LEN)
+                .map(|_| rng.gen_range(0..50254))
+                .collect::<Vec<i32>>();
+            writeln!(encoder, "{}", serde_json::json!({ "token_ids": token_ids })).unwrap();
+        }
+        encoder.finish().unwrap();
+    }
+    let mut queries = fs::File::create(dir.join("query.jsonl")).unwrap();
+    for _ in 0..20 {
+        let token_ids = (0..50)
+            .map(|_| rng.gen_range(0..50254))
+            .collect::<Vec<i32>>();
+        writeln!(queries, "{}", serde_json::json!({ "token_ids": token_ids })).unwrap();
+    }
+}
+
+/// Benchmark the command line tool over 16 small files, processing one file at a time or several concurrently.
+fn file_parallelism_benchmark(c: &mut Criterion) {
+    let dir = std::env::temp_dir().join(format!("neardup-bench-files-{}", std::process::id()));
+    write_corpus(&dir);
+    let mut group = c.benchmark_group("file_parallelism");
+    group.sample_size(10);
+    for file_parallelism in [1, 4] {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(file_parallelism),
+            &file_parallelism,
+            |b, file_parallelism| {
+                b.iter(|| {
+                    let status = Command::new(env!("CARGO_BIN_EXE_neardup"))
+                        .arg("--search-dir")
+                        .arg(dir.join("docs"))
+                        .arg("--query-path")
+                        .arg(dir.join("query.jsonl"))
+                        .args(["--n", "10", "--quiet"])
+                        .args(["--file-parallelism", &file_parallelism.to_string()])
+                        .stderr(Stdio::null())
+                        .status()
+                        .unwrap();
+                    assert!(status.success());
+                })
+            },
+        );
+    }
+    group.finish();
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+criterion_group!(benches, file_parallelism_benchmark);
+criterion_main!(benches);
@@ -0,0 +1,20 @@
+import neardup
+import pytest
+
+
+def test_has_doc_duplicate():
+    query = [1, 2, 3, 4, 5]
+    doc = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10]
+    assert neardup.has_doc_duplicate(doc, query, 0.8, 3) is True
+    assert neardup.has_doc_duplicate(doc, [11, 12, 13, 14, 15], 0.8, 3) is False
+
+
+def test_has_doc_duplicate_invalid_query():
+    with pytest.raises(ValueError):
+        neardup.has_doc_duplicate([1, 2, 3], [], 0.8, 3)
+    with pytest.raises(ValueError):
+        neardup.has_doc_duplicate([1, 2, 3], [1, 2], 0.8, 3)
+
+
+def test_weighted_jaccard():
+    assert neardup.weighted_jaccard([1, 2, 2], [1, 1, 2]) == 2.0 / 4.0
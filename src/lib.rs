@@ -12,9 +12,9 @@
 //!     l_s = len(s)
 //!     l_d = len(d)
 //!     H = set(ngram(s, n))
-//!     for i in range(max(l_d - l_s, 0)):
+//!     for i in range(max(l_d - n + 1, 0)):
 //!         if d[i:i+n] in H:
-//!             for j in range(max(i - l_s + n, 0), i):
+//!             for j in range(max(i - l_s + n, 0), min(i, l_d - l_s) + 1):
 //!                 t = d[j:j+l_s]
 //!                 if Jaccard_W(s, t) >= threshold:
 //!                     return True
@@ -24,12 +24,78 @@
 //! You can use fast hash functions like [fxhash](https://docs.rs/fxhash/latest/fxhash/) or [rolling hash](https://en.wikipedia.org/wiki/Rolling_hash).
 //! When the size of $n$ of $n$-gram is small, fxhash is faster than rolling hash. However, when the size of $n$ is large, rolling hash is faster than fxhash because the rolling hash can calculate the hash value of the next $n$-gram in $O(1)$ time.
 
-use fxhash;
-
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
 use rustc_hash::FxHashSet as HashSet;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use std::borrow::Borrow;
+use std::cmp::{max, min, Reverse};
+use std::collections::{BinaryHeap, HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+#[cfg(feature = "serde")]
+use std::path::Path;
+#[cfg(feature = "parallel")]
+use std::sync::atomic::{AtomicBool, Ordering};
+
+#[cfg(feature = "python")]
+mod python;
+#[cfg(feature = "text")]
+pub mod text;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+/// Errors returned by the fallible matching functions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NeardupError {
+    /// The query has no tokens.
+    EmptyQuery,
+    /// The n-gram size is zero.
+    ZeroN,
+    /// The n-gram size is larger than the query.
+    NLargerThanQuery,
+    /// The n-gram set was built with a different n-gram size than the one used for matching.
+    NgramSizeMismatch {
+        /// The n-gram size of the n-gram set.
+        expected: usize,
+        /// The n-gram size used for matching.
+        actual: usize,
+    },
+}
+
+impl std::fmt::Display for NeardupError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NeardupError::EmptyQuery => write!(f, "query is empty"),
+            NeardupError::ZeroN => write!(f, "n must be larger than 0"),
+            NeardupError::NLargerThanQuery => write!(f, "n must not be larger than the query"),
+            NeardupError::NgramSizeMismatch { expected, actual } => write!(
+                f,
+                "n-gram set was built with n = {}, but n = {} was given",
+                expected, actual
+            ),
+        }
+    }
+}
+
+impl std::error::Error for NeardupError {}
 
-use std::cmp::max;
-use std::collections::HashMap;
+/// Check that the query and n are valid for matching.
+fn validate_query<T>(query: &[T], n: usize) -> Result<(), NeardupError> {
+    if query.is_empty() {
+        return Err(NeardupError::EmptyQuery);
+    }
+    if n == 0 {
+        return Err(NeardupError::ZeroN);
+    }
+    if n > query.len() {
+        return Err(NeardupError::NLargerThanQuery);
+    }
+    Ok(())
+}
 
 /// A struct for rolling hash.
 /// # Examples
@@ -41,6 +107,7 @@ use std::collections::HashMap;
 /// }
 /// assert_eq!(rolling_hash.get_hash(), (1 * u64::pow(31, 4) + 2 * u64::pow(31, 3) + 3 * u64::pow(31, 2) + 4 * 31 + 5) % 1_000_000_007);
 /// ```
+#[derive(Clone, Debug)]
 pub struct RollingHash {
     base: u64,
     modulo: u64,
@@ -49,17 +116,85 @@ pub struct RollingHash {
     window_size: usize,
 }
 
+impl Default for RollingHash {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl RollingHash {
     pub fn new() -> Self {
+        Self::with_params(31, 1_000_000_007)
+    }
+
+    /// Create a rolling hash with a custom base and modulo.
+    ///
+    /// A base larger than the alphabet size and a large prime modulo reduce collisions.
+    /// The parameters must satisfy `1 < base < modulo`, and the modulo must be prime (required by [`RollingHash::pop_front`]).
+    /// Any modulo up to `u64::MAX` can be used since the arithmetic is done in `u128`.
+    /// # Examples
+    /// ```
+    /// let mut rolling_hash = neardup::RollingHash::with_params(1_000_003, (1 << 61) - 1);
+    /// for c in [1, 2, 3] {
+    ///     rolling_hash.append(c);
+    /// }
+    /// assert_eq!(rolling_hash.get_hash(), 1_000_003 * 1_000_003 + 2 * 1_000_003 + 3);
+    /// ```
+    pub fn with_params(base: u64, modulo: u64) -> Self {
+        assert!(
+            1 < base && base < modulo,
+            "base must satisfy 1 < base < modulo"
+        );
         Self {
-            base: 31,
-            modulo: 1_000_000_007,
+            base,
+            modulo,
             hash: 0,
             base_power: 1,
             window_size: 0,
         }
     }
 
+    /// Create a rolling hash whose window holds the given characters.
+    /// # Examples
+    /// ```
+    /// let mut rolling_hash = neardup::RollingHash::new();
+    /// for c in [1, 2, 3, 4, 5] {
+    ///     rolling_hash.append(c);
+    /// }
+    /// assert_eq!(neardup::RollingHash::from_slice(&[1, 2, 3, 4, 5]).get_hash(), rolling_hash.get_hash());
+    /// ```
+    pub fn from_slice(chars: &[u64]) -> Self {
+        Self::from_params_slice(31, 1_000_000_007, chars)
+    }
+
+    /// Create a rolling hash with a custom base and modulo whose window holds the given characters.
+    ///
+    /// The parameters must satisfy the same constraints as [`RollingHash::with_params`].
+    /// # Examples
+    /// ```
+    /// let rolling_hash = neardup::RollingHash::from_params_slice(1_000_003, (1 << 61) - 1, &[1, 2, 3]);
+    /// assert_eq!(rolling_hash.get_hash(), 1_000_003 * 1_000_003 + 2 * 1_000_003 + 3);
+    /// ```
+    pub fn from_params_slice(base: u64, modulo: u64, chars: &[u64]) -> Self {
+        let mut rolling_hash = Self::with_params(base, modulo);
+        rolling_hash.append_slice(chars);
+        rolling_hash
+    }
+
+    /// Empty the window, keeping the base and modulo, so the rolling hash can be reused for another text.
+    /// # Examples
+    /// ```
+    /// let mut rolling_hash = neardup::RollingHash::from_slice(&[1, 2, 3]);
+    /// rolling_hash.reset();
+    /// rolling_hash.append(4);
+    /// assert_eq!(rolling_hash.get_hash(), neardup::RollingHash::from_slice(&[4]).get_hash());
+    /// ```
+    pub fn reset(&mut self) {
+        self.hash = 0;
+        self.base_power = 1;
+        self.window_size = 0;
+    }
+
     /// Append a character to the window.
     /// # Examples
     /// ```
@@ -71,12 +206,25 @@ impl RollingHash {
     /// assert_eq!(rolling_hash.get_hash(), (1 * u64::pow(31, 4) + 2 * u64::pow(31, 3) + 3 * u64::pow(31, 2) + 4 * 31 + 5) % 1_000_000_007);
     /// ```
     pub fn append(&mut self, char: u64) {
-        self.hash = (self.hash * self.base + char) % self.modulo;
+        self.hash = self.add_mod(self.mul_mod(self.hash, self.base), char);
         self.window_size += 1;
         if self.window_size == 1 {
             self.base_power = 1;
         } else {
-            self.base_power = (self.base_power * self.base) % self.modulo;
+            self.base_power = self.mul_mod(self.base_power, self.base);
+        }
+    }
+
+    /// Append multiple characters to the window, in order.
+    /// # Examples
+    /// ```
+    /// let mut rolling_hash = neardup::RollingHash::from_slice(&[1, 2]);
+    /// rolling_hash.append_slice(&[3, 4, 5]);
+    /// assert_eq!(rolling_hash.get_hash(), neardup::RollingHash::from_slice(&[1, 2, 3, 4, 5]).get_hash());
+    /// ```
+    pub fn append_slice(&mut self, chars: &[u64]) {
+        for c in chars {
+            self.append(*c);
         }
     }
 
@@ -92,9 +240,72 @@ impl RollingHash {
     /// assert_eq!(rolling_hash.get_hash(), (2 * u64::pow(31, 4) + 3 * u64::pow(31, 3) + 4 * u64::pow(31, 2) + 5 * 31 + 6) % 1_000_000_007);
     /// ```
     pub fn slide(&mut self, old_char: u64, new_char: u64) {
-        self.hash =
-            ((self.hash + self.modulo) - (old_char * self.base_power) % self.modulo) % self.modulo;
-        self.hash = (self.hash * self.base + new_char) % self.modulo;
+        debug_assert!(self.window_size > 0, "slide on an empty window");
+        self.hash = self.sub_mod(self.hash, self.mul_mod(old_char, self.base_power));
+        self.hash = self.add_mod(self.mul_mod(self.hash, self.base), new_char);
+    }
+
+    /// Slide the window by multiple positions, removing the old characters and adding the new characters in order.
+    ///
+    /// Equivalent to calling [`RollingHash::slide`] with each pair of `old_chars` and `new_chars`,
+    /// so `old_chars` must be the characters at the front of the window, followed by the new characters if it slides past the window.
+    /// # Panics
+    /// Panics if `old_chars` and `new_chars` have different lengths.
+    /// # Examples
+    /// ```
+    /// let mut rolling_hash = neardup::RollingHash::from_slice(&[1, 2, 3, 4, 5]);
+    /// rolling_hash.slide_slice(&[1, 2], &[6, 7]);
+    /// assert_eq!(rolling_hash.get_hash(), neardup::RollingHash::from_slice(&[3, 4, 5, 6, 7]).get_hash());
+    /// ```
+    pub fn slide_slice(&mut self, old_chars: &[u64], new_chars: &[u64]) {
+        assert_eq!(
+            old_chars.len(),
+            new_chars.len(),
+            "old_chars and new_chars must have the same length"
+        );
+        for (old_char, new_char) in old_chars.iter().zip(new_chars) {
+            self.slide(*old_char, *new_char);
+        }
+    }
+
+    /// Remove the oldest character from the window without adding a new one.
+    ///
+    /// `old_char` must be the character at the front of the window.
+    /// The modulo must be prime since `base_power` is shrunk by multiplying it with the modular inverse of `base`.
+    /// # Examples
+    /// ```
+    /// let text = vec![1, 2, 3, 4, 5];
+    /// let mut rolling_hash = neardup::RollingHash::new();
+    /// for c in text.iter().map(|v| *v as u64) {
+    ///     rolling_hash.append(c);
+    /// }
+    /// rolling_hash.pop_front(1);
+    /// assert_eq!(rolling_hash.get_hash(), (2 * u64::pow(31, 3) + 3 * u64::pow(31, 2) + 4 * 31 + 5) % 1_000_000_007);
+    /// ```
+    pub fn pop_front(&mut self, old_char: u64) {
+        assert!(self.window_size > 0, "pop_front on an empty window");
+        self.hash = self.sub_mod(self.hash, self.mul_mod(old_char, self.base_power));
+        self.window_size -= 1;
+        if self.window_size <= 1 {
+            self.base_power = 1;
+        } else {
+            let base_inverse = mod_pow(self.base, self.modulo - 2, self.modulo);
+            self.base_power = self.mul_mod(self.base_power, base_inverse);
+        }
+    }
+
+    /// Get the number of characters in the current window.
+    ///
+    /// [`RollingHash::slide`] keeps the window size, so check it before sliding to make sure the window is full.
+    /// # Examples
+    /// ```
+    /// let mut rolling_hash = neardup::RollingHash::from_slice(&[1, 2, 3]);
+    /// assert_eq!(rolling_hash.window_size(), 3);
+    /// rolling_hash.slide(1, 4);
+    /// assert_eq!(rolling_hash.window_size(), 3);
+    /// ```
+    pub fn window_size(&self) -> usize {
+        self.window_size
     }
 
     /// Get the hash value of the current window.
@@ -110,257 +321,5902 @@ impl RollingHash {
     pub fn get_hash(&self) -> u64 {
         self.hash
     }
-}
 
-fn create_frequency_vector<'a>(set: &'a [i32]) -> HashMap<&'a i32, usize> {
-    let mut frequency_vector: HashMap<&i32, usize> = HashMap::new();
-    for element in set {
-        *frequency_vector.entry(element).or_insert(0) += 1;
+    fn add_mod(&self, a: u64, b: u64) -> u64 {
+        ((a as u128 + b as u128) % self.modulo as u128) as u64
     }
-    frequency_vector
-}
 
-/// Compute weighted jaccard similarity between two texts.
-pub fn weighted_jaccard(text1: &[i32], text2: &[i32]) -> f64 {
-    let x = create_frequency_vector(text1);
-    let y = create_frequency_vector(text2);
-    let mut intersection_frequency = 0;
-    for (element, frequency1) in &x {
-        if let Some(frequency2) = y.get(element) {
-            intersection_frequency += frequency1.min(frequency2);
-        }
+    fn sub_mod(&self, a: u64, b: u64) -> u64 {
+        ((a as u128 + self.modulo as u128 - b as u128 % self.modulo as u128) % self.modulo as u128)
+            as u64
     }
 
-    let sum_of_frequencies_x: usize = x.values().sum();
-    let sum_of_frequencies_y: usize = y.values().sum();
-    let union_frequency = sum_of_frequencies_x + sum_of_frequencies_y - intersection_frequency;
-    if union_frequency > 0 {
-        intersection_frequency as f64 / union_frequency as f64
-    } else {
-        0.0
+    fn mul_mod(&self, a: u64, b: u64) -> u64 {
+        (a as u128 * b as u128 % self.modulo as u128) as u64
     }
 }
 
-/// Compute n-grams of a text using fxhash.
-///
-/// # Examples
-///
-/// ```
-/// let text = vec![1, 2, 3, 4, 5];
-/// let ngrams = neardup::ngram(&text, 2);
-/// assert_eq!(ngrams.len(), 4);
-/// assert_eq!(ngrams.contains(&fxhash::hash(&vec![1, 2])), true);
-/// ```
-pub fn ngram(text: &[i32], n: usize) -> HashSet<usize> {
-    let mut ngrams = HashSet::default();
-    for i in 0..text.len() - n + 1 {
-        ngrams.insert(fxhash::hash(&text[i..i + n]));
-    }
-    ngrams
-}
+/// Mersenne prime modulo of the MinHash permutations.
+const MINHASH_PRIME: u64 = (1 << 61) - 1;
 
-/// Compute n-grams of a text using rolling hash.
+/// A MinHash signature of the n-grams of a text, for estimating jaccard similarity cheaply.
 ///
+/// Use it to coarsely cluster documents, then verify candidates with [`has_doc_duplicate`].
+/// Signatures are comparable only if they are created with the same `num_perm`, `seed`, and n-gram size.
 /// # Examples
-///
 /// ```
-/// let text = vec![1, 2, 3, 4, 5];
-/// let ngrams = neardup::ngram_rolling(&text, 2);
-/// assert_eq!(ngrams.len(), 4);
-/// let mut rolling_hash = neardup::RollingHash::new();
-/// for c in vec![1, 2].iter().map(|v| *v as u64) {
-///    rolling_hash.append(c);
-/// }
-/// assert_eq!(ngrams.contains(&(rolling_hash.get_hash() as usize)), true);
+/// let mut a = neardup::MinHash::new(128, 42);
+/// a.update(&[1, 2, 3, 4]);
+/// let mut b = neardup::MinHash::new(128, 42);
+/// b.update(&[1, 2, 3, 4]);
+/// assert_eq!(a.jaccard_estimate(&b), 1.0);
 /// ```
-pub fn ngram_rolling(text: &[i32], n: usize) -> HashSet<usize> {
-    let mut ngrams = HashSet::default();
-    for i in 0..text.len() - n + 1 {
-        let text = text.iter().map(|v| *v as u64).collect::<Vec<u64>>();
-        let mut rolling_hash = RollingHash::new();
-        for c in text[i..i + n].iter() {
-            rolling_hash.append(*c);
-        }
-        ngrams.insert(rolling_hash.hash as usize);
-    }
-    ngrams
+#[derive(Debug, Clone)]
+pub struct MinHash {
+    n: usize,
+    permutations: Vec<(u64, u64)>,
+    signature: Vec<u64>,
 }
 
-// test
-#[cfg(test)]
-mod tests {
-    use super::*;
+impl MinHash {
+    /// Create an empty signature with `num_perm` permutations over unigrams.
+    pub fn new(num_perm: usize, seed: u64) -> Self {
+        Self::with_ngram_size(num_perm, seed, 1)
+    }
 
-    #[test]
-    fn test_weighted_jaccard() {
-        let text1 = vec![1, 2, 3, 4, 5];
-        let text2 = vec![1, 2, 3, 4, 5];
-        assert_eq!(weighted_jaccard(&text1, &text2), 1.0);
-        let text1 = vec![1, 2, 2];
-        let text2 = vec![1, 1, 2];
-        assert_eq!(weighted_jaccard(&text1, &text2), (2.0) / 4.0);
-        let text1 = vec![1, 1, 2, 3];
-        let text2 = vec![1, 2, 2, 2];
-        assert_eq!(weighted_jaccard(&text1, &text2), (2.0) / 6.0);
-        let text1 = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
-        let text2 = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+    /// Create an empty signature with `num_perm` permutations over n-grams of size `n`.
+    pub fn with_ngram_size(num_perm: usize, seed: u64, n: usize) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let permutations = (0..num_perm)
+            .map(|_| {
+                (
+                    rng.gen_range(1..MINHASH_PRIME),
+                    rng.gen_range(0..MINHASH_PRIME),
+                )
+            })
+            .collect();
+        Self {
+            n,
+            permutations,
+            signature: vec![u64::MAX; num_perm],
+        }
     }
 
-    #[test]
-    fn test_ngram() {
-        let text = vec![1, 2, 3, 4, 5];
-        let mut ngrams = HashSet::default();
-        ngrams.insert(fxhash::hash(&vec![1, 2]));
-        ngrams.insert(fxhash::hash(&vec![2, 3]));
-        ngrams.insert(fxhash::hash(&vec![3, 4]));
-        ngrams.insert(fxhash::hash(&vec![4, 5]));
-        assert_eq!(ngram(&text, 2), ngrams);
+    /// Fold the n-grams of the tokens into the signature.
+    pub fn update(&mut self, tokens: &[i32]) {
+        if tokens.len() < self.n {
+            return;
+        }
+        for window in tokens.windows(self.n) {
+            let hash = (fxhash::hash64(window) % MINHASH_PRIME) as u128;
+            for (value, (a, b)) in self.signature.iter_mut().zip(&self.permutations) {
+                let permuted = ((*a as u128 * hash + *b as u128) % MINHASH_PRIME as u128) as u64;
+                if permuted < *value {
+                    *value = permuted;
+                }
+            }
+        }
     }
 
-    #[test]
-    fn test_query_contain() {
-        let query = vec![1, 2, 3, 4, 5];
-        let doc = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+    /// Get the signature, i.e. the minimum permuted hash per permutation.
+    pub fn signature(&self) -> &[u64] {
+        &self.signature
+    }
 
-        let query_ngram = ngram(&query, 3);
-        let threshold = 0.8;
-        let n = 3;
+    /// Estimate the jaccard similarity of the n-gram sets as the fraction of equal signature values.
+    /// # Examples
+    /// ```
+    /// let mut a = neardup::MinHash::new(256, 0);
+    /// a.update(&[1, 2, 3, 4]);
+    /// let mut b = neardup::MinHash::new(256, 0);
+    /// b.update(&[5, 6, 7, 8]);
+    /// assert!(a.jaccard_estimate(&b) < 0.1);
+    /// ```
+    pub fn jaccard_estimate(&self, other: &MinHash) -> f64 {
         assert_eq!(
-            has_doc_duplicate(doc, &query, &query_ngram, threshold, n),
-            true
+            self.signature.len(),
+            other.signature.len(),
+            "signatures must have the same number of permutations"
         );
+        if self.signature.is_empty() {
+            return 0.0;
+        }
+        let equal = self
+            .signature
+            .iter()
+            .zip(&other.signature)
+            .filter(|(a, b)| a == b)
+            .count();
+        equal as f64 / self.signature.len() as f64
     }
-    #[test]
-    fn test_rolling_hash() {
-        let text = vec![1, 2, 3, 4, 5];
-        let mut rolling_hash = RollingHash::new();
-        for c in text.iter().map(|v| *v as u64) {
-            rolling_hash.append(c);
+}
+
+/// A locality-sensitive hashing index of documents, for generating near-duplicate candidates without scanning every document.
+///
+/// The MinHash signature of each document is split into `bands` bands of `rows` values, and each band is hashed into a bucket.
+/// Documents whose n-gram sets have jaccard similarity `s` share at least one bucket with probability `1 - (1 - s^rows)^bands`,
+/// so more rows per band filter more dissimilar documents and more bands retrieve more similar ones.
+/// Candidates should be verified with [`has_doc_duplicate`].
+///
+/// # Examples
+/// ```
+/// let mut index = neardup::LshIndex::new(16, 4, 3, 0);
+/// index.insert(0, &[1, 2, 3, 4, 5, 6, 7, 8, 9, 10]);
+/// index.insert(1, &[11, 12, 13, 14, 15, 16, 17, 18, 19, 20]);
+/// assert_eq!(index.query(&[1, 2, 3, 4, 5, 6, 7, 8, 9, 10]), vec![0]);
+/// ```
+pub struct LshIndex {
+    minhash: MinHash,
+    rows: usize,
+    buckets: Vec<HashMap<u64, Vec<usize>>>,
+}
+
+impl LshIndex {
+    /// Create an empty index hashing n-grams of size `n` with `bands * rows` MinHash permutations.
+    pub fn new(bands: usize, rows: usize, n: usize, seed: u64) -> Self {
+        assert!(rows > 0, "rows must be positive");
+        Self {
+            minhash: MinHash::with_ngram_size(bands * rows, seed, n),
+            rows,
+            buckets: vec![HashMap::new(); bands],
         }
-        assert_eq!(
-            rolling_hash.hash,
-            (1 * u64::pow(31, 4) + 2 * u64::pow(31, 3) + 3 * u64::pow(31, 2) + 4 * 31 + 5)
-                % 1_000_000_007
-        );
     }
 
-    #[test]
-    fn test_update() {
-        let text = vec![1, 2, 3, 4, 5];
-        let mut rolling_hash = RollingHash::new();
-        for c in text.iter().map(|v| *v as u64) {
-            rolling_hash.append(c);
+    /// Hash each band of the signature of the tokens, or `None` if the tokens have no n-grams.
+    fn band_hashes(&self, tokens: &[i32]) -> Option<Vec<u64>> {
+        if tokens.len() < self.minhash.n {
+            return None;
         }
-        assert_eq!(
-            rolling_hash.hash,
-            (1 * u64::pow(31, 4) + 2 * u64::pow(31, 3) + 3 * u64::pow(31, 2) + 4 * 31 + 5)
-                % 1_000_000_007
-        );
-        println!("{:?}", rolling_hash.hash);
-        rolling_hash.slide(1, 6);
-        assert_eq!(
-            rolling_hash.hash,
-            (2 * u64::pow(31, 4) + 3 * u64::pow(31, 3) + 4 * u64::pow(31, 2) + 5 * 31 + 6)
-                % 1_000_000_007
-        );
+        let mut minhash = self.minhash.clone();
+        minhash.update(tokens);
+        Some(
+            minhash
+                .signature()
+                .chunks(self.rows)
+                .map(fxhash::hash64)
+                .collect(),
+        )
+    }
+
+    /// Add a document to the buckets of its bands. Documents shorter than `n` are not indexed.
+    pub fn insert(&mut self, doc_id: usize, tokens: &[i32]) {
+        let Some(band_hashes) = self.band_hashes(tokens) else {
+            return;
+        };
+        for (bucket, hash) in self.buckets.iter_mut().zip(band_hashes) {
+            bucket.entry(hash).or_default().push(doc_id);
+        }
+    }
+
+    /// Get the sorted ids of the documents sharing at least one band bucket with the tokens.
+    pub fn query(&self, tokens: &[i32]) -> Vec<usize> {
+        let Some(band_hashes) = self.band_hashes(tokens) else {
+            return Vec::new();
+        };
+        let mut candidates = self
+            .buckets
+            .iter()
+            .zip(band_hashes)
+            .filter_map(|(bucket, hash)| bucket.get(&hash))
+            .flatten()
+            .copied()
+            .collect::<Vec<usize>>();
+        candidates.sort_unstable();
+        candidates.dedup();
+        candidates
     }
 }
 
-///  Check whether the document contains spans whose similarity to the query is above a threshold using rabin-karp method with fxhash.
+/// SimHash fingerprints of n-gram sets.
+///
+/// Each n-gram votes on every bit of the fingerprint, so sets sharing most of their n-grams get fingerprints differing in few bits.
+/// A small [`hamming_distance`] between two fingerprints correlates with a high jaccard similarity of the n-gram sets,
+/// which makes it cheap to bucket spans and prefilter candidates before running [`has_doc_duplicate`].
 ///
 /// # Examples
+///
 /// ```
-/// let query = vec![1, 2, 3, 4, 5];
-/// let doc = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
-/// let n = 3;
-/// let query_ngram = neardup::ngram(&query, n);
-/// let sim_threshold = 0.8;
-/// assert_eq!(neardup::has_doc_duplicate(doc, &query, &query_ngram, sim_threshold, n), true);
+/// let a = neardup::SimHash::from_ngrams(&neardup::ngram(&[1, 2, 3, 4, 5, 6], 2), 64);
+/// let b = neardup::SimHash::from_ngrams(&neardup::ngram(&[1, 2, 3, 4, 5, 6], 2), 64);
+/// assert_eq!(neardup::hamming_distance(a, b), 0);
 /// ```
-///
-pub fn has_doc_duplicate(
-    doc: Vec<i32>,
-    query: &[i32],
-    query_ngram: &HashSet<usize>,
-    threshold: f64,
-    n: usize,
-) -> bool {
-    for start in 0..doc.len() - query.len() {
-        let is_in_query_ngram = query_ngram.contains(&fxhash::hash(&doc[start..start + n]));
-        if !is_in_query_ngram {
-            continue;
-        }
-        let inner_start = max(0, start as i32 - query.len() as i32 + n as i32) as usize;
-        for s in inner_start..(start + 1) {
-            let end = s + query.len();
-            let sim = weighted_jaccard(&query, &doc[s..end]);
-            if sim >= threshold {
-                return true;
+pub struct SimHash;
+
+impl SimHash {
+    /// Compute a fingerprint of `bits` bits (at most 64) from the n-gram hashes.
+    pub fn from_ngrams(ngrams: &HashSet<usize>, bits: usize) -> u64 {
+        assert!(
+            0 < bits && bits <= 64,
+            "bits must be between 1 and 64, got {}",
+            bits
+        );
+        let mut votes = vec![0i64; bits];
+        for ngram in ngrams {
+            // mix the n-gram hash so that every bit is well distributed
+            let hash = fxhash::hash64(ngram);
+            for (i, vote) in votes.iter_mut().enumerate() {
+                if hash >> i & 1 == 1 {
+                    *vote += 1;
+                } else {
+                    *vote -= 1;
+                }
             }
         }
+        votes
+            .iter()
+            .enumerate()
+            .filter(|(_, vote)| **vote > 0)
+            .fold(0, |fingerprint, (i, _)| fingerprint | 1 << i)
     }
-    return false;
 }
 
-///  Check whether the document contains spans whose similarity to the query is above a threshold using naive method.
+/// Count the bits that differ between two fingerprints.
 ///
 /// # Examples
+///
 /// ```
-/// let query = vec![1, 2, 3, 4, 5];
-/// let doc = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
-/// let sim_threshold = 0.8;
-/// assert_eq!(neardup::has_doc_duplicate_naive(doc, &query, sim_threshold), true);
+/// assert_eq!(neardup::hamming_distance(0b1011, 0b0110), 3);
 /// ```
-pub fn has_doc_duplicate_naive(doc: Vec<i32>, query: &[i32], threshold: f64) -> bool {
-    for start in 0..doc.len() - query.len() {
-        let sim = weighted_jaccard(&query, &doc[start..start + query.len()]);
-        if sim >= threshold {
-            return true;
+pub fn hamming_distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+/// Compute `base^exp % modulo` by repeated squaring.
+fn mod_pow(base: u64, mut exp: u64, modulo: u64) -> u64 {
+    let modulo = modulo as u128;
+    let mut base = base as u128 % modulo;
+    let mut result = 1;
+    while exp > 0 {
+        if exp & 1 == 1 {
+            result = result * base % modulo;
         }
+        base = base * base % modulo;
+        exp >>= 1;
     }
-    return false;
+    result as u64
 }
 
-/// Check whether the document contains spans whose similarity to the query is above a threshold using rabin-karp method with rolling hash.
+/// Count the occurrences of each token of a text.
 ///
 /// # Examples
 ///
 /// ```
-/// let query = vec![1, 2, 3, 4, 5];
-/// let doc = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
-/// let n = 3;
-/// let query_ngram = neardup::ngram_rolling(&query, n);
-/// let sim_threshold = 0.8;
-/// assert_eq!(neardup::has_doc_duplicate_rolling(doc, &query, &query_ngram, sim_threshold, n), true);
+/// let frequency_vector = neardup::create_frequency_vector(&[1, 2, 2]);
+/// assert_eq!(frequency_vector[&1], 1);
+/// assert_eq!(frequency_vector[&2], 2);
 /// ```
-pub fn has_doc_duplicate_rolling(
-    doc: Vec<i32>,
-    query: &[i32],
+pub fn create_frequency_vector<T: Hash + Eq>(set: &[T]) -> HashMap<&T, usize> {
+    let mut frequency_vector: HashMap<&T, usize> = HashMap::new();
+    for element in set {
+        *frequency_vector.entry(element).or_insert(0) += 1;
+    }
+    frequency_vector
+}
+
+/// A frequency vector of a sliding window, compared against a fixed query.
+///
+/// The multiset intersection with the query is updated on each add/remove, so the weighted jaccard similarity of the window is available in O(1).
+struct SlidingFrequency<K: Hash + Eq> {
+    query_frequency: HashMap<K, usize>,
+    query_len: usize,
+    window_frequency: HashMap<K, usize>,
+    window_len: usize,
+    intersection_frequency: usize,
+}
+
+impl<K: Hash + Eq> SlidingFrequency<K> {
+    fn new<I: IntoIterator<Item = K>>(query: I) -> Self {
+        let mut query_frequency = HashMap::new();
+        let mut query_len = 0;
+        for token in query {
+            *query_frequency.entry(token).or_insert(0) += 1;
+            query_len += 1;
+        }
+        Self {
+            query_frequency,
+            query_len,
+            window_frequency: HashMap::new(),
+            window_len: 0,
+            intersection_frequency: 0,
+        }
+    }
+
+    /// Add a token to the window.
+    fn add(&mut self, token: K) {
+        let query_frequency = *self.query_frequency.get(&token).unwrap_or(&0);
+        let frequency = self.window_frequency.entry(token).or_insert(0);
+        *frequency += 1;
+        if *frequency <= query_frequency {
+            self.intersection_frequency += 1;
+        }
+        self.window_len += 1;
+    }
+
+    /// Remove a token that is in the window.
+    fn remove(&mut self, token: K) {
+        let frequency = self
+            .window_frequency
+            .get_mut(&token)
+            .expect("token is not in the window");
+        if *frequency <= *self.query_frequency.get(&token).unwrap_or(&0) {
+            self.intersection_frequency -= 1;
+        }
+        *frequency -= 1;
+        self.window_len -= 1;
+    }
+
+    /// Size of the multiset union of the query and the window.
+    fn union_frequency(&self) -> usize {
+        self.query_len + self.window_len - self.intersection_frequency
+    }
+
+    /// Weighted jaccard similarity between the query and the window.
+    fn similarity(&self) -> f64 {
+        let union_frequency = self.union_frequency();
+        if union_frequency > 0 {
+            self.intersection_frequency as f64 / union_frequency as f64
+        } else {
+            1.0
+        }
+    }
+}
+
+/// Sum of the minimum frequencies of the tokens shared by two frequency vectors.
+fn intersection_frequency<K: Hash + Eq>(x: &HashMap<K, usize>, y: &HashMap<K, usize>) -> usize {
+    let mut intersection_frequency = 0;
+    for (element, frequency1) in x {
+        if let Some(frequency2) = y.get(element) {
+            intersection_frequency += frequency1.min(frequency2);
+        }
+    }
+    intersection_frequency
+}
+
+/// Compute weighted jaccard similarity between two texts.
+///
+/// Tokens can be of any type implementing `Hash + Eq`, e.g. `i32`, `u32`, `u8`, or `char`.
+/// Two empty texts are identical, so their similarity is 1.0, while an empty and a non-empty text have similarity 0.0.
+/// The other weighted jaccard functions follow the same convention.
+///
+/// # Examples
+///
+/// ```
+/// assert_eq!(neardup::weighted_jaccard(&['a', 'b', 'b'], &['a', 'b', 'c']), 2.0 / 4.0);
+/// assert_eq!(neardup::weighted_jaccard::<char>(&[], &[]), 1.0);
+/// ```
+pub fn weighted_jaccard<T: Hash + Eq>(text1: &[T], text2: &[T]) -> f64 {
+    weighted_jaccard_from_freq(
+        &create_frequency_vector(text1),
+        &create_frequency_vector(text2),
+    )
+}
+
+/// Compute weighted jaccard similarity between two precomputed frequency vectors.
+///
+/// The frequency vectors map each token to its number of occurrences, e.g. as built by [`create_frequency_vector`].
+///
+/// # Examples
+///
+/// ```
+/// use std::collections::HashMap;
+/// let x = HashMap::from([(1, 1), (2, 2)]);
+/// let y = HashMap::from([(1, 1), (2, 1), (3, 1)]);
+/// assert_eq!(neardup::weighted_jaccard_from_freq(&x, &y), 2.0 / 4.0);
+/// ```
+pub fn weighted_jaccard_from_freq<K: Hash + Eq>(
+    x: &HashMap<K, usize>,
+    y: &HashMap<K, usize>,
+) -> f64 {
+    let intersection_frequency = intersection_frequency(x, y);
+
+    let sum_of_frequencies_x: usize = x.values().sum();
+    let sum_of_frequencies_y: usize = y.values().sum();
+    let union_frequency = sum_of_frequencies_x + sum_of_frequencies_y - intersection_frequency;
+    if union_frequency > 0 {
+        intersection_frequency as f64 / union_frequency as f64
+    } else {
+        1.0
+    }
+}
+
+/// Compute weighted jaccard distance between two texts, i.e. `1.0 - weighted_jaccard(text1, text2)`.
+///
+/// Two empty texts are identical, so their distance is 0.0, and an empty and a non-empty text are at distance 1.0.
+///
+/// # Examples
+///
+/// ```
+/// assert_eq!(neardup::weighted_jaccard_distance(&[1, 2, 2], &[1, 2, 3]), 1.0 - 2.0 / 4.0);
+/// assert_eq!(neardup::weighted_jaccard_distance::<i32>(&[], &[]), 0.0);
+/// ```
+pub fn weighted_jaccard_distance<T: Hash + Eq>(text1: &[T], text2: &[T]) -> f64 {
+    1.0 - weighted_jaccard(text1, text2)
+}
+
+/// Compute weighted jaccard similarity between two texts together with the tokens that drove it.
+///
+/// The map holds each token shared by the two texts with its intersection frequency, i.e. the minimum of its
+/// frequencies in the two texts, so its values sum to the intersection frequency of the similarity.
+///
+/// # Examples
+///
+/// ```
+/// let (similarity, overlap) = neardup::weighted_jaccard_explain(&[1, 2, 2, 4], &[1, 2, 2, 2, 3]);
+/// assert_eq!(similarity, 3.0 / 6.0);
+/// assert_eq!(overlap, [(1, 1), (2, 2)].into_iter().collect());
+/// ```
+pub fn weighted_jaccard_explain<T: Hash + Eq + Clone>(
+    text1: &[T],
+    text2: &[T],
+) -> (f64, HashMap<T, usize>) {
+    let x = create_frequency_vector(text1);
+    let y = create_frequency_vector(text2);
+    let overlap = x
+        .iter()
+        .filter_map(|(&element, frequency1)| {
+            y.get(element)
+                .map(|frequency2| (element.clone(), *frequency1.min(frequency2)))
+        })
+        .collect();
+    (weighted_jaccard_from_freq(&x, &y), overlap)
+}
+
+/// Compute weighted jaccard similarity between two texts, ignoring the given tokens.
+///
+/// Tokens such as padding or BOS/EOS are dropped from both texts before building the frequency vectors, so they do not inflate the similarity.
+///
+/// # Examples
+///
+/// ```
+/// let ignore = [0].into_iter().collect();
+/// assert_eq!(neardup::weighted_jaccard(&[0, 0, 1, 2], &[0, 0, 3, 4]), 2.0 / 6.0);
+/// assert_eq!(neardup::weighted_jaccard_filtered(&[0, 0, 1, 2], &[0, 0, 3, 4], &ignore), 0.0);
+/// ```
+pub fn weighted_jaccard_filtered<T: Hash + Eq + Copy>(
+    text1: &[T],
+    text2: &[T],
+    ignore: &HashSet<T>,
+) -> f64 {
+    weighted_jaccard(
+        &remove_ignored(text1, ignore),
+        &remove_ignored(text2, ignore),
+    )
+}
+
+/// Copy the tokens of a text that are not ignored.
+///
+/// Use it to filter a query once before computing its n-grams for [`has_doc_duplicate_filtered`].
+///
+/// # Examples
+///
+/// ```
+/// let ignore = [0].into_iter().collect();
+/// assert_eq!(neardup::remove_ignored(&[0, 1, 0, 2], &ignore), vec![1, 2]);
+/// ```
+pub fn remove_ignored<T: Hash + Eq + Copy>(text: &[T], ignore: &HashSet<T>) -> Vec<T> {
+    text.iter()
+        .filter(|token| !ignore.contains(token))
+        .copied()
+        .collect()
+}
+
+/// Compute weighted jaccard similarity between two texts by sorting copies of them and merging.
+///
+/// Returns the same value as [`weighted_jaccard`] without hashing, which is faster for short texts like spans of tens of tokens.
+/// Sorting takes O(L log L), so prefer [`weighted_jaccard`] for long texts.
+///
+/// # Examples
+///
+/// ```
+/// assert_eq!(neardup::weighted_jaccard_sorted(&[1, 2, 2], &[1, 1, 2]), 2.0 / 4.0);
+/// ```
+pub fn weighted_jaccard_sorted<T: Ord + Copy>(text1: &[T], text2: &[T]) -> f64 {
+    let mut x = text1.to_vec();
+    let mut y = text2.to_vec();
+    x.sort_unstable();
+    y.sort_unstable();
+    let (mut i, mut j) = (0, 0);
+    let mut intersection_frequency = 0;
+    while i < x.len() && j < y.len() {
+        match x[i].cmp(&y[j]) {
+            std::cmp::Ordering::Less => i += 1,
+            std::cmp::Ordering::Greater => j += 1,
+            std::cmp::Ordering::Equal => {
+                intersection_frequency += 1;
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    let union_frequency = x.len() + y.len() - intersection_frequency;
+    if union_frequency > 0 {
+        intersection_frequency as f64 / union_frequency as f64
+    } else {
+        1.0
+    }
+}
+
+/// Compute an upper bound of the weighted jaccard similarity between two texts from their lengths.
+///
+/// The intersection is at most `min(len1, len2)` tokens and the union at least `max(len1, len2)` tokens,
+/// so a span can be skipped without building its frequency vector if this bound is below the threshold.
+///
+/// # Examples
+///
+/// ```
+/// assert_eq!(neardup::weighted_jaccard_upper_bound(5, 10), 0.5);
+/// assert!(neardup::weighted_jaccard(&[1, 2, 3, 4, 5], &[1, 2, 3, 4, 5, 6, 7, 8, 9, 10]) <= 0.5);
+/// ```
+pub fn weighted_jaccard_upper_bound(len1: usize, len2: usize) -> f64 {
+    let max_len = max(len1, len2);
+    if max_len > 0 {
+        min(len1, len2) as f64 / max_len as f64
+    } else {
+        1.0
+    }
+}
+
+/// Compute the maximum weighted jaccard similarity that texts of lengths `len_a` and `len_b` can reach, i.e. `min / max`.
+///
+/// This is the length-ratio prefilter of [`has_doc_duplicate_flex`], equal to [`weighted_jaccard_upper_bound`].
+///
+/// # Examples
+///
+/// ```
+/// assert_eq!(neardup::max_possible_jaccard(8, 10), 0.8);
+/// assert_eq!(neardup::max_possible_jaccard(10, 8), 0.8);
+/// ```
+pub fn max_possible_jaccard(len_a: usize, len_b: usize) -> f64 {
+    weighted_jaccard_upper_bound(len_a, len_b)
+}
+
+/// A mapping from token ids to dense ids `0..len()` in the order they are observed.
+///
+/// Texts using few distinct ids out of a large vocabulary can be encoded to dense ids,
+/// so that [`weighted_jaccard_dense`] counts tokens in a `Vec` instead of a `HashMap`.
+///
+/// # Examples
+///
+/// ```
+/// let mut vocabulary = neardup::Vocabulary::new();
+/// assert_eq!(vocabulary.encode(&[50000, 7, 50000]), vec![0, 1, 0]);
+/// assert_eq!(vocabulary.encode(&[7, 123]), vec![1, 2]);
+/// assert_eq!(vocabulary.len(), 3);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Vocabulary {
+    ids: HashMap<i32, u32>,
+}
+
+impl Vocabulary {
+    /// Create an empty vocabulary.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Map the token ids of a text to dense ids, adding the unseen ones to the vocabulary.
+    pub fn encode(&mut self, text: &[i32]) -> Vec<u32> {
+        text.iter()
+            .map(|token| {
+                let next_id = self.ids.len() as u32;
+                *self.ids.entry(*token).or_insert(next_id)
+            })
+            .collect()
+    }
+
+    /// Get the dense id of a token id, if it has been observed.
+    pub fn get(&self, token: i32) -> Option<u32> {
+        self.ids.get(&token).copied()
+    }
+
+    /// Get the number of distinct token ids observed.
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    /// Check whether no token id has been observed.
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+}
+
+/// Compute weighted jaccard similarity between two texts of dense ids encoded by a [`Vocabulary`].
+///
+/// Returns the same value as [`weighted_jaccard`] on the original texts, but counts the tokens in a `Vec` of `vocabulary_size` counters.
+///
+/// # Panics
+///
+/// Panics if a dense id is not smaller than `vocabulary_size`.
+///
+/// # Examples
+///
+/// ```
+/// let mut vocabulary = neardup::Vocabulary::new();
+/// let text1 = vocabulary.encode(&[1, 50000, 50000]);
+/// let text2 = vocabulary.encode(&[1, 1, 50000]);
+/// assert_eq!(neardup::weighted_jaccard_dense(&text1, &text2, vocabulary.len()), 2.0 / 4.0);
+/// ```
+pub fn weighted_jaccard_dense(text1: &[u32], text2: &[u32], vocabulary_size: usize) -> f64 {
+    let mut frequency = vec![0usize; vocabulary_size];
+    for token in text1 {
+        frequency[*token as usize] += 1;
+    }
+    let mut intersection_frequency = 0;
+    for token in text2 {
+        let frequency = &mut frequency[*token as usize];
+        if *frequency > 0 {
+            *frequency -= 1;
+            intersection_frequency += 1;
+        }
+    }
+    let union_frequency = text1.len() + text2.len() - intersection_frequency;
+    if union_frequency > 0 {
+        intersection_frequency as f64 / union_frequency as f64
+    } else {
+        1.0
+    }
+}
+
+/// Compute weighted jaccard similarity between two byte strings.
+///
+/// Returns the same value as [`weighted_jaccard`], but counts the bytes in two fixed `[u32; 256]` histograms instead of hash maps,
+/// whose element-wise minimum and maximum are summed in loops the compiler can vectorize. Use it for byte-level corpora such as DNA.
+///
+/// # Examples
+///
+/// ```
+/// assert_eq!(neardup::weighted_jaccard_u8(b"ACGT", b"ACGA"), 3.0 / 5.0);
+/// assert_eq!(neardup::weighted_jaccard_u8(b"ACGT", b"TGCA"), 1.0);
+/// ```
+pub fn weighted_jaccard_u8(text1: &[u8], text2: &[u8]) -> f64 {
+    let mut frequency1 = [0u32; 256];
+    let mut frequency2 = [0u32; 256];
+    for byte in text1 {
+        frequency1[*byte as usize] += 1;
+    }
+    for byte in text2 {
+        frequency2[*byte as usize] += 1;
+    }
+    let mut intersection_frequency = 0u64;
+    let mut union_frequency = 0u64;
+    for (x, y) in frequency1.iter().zip(&frequency2) {
+        intersection_frequency += min(*x, *y) as u64;
+        union_frequency += max(*x, *y) as u64;
+    }
+    if union_frequency > 0 {
+        intersection_frequency as f64 / union_frequency as f64
+    } else {
+        1.0
+    }
+}
+
+/// Compute the dice coefficient `2|X∩Y| / (|X|+|Y|)` between two texts, where `X` and `Y` are the token multisets.
+///
+/// # Examples
+///
+/// ```
+/// assert_eq!(neardup::dice_coefficient(&[1, 2, 2], &[1, 1, 2]), 2.0 * 2.0 / 6.0);
+/// ```
+pub fn dice_coefficient<T: Hash + Eq>(text1: &[T], text2: &[T]) -> f64 {
+    let x = create_frequency_vector(text1);
+    let y = create_frequency_vector(text2);
+    let intersection_frequency = intersection_frequency(&x, &y);
+    let sum_of_frequencies = text1.len() + text2.len();
+    if sum_of_frequencies > 0 {
+        2.0 * intersection_frequency as f64 / sum_of_frequencies as f64
+    } else {
+        0.0
+    }
+}
+
+/// Compute the overlap coefficient `|X∩Y| / min(|X|,|Y|)` between two texts, where `X` and `Y` are the token multisets.
+///
+/// # Examples
+///
+/// ```
+/// assert_eq!(neardup::overlap_coefficient(&[1, 2], &[1, 2, 3, 4]), 1.0);
+/// ```
+pub fn overlap_coefficient<T: Hash + Eq>(text1: &[T], text2: &[T]) -> f64 {
+    let x = create_frequency_vector(text1);
+    let y = create_frequency_vector(text2);
+    let intersection_frequency = intersection_frequency(&x, &y);
+    let min_frequency = text1.len().min(text2.len());
+    if min_frequency > 0 {
+        intersection_frequency as f64 / min_frequency as f64
+    } else {
+        0.0
+    }
+}
+
+/// Compute the containment `|X∩Y| / |X|` of a query in a span, where `X` and `Y` are the token multisets.
+///
+/// Unlike [`weighted_jaccard`], tokens of the span that are not in the query are not penalized,
+/// so a query fully contained in a longer span scores 1.0. Returns 0.0 if the query is empty.
+///
+/// # Examples
+///
+/// ```
+/// assert_eq!(neardup::containment(&[1, 2, 3], &[0, 1, 2, 3, 4, 5]), 1.0);
+/// assert_eq!(neardup::weighted_jaccard(&[1, 2, 3], &[0, 1, 2, 3, 4, 5]), 3.0 / 6.0);
+/// ```
+pub fn containment<T: Hash + Eq>(query: &[T], span: &[T]) -> f64 {
+    let x = create_frequency_vector(query);
+    let y = create_frequency_vector(span);
+    let intersection_frequency = intersection_frequency(&x, &y);
+    if !query.is_empty() {
+        intersection_frequency as f64 / query.len() as f64
+    } else {
+        0.0
+    }
+}
+
+/// A query with its precomputed frequency vector, for computing weighted jaccard similarity against many spans.
+///
+/// # Examples
+///
+/// ```
+/// let query = vec![1, 2, 2];
+/// let profile = neardup::QueryProfile::new(&query);
+/// assert_eq!(profile.similarity(&[1, 1, 2]), neardup::weighted_jaccard(&query, &[1, 1, 2]));
+/// ```
+pub struct QueryProfile<'a, T: Hash + Eq> {
+    query: MaybeOwned<'a, [T], Vec<T>>,
+    frequency_vector: HashMap<MaybeOwned<'a, T, T>, usize>,
+}
+
+impl<'a, T: Hash + Eq> QueryProfile<'a, T> {
+    pub fn new(query: &'a [T]) -> Self {
+        let mut frequency_vector = HashMap::new();
+        for token in query {
+            *frequency_vector
+                .entry(MaybeOwned::Borrowed(token))
+                .or_insert(0) += 1;
+        }
+        Self {
+            query: MaybeOwned::Borrowed(query),
+            frequency_vector,
+        }
+    }
+
+    /// Build a profile owning the query, e.g. to store it next to the query's n-grams as [`Matcher`] does.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let profile = neardup::QueryProfile::from_vec(vec![1, 2, 2]);
+    /// assert_eq!(profile.query(), &[1, 2, 2]);
+    /// assert_eq!(profile.similarity(&[1, 1, 2]), neardup::weighted_jaccard(&[1, 2, 2], &[1, 1, 2]));
+    /// ```
+    pub fn from_vec(query: Vec<T>) -> Self
+    where
+        T: Clone,
+    {
+        let mut frequency_vector = HashMap::new();
+        for token in &query {
+            *frequency_vector
+                .entry(MaybeOwned::Owned(token.clone()))
+                .or_insert(0) += 1;
+        }
+        Self {
+            query: MaybeOwned::Owned(query),
+            frequency_vector,
+        }
+    }
+
+    /// Get the query.
+    pub fn query(&self) -> &[T] {
+        self.query.get()
+    }
+
+    /// Compute weighted jaccard similarity between the query and a span.
+    ///
+    /// Returns the same value as `weighted_jaccard(query, span)`, but only the frequency vector of the span is built.
+    pub fn similarity(&self, span: &[T]) -> f64 {
+        let y = create_frequency_vector(span);
+        let intersection_frequency = y
+            .iter()
+            .filter_map(|(token, frequency)| {
+                let query_frequency = self.frequency_vector.get(*token)?;
+                Some(min(*frequency, *query_frequency))
+            })
+            .sum::<usize>();
+        let union_frequency = self.query().len() + span.len() - intersection_frequency;
+        if union_frequency > 0 {
+            intersection_frequency as f64 / union_frequency as f64
+        } else {
+            1.0
+        }
+    }
+}
+
+/// A value of a [`QueryProfile`], either borrowed from the caller or owned by the profile.
+enum MaybeOwned<'a, B: ?Sized, O> {
+    Borrowed(&'a B),
+    Owned(O),
+}
+
+impl<B: ?Sized, O: Borrow<B>> MaybeOwned<'_, B, O> {
+    fn get(&self) -> &B {
+        match self {
+            MaybeOwned::Borrowed(value) => value,
+            MaybeOwned::Owned(value) => value.borrow(),
+        }
+    }
+}
+
+impl<B: ?Sized, O: Borrow<B>> Borrow<B> for MaybeOwned<'_, B, O> {
+    fn borrow(&self) -> &B {
+        self.get()
+    }
+}
+
+impl<B: ?Sized + Hash, O: Borrow<B>> Hash for MaybeOwned<'_, B, O> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.get().hash(state)
+    }
+}
+
+impl<B: ?Sized + PartialEq, O: Borrow<B>> PartialEq for MaybeOwned<'_, B, O> {
+    fn eq(&self, other: &Self) -> bool {
+        self.get() == other.get()
+    }
+}
+
+impl<B: ?Sized + Eq, O: Borrow<B>> Eq for MaybeOwned<'_, B, O> {}
+
+/// Compute jaccard similarity between the sets of distinct tokens of two texts.
+///
+/// Unlike [`weighted_jaccard`], repeated tokens are counted only once.
+///
+/// # Examples
+///
+/// ```
+/// assert_eq!(neardup::jaccard(&[1, 1, 2], &[1, 2, 2]), 1.0);
+/// assert_eq!(neardup::jaccard(&[1, 2, 3], &[2, 3, 4]), 2.0 / 4.0);
+/// ```
+pub fn jaccard<T: Hash + Eq>(text1: &[T], text2: &[T]) -> f64 {
+    let x: HashSet<&T> = text1.iter().collect();
+    let y: HashSet<&T> = text2.iter().collect();
+    let intersection = x.intersection(&y).count();
+    let union = x.len() + y.len() - intersection;
+    if union > 0 {
+        intersection as f64 / union as f64
+    } else {
+        0.0
+    }
+}
+
+/// Compute the normalized edit-distance similarity of two texts, i.e. `1 - levenshtein(text1, text2) / max(len1, len2)`.
+///
+/// Unlike [`weighted_jaccard`], the order of the tokens matters, so a shuffled copy of a text is not a perfect match.
+/// Takes O(len1 * len2) time and O(len2) memory. Returns 1.0 if both texts are empty.
+///
+/// # Examples
+///
+/// ```
+/// assert_eq!(neardup::normalized_levenshtein(&[1, 2, 3, 4], &[1, 2, 3, 4]), 1.0);
+/// assert_eq!(neardup::normalized_levenshtein(&[1, 2, 3, 4], &[1, 2, 0, 4]), 0.75);
+/// assert_eq!(neardup::normalized_levenshtein(&[1, 2, 3, 4], &[4, 3, 2, 1]), 0.0);
+/// ```
+pub fn normalized_levenshtein<T: Eq>(text1: &[T], text2: &[T]) -> f64 {
+    let max_len = max(text1.len(), text2.len());
+    if max_len == 0 {
+        return 1.0;
+    }
+    // distances between the prefix of text1 processed so far and every prefix of text2
+    let mut row = (0..=text2.len()).collect::<Vec<usize>>();
+    for (i, token1) in text1.iter().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, token2) in text2.iter().enumerate() {
+            let substitution = diagonal + usize::from(token1 != token2);
+            diagonal = row[j + 1];
+            row[j + 1] = min(substitution, min(row[j], row[j + 1]) + 1);
+        }
+    }
+    1.0 - row[text2.len()] as f64 / max_len as f64
+}
+
+/// Compute n-grams of a text using fxhash.
+///
+/// Returns an empty set if `n == 0` or the text is shorter than `n`, as do the other n-gram functions.
+///
+/// # Examples
+///
+/// ```
+/// let text = vec![1, 2, 3, 4, 5];
+/// let ngrams = neardup::ngram(&text, 2);
+/// assert_eq!(ngrams.len(), 4);
+/// assert_eq!(ngrams.contains(&fxhash::hash(&vec![1, 2])), true);
+/// ```
+pub fn ngram<T: Hash>(text: &[T], n: usize) -> HashSet<usize> {
+    ngram_iter(text, n).map(|(_, hash)| hash).collect()
+}
+
+/// Iterate over the n-grams of a text using fxhash, yielding the start position and hash of each n-gram lazily.
+///
+/// Unlike [`ngram`], the position of every n-gram is kept, including repeated ones.
+/// Yields nothing if `n == 0` or the text is shorter than `n`.
+///
+/// # Examples
+///
+/// ```
+/// let text = vec![1, 2, 1, 2];
+/// let ngrams = neardup::ngram_iter(&text, 2).collect::<Vec<_>>();
+/// assert_eq!(ngrams.len(), 3);
+/// assert_eq!(ngrams[2], (2, fxhash::hash(&vec![1, 2])));
+/// assert_eq!(ngrams[0].1, ngrams[2].1);
+/// ```
+pub fn ngram_iter<T: Hash>(text: &[T], n: usize) -> impl Iterator<Item = (usize, usize)> + '_ {
+    let count = if n == 0 {
+        0
+    } else {
+        (text.len() + 1).saturating_sub(n)
+    };
+    (0..count).map(move |i| (i, fxhash::hash(&text[i..i + n])))
+}
+
+/// Compute n-grams of a text using fxhash, hashing chunks of the text in parallel.
+///
+/// Returns the same set as [`ngram`]. The start positions are split into chunks hashed by rayon threads,
+/// each reading `n - 1` tokens past its chunk so that the n-grams spanning chunk boundaries are kept, and the per-chunk sets are merged.
+/// This pays off for texts of hundreds of thousands of tokens, e.g. when a whole document is used as a query.
+/// Requires the `parallel` feature.
+///
+/// # Examples
+///
+/// ```
+/// let text = (0..100_000).map(|i| i % 1000).collect::<Vec<i32>>();
+/// assert_eq!(neardup::ngram_par(&text, 10), neardup::ngram(&text, 10));
+/// ```
+#[cfg(feature = "parallel")]
+pub fn ngram_par<T: Hash + Sync>(text: &[T], n: usize) -> HashSet<usize> {
+    if n == 0 || text.len() < n {
+        return HashSet::default();
+    }
+    let num_starts = text.len() - n + 1;
+    (0..num_starts.div_ceil(PAR_CHUNK_SIZE))
+        .into_par_iter()
+        .map(|chunk| {
+            let chunk_start = chunk * PAR_CHUNK_SIZE;
+            let chunk_end = min(chunk_start + PAR_CHUNK_SIZE, num_starts);
+            (chunk_start..chunk_end)
+                .map(|i| fxhash::hash(&text[i..i + n]))
+                .collect::<HashSet<usize>>()
+        })
+        .reduce(HashSet::default, |mut a, mut b| {
+            if a.len() < b.len() {
+                std::mem::swap(&mut a, &mut b);
+            }
+            a.extend(b);
+            a
+        })
+}
+
+/// Compute n-grams of many texts using fxhash, one text per rayon task.
+///
+/// Returns the same sets as calling [`ngram`] on each text, in the same order.
+/// Unlike [`ngram_par`], which splits one long text, this pays off for many short texts, e.g. building the n-grams of tens of thousands of queries.
+/// Requires the `parallel` feature.
+///
+/// # Examples
+///
+/// ```
+/// let queries = vec![vec![1, 2, 3], vec![4, 5, 6, 7]];
+/// let ngrams = neardup::ngram_batch_par(&queries, 2);
+/// assert_eq!(ngrams, vec![neardup::ngram(&queries[0], 2), neardup::ngram(&queries[1], 2)]);
+/// ```
+#[cfg(feature = "parallel")]
+pub fn ngram_batch_par<T: Hash + Sync>(queries: &[Vec<T>], n: usize) -> Vec<HashSet<usize>> {
+    queries.par_iter().map(|query| ngram(query, n)).collect()
+}
+
+/// Compute n-grams of a text using 64-bit fxhash.
+///
+/// [`ngram`] hashes to `usize`, which is only 32 bits on 32-bit and wasm targets, so n-grams collide far more often there and more spans are compared for nothing.
+/// This always uses [`fxhash::hash64`] so that the collision rate does not depend on the platform; use it with [`has_doc_duplicate_fx64`].
+///
+/// # Examples
+///
+/// ```
+/// let text = vec![1, 2, 3, 4, 5];
+/// let ngrams = neardup::ngram_fx64(&text, 2);
+/// assert_eq!(ngrams.len(), 4);
+/// assert_eq!(ngrams.contains(&fxhash::hash64(&[1, 2][..])), true);
+/// ```
+pub fn ngram_fx64<T: Hash>(text: &[T], n: usize) -> HashSet<u64> {
+    let mut ngrams = HashSet::default();
+    if n == 0 || text.len() < n {
+        return ngrams;
+    }
+    for i in 0..text.len() - n + 1 {
+        ngrams.insert(fxhash::hash64(&text[i..i + n]));
+    }
+    ngrams
+}
+
+/// Hash an n-gram with 64-bit fxhash after mixing in a seed.
+fn seeded_hash<T: Hash>(window: &[T], seed: u64) -> u64 {
+    let mut hasher = fxhash::FxHasher64::default();
+    hasher.write_u64(seed);
+    window.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Compute n-grams of a text using 64-bit fxhash seeded with `seed`.
+///
+/// Different seeds give unrelated hash values, so colliding inputs cannot be crafted without knowing the seed,
+/// and runs with several seeds can be ensembled. Use it with [`has_doc_duplicate_seeded`] and the same `seed`.
+///
+/// # Examples
+///
+/// ```
+/// let text = vec![1, 2, 3, 4, 5];
+/// let ngrams = neardup::ngram_seeded(&text, 2, 42);
+/// assert_eq!(ngrams.len(), 4);
+/// assert_ne!(ngrams, neardup::ngram_seeded(&text, 2, 43));
+/// ```
+pub fn ngram_seeded<T: Hash>(text: &[T], n: usize, seed: u64) -> HashSet<u64> {
+    let mut ngrams = HashSet::default();
+    if n == 0 || text.len() < n {
+        return ngrams;
+    }
+    for i in 0..text.len() - n + 1 {
+        ngrams.insert(seeded_hash(&text[i..i + n], seed));
+    }
+    ngrams
+}
+
+/// Compute n-grams of a text using rolling hash.
+///
+/// Tokens must be convertible to `i64` losslessly (e.g. `i32`, `u32`, `u16`, `u8`) so that they can be fed to [`RollingHash`].
+/// The hash of each n-gram is computed from the previous one in O(1), so this takes O(L) time for a text of length L regardless of `n`.
+///
+/// # Examples
+///
+/// ```
+/// let text = vec![1, 2, 3, 4, 5];
+/// let ngrams = neardup::ngram_rolling(&text, 2);
+/// assert_eq!(ngrams.len(), 4);
+/// let mut rolling_hash = neardup::RollingHash::new();
+/// for c in vec![1, 2].iter().map(|v| *v as u64) {
+///    rolling_hash.append(c);
+/// }
+/// assert_eq!(ngrams.contains(&(rolling_hash.get_hash() as usize)), true);
+/// ```
+pub fn ngram_rolling<T: Copy + Into<i64>>(text: &[T], n: usize) -> HashSet<usize> {
+    let mut ngrams = HashSet::default();
+    if n == 0 || text.len() < n {
+        return ngrams;
+    }
+    let text = text
+        .iter()
+        .map(|v| (*v).into() as u64)
+        .collect::<Vec<u64>>();
+    let mut rolling_hash = RollingHash::from_slice(&text[..n]);
+    ngrams.insert(rolling_hash.hash as usize);
+    for i in n..text.len() {
+        rolling_hash.slide(text[i - n], text[i]);
+        ngrams.insert(rolling_hash.hash as usize);
+    }
+    ngrams
+}
+
+/// Base of the second rolling hash used by [`ngram_rolling_double`].
+const DOUBLE_HASH_BASE: u64 = 1_000_003;
+/// Modulo of the second rolling hash used by [`ngram_rolling_double`].
+const DOUBLE_HASH_MODULO: u64 = 998_244_353;
+
+/// Compute n-grams of a text using two independent rolling hashes.
+///
+/// Each n-gram is stored as a pair of hashes: the default [`RollingHash`] and a second one with a different base and modulo.
+/// An n-gram collides only if both hashes collide, which removes most false candidates of [`ngram_rolling`].
+///
+/// # Examples
+///
+/// ```
+/// let text = vec![0, 1, 0, 0, 0, 31];
+/// // [0, 1, 0] and [0, 0, 31] collide under a single rolling hash
+/// assert_eq!(neardup::ngram_rolling(&text, 3).len(), 3);
+/// assert_eq!(neardup::ngram_rolling_double(&text, 3).len(), 4);
+/// ```
+pub fn ngram_rolling_double<T: Copy + Into<i64>>(text: &[T], n: usize) -> HashSet<(usize, usize)> {
+    let mut ngrams = HashSet::default();
+    if n == 0 || text.len() < n {
+        return ngrams;
+    }
+    let text = text
+        .iter()
+        .map(|v| (*v).into() as u64)
+        .collect::<Vec<u64>>();
+    let mut rolling_hash = RollingHash::from_slice(&text[..n]);
+    let mut second_rolling_hash =
+        RollingHash::from_params_slice(DOUBLE_HASH_BASE, DOUBLE_HASH_MODULO, &text[..n]);
+    ngrams.insert((
+        rolling_hash.hash as usize,
+        second_rolling_hash.hash as usize,
+    ));
+    for i in n..text.len() {
+        rolling_hash.slide(text[i - n], text[i]);
+        second_rolling_hash.slide(text[i - n], text[i]);
+        ngrams.insert((
+            rolling_hash.hash as usize,
+            second_rolling_hash.hash as usize,
+        ));
+    }
+    ngrams
+}
+
+/// A set of n-gram hashes that remembers the n-gram size it was built with.
+///
+/// Matching a document with an n-gram size different from the one of the query n-grams silently gives wrong results,
+/// so [`try_has_doc_duplicate_rolling`] checks it against the size stored here.
+///
+/// # Examples
+///
+/// ```
+/// let query_ngram = neardup::NgramSet::rolling(&[1, 2, 3, 4, 5], 3);
+/// assert_eq!(query_ngram.n(), 3);
+/// assert_eq!(query_ngram.hashes(), &neardup::ngram_rolling(&[1, 2, 3, 4, 5], 3));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NgramSet {
+    n: usize,
+    hashes: HashSet<usize>,
+}
+
+impl NgramSet {
+    /// Compute the n-grams of a text with [`ngram_rolling`].
+    pub fn rolling<T: Copy + Into<i64>>(text: &[T], n: usize) -> Self {
+        Self {
+            n,
+            hashes: ngram_rolling(text, n),
+        }
+    }
+
+    /// Get the n-gram size.
+    pub fn n(&self) -> usize {
+        self.n
+    }
+
+    /// Get the n-gram hashes.
+    pub fn hashes(&self) -> &HashSet<usize> {
+        &self.hashes
+    }
+}
+
+/// Compute the n-grams of a text starting every `stride` tokens using fxhash.
+///
+/// With `stride == 1` this is the same as [`ngram`]; larger strides keep about `1 / stride` of the n-grams to make the set smaller.
+/// Use it with [`has_doc_duplicate_strided`] and the same `stride`, since the n-grams of the query and the document must be sampled alike.
+///
+/// # Panics
+/// Panics if `stride` is 0.
+///
+/// # Examples
+///
+/// ```
+/// let text = vec![1, 2, 3, 4, 5, 6];
+/// let ngrams = neardup::ngram_strided(&text, 2, 2);
+/// assert_eq!(ngrams.len(), 3);
+/// assert_eq!(ngrams.contains(&fxhash::hash(&vec![3, 4])), true);
+/// assert_eq!(ngrams.contains(&fxhash::hash(&vec![2, 3])), false);
+/// ```
+pub fn ngram_strided<T: Hash>(text: &[T], n: usize, stride: usize) -> HashSet<usize> {
+    assert!(stride > 0, "stride must be positive");
+    let mut ngrams = HashSet::default();
+    if n == 0 || text.len() < n {
+        return ngrams;
+    }
+    for i in (0..text.len() - n + 1).step_by(stride) {
+        ngrams.insert(fxhash::hash(&text[i..i + n]));
+    }
+    ngrams
+}
+
+/// Compute n-grams of raw bytes using rolling hash.
+///
+/// This lets you match UTF-8 text directly without tokenizing it; each byte is treated as one token.
+///
+/// # Examples
+///
+/// ```
+/// let ngrams = neardup::ngram_bytes(b"abcab", 2);
+/// assert_eq!(ngrams.len(), 3);
+/// assert_eq!(ngrams, neardup::ngram_rolling(&[97u8, 98, 99, 97, 98], 2));
+/// ```
+pub fn ngram_bytes(text: &[u8], n: usize) -> HashSet<usize> {
+    ngram_rolling(text, n)
+}
+
+/// Compute jaccard similarity between two precomputed n-gram sets.
+///
+/// This compares whole documents from their n-gram sets without going back to the tokens, e.g. for clustering.
+/// Both sets must be computed with the same n-gram function and `n`. Returns 0.0 if both sets are empty.
+///
+/// # Examples
+///
+/// ```
+/// let a = neardup::ngram(&[1, 2, 3, 4, 5], 2);
+/// let b = neardup::ngram(&[2, 3, 4, 5, 6], 2);
+/// assert_eq!(neardup::ngram_jaccard(&a, &b), 3.0 / 5.0);
+/// ```
+pub fn ngram_jaccard<H: Hash + Eq>(a: &HashSet<H>, b: &HashSet<H>) -> f64 {
+    let (smaller, larger) = if a.len() <= b.len() { (a, b) } else { (b, a) };
+    let intersection = smaller.iter().filter(|hash| larger.contains(hash)).count();
+    let union = a.len() + b.len() - intersection;
+    if union > 0 {
+        intersection as f64 / union as f64
+    } else {
+        0.0
+    }
+}
+
+/// Find the first span in the document whose similarity to the query is above a threshold using rabin-karp method with fxhash.
+///
+/// Returns the `(start, end)` token offsets of the matching span, i.e. `doc[start..end]` is the span that passed the threshold.
+///
+/// # Examples
+/// ```
+/// let query = vec![3, 4, 5, 6, 7];
+/// let doc = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+/// let n = 3;
+/// let query_ngram = neardup::ngram(&query, n);
+/// let sim_threshold = 0.8;
+/// assert_eq!(neardup::find_doc_duplicate(&doc, &query, &query_ngram, sim_threshold, n), Some((2, 7)));
+/// ```
+pub fn find_doc_duplicate<T: Hash + Eq>(
+    doc: &[T],
+    query: &[T],
+    query_ngram: &HashSet<usize>,
+    threshold: f64,
+    n: usize,
+) -> Option<(usize, usize)> {
+    let profile = QueryProfile::new(query);
+    find_doc_duplicate_with(doc, query, query_ngram, threshold, n, |_, span| {
+        profile.similarity(span)
+    })
+}
+
+/// Find the first span whose `sim(query, span)` is above a threshold.
+///
+/// This holds the n-gram prefilter shared by [`find_doc_duplicate`] and [`has_doc_duplicate_with`].
+fn find_doc_duplicate_with<T: Hash + Eq, F: Fn(&[T], &[T]) -> f64>(
+    doc: &[T],
+    query: &[T],
+    query_ngram: &HashSet<usize>,
+    threshold: f64,
+    n: usize,
+    sim: F,
+) -> Option<(usize, usize)> {
+    if n == 0 || query.len() > doc.len() || n > doc.len() {
+        return None;
+    }
+    for start in 0..=doc.len() - n {
+        let is_in_query_ngram = query_ngram.contains(&fxhash::hash(&doc[start..start + n]));
+        if !is_in_query_ngram {
+            continue;
+        }
+        let inner_start = max(0, start as i32 - query.len() as i32 + n as i32) as usize;
+        for s in inner_start..=min(start, doc.len() - query.len()) {
+            let end = s + query.len();
+            if sim(query, &doc[s..end]) >= threshold {
+                return Some((s, end));
+            }
+        }
+    }
+    None
+}
+
+/// Find all spans in the document whose similarity to the query is above a threshold using rabin-karp method with fxhash.
+///
+/// Returns `(start, end, similarity)` for each matching span `doc[start..end]`, ordered by `start`.
+/// Each span is evaluated at most once even if several n-gram hits cover it.
+/// Overlapping spans around the same match are all returned; use [`keep_local_best`] to reduce them to one span per match.
+///
+/// # Examples
+/// ```
+/// let query = vec![3, 4, 5, 6, 7];
+/// let doc = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+/// let n = 3;
+/// let query_ngram = neardup::ngram(&query, n);
+/// let spans = neardup::find_all_doc_duplicates(&doc, &query, &query_ngram, 0.6, n);
+/// assert_eq!(spans.len(), 3);
+/// assert_eq!(spans[1], (2, 7, 1.0));
+/// ```
+pub fn find_all_doc_duplicates<T: Hash + Eq>(
+    doc: &[T],
+    query: &[T],
+    query_ngram: &HashSet<usize>,
+    threshold: f64,
+    n: usize,
+) -> Vec<(usize, usize, f64)> {
+    if n == 0 || query.len() > doc.len() || n > doc.len() {
+        return Vec::new();
+    }
+    let profile = QueryProfile::new(query);
+    let mut spans = Vec::new();
+    // the first span start that has not been evaluated yet
+    let mut next_s = 0;
+    for start in 0..=doc.len() - n {
+        let is_in_query_ngram = query_ngram.contains(&fxhash::hash(&doc[start..start + n]));
+        if !is_in_query_ngram {
+            continue;
+        }
+        let inner_start = max(0, start as i32 - query.len() as i32 + n as i32) as usize;
+        for s in max(inner_start, next_s)..=min(start, doc.len() - query.len()) {
+            let end = s + query.len();
+            let sim = profile.similarity(&doc[s..end]);
+            if sim >= threshold {
+                spans.push((s, end, sim));
+            }
+        }
+        next_s = start + 1;
+    }
+    spans
+}
+
+/// Keep only the locally-best span within each cluster of overlapping spans.
+///
+/// `spans` must be ordered by start, as returned by [`find_all_doc_duplicates`].
+/// A cluster is a run of spans where each span overlaps the cluster built so far; the span with the highest similarity is kept (the earliest one on ties).
+///
+/// # Examples
+/// ```
+/// let spans = vec![(0, 5, 0.6), (1, 6, 0.8), (2, 7, 0.7), (10, 15, 0.9)];
+/// assert_eq!(neardup::keep_local_best(spans), vec![(1, 6, 0.8), (10, 15, 0.9)]);
+/// ```
+pub fn keep_local_best(spans: Vec<(usize, usize, f64)>) -> Vec<(usize, usize, f64)> {
+    let mut best_spans: Vec<(usize, usize, f64)> = Vec::new();
+    let mut cluster_end = 0;
+    for span in spans {
+        match best_spans.last_mut() {
+            Some(best) if span.0 < cluster_end => {
+                if span.2 > best.2 {
+                    *best = span;
+                }
+            }
+            _ => best_spans.push(span),
+        }
+        cluster_end = max(cluster_end, span.1);
+    }
+    best_spans
+}
+
+/// Intersection over union of two spans `(start, end)`.
+fn span_iou(a: (usize, usize), b: (usize, usize)) -> f64 {
+    let intersection = min(a.1, b.1).saturating_sub(max(a.0, b.0));
+    let union = (a.1 - a.0) + (b.1 - b.0) - intersection;
+    if union > 0 {
+        intersection as f64 / union as f64
+    } else {
+        0.0
+    }
+}
+
+/// Keep the distinct matching regions among spans with non-maximum suppression.
+///
+/// Spans are visited from the highest similarity (the earliest one on ties), and a span is kept unless its
+/// intersection over union with an already kept span is above `iou_threshold`. Returns the kept spans ordered by start.
+/// Unlike [`keep_local_best`], two spans that barely overlap are both kept, and `spans` may be in any order.
+///
+/// # Examples
+/// ```
+/// let spans = vec![(0, 5, 0.6), (1, 6, 0.8), (2, 7, 0.7), (4, 9, 0.7), (10, 15, 0.9)];
+/// assert_eq!(
+///     neardup::non_maximum_suppression(spans, 0.3),
+///     vec![(1, 6, 0.8), (4, 9, 0.7), (10, 15, 0.9)]
+/// );
+/// ```
+pub fn non_maximum_suppression(
+    mut spans: Vec<(usize, usize, f64)>,
+    iou_threshold: f64,
+) -> Vec<(usize, usize, f64)> {
+    spans.sort_by(|a, b| b.2.total_cmp(&a.2).then(a.0.cmp(&b.0)));
+    let mut kept: Vec<(usize, usize, f64)> = Vec::new();
+    for span in spans {
+        if kept
+            .iter()
+            .all(|best| span_iou((best.0, best.1), (span.0, span.1)) <= iou_threshold)
+        {
+            kept.push(span);
+        }
+    }
+    kept.sort_by_key(|span| span.0);
+    kept
+}
+
+/// Find the distinct regions of the document whose similarity to the query is above a threshold using rabin-karp method with fxhash.
+///
+/// Runs [`find_all_doc_duplicates`] and reduces the overlapping spans around each match with [`non_maximum_suppression`].
+///
+/// # Examples
+/// ```
+/// let query = vec![3, 4, 5, 6, 7];
+/// let doc = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+/// let n = 3;
+/// let query_ngram = neardup::ngram(&query, n);
+/// let spans = neardup::find_distinct_doc_duplicates(&doc, &query, &query_ngram, 0.6, n, 0.3);
+/// assert_eq!(spans, vec![(2, 7, 1.0)]);
+/// ```
+pub fn find_distinct_doc_duplicates<T: Hash + Eq>(
+    doc: &[T],
+    query: &[T],
+    query_ngram: &HashSet<usize>,
+    threshold: f64,
+    n: usize,
+    iou_threshold: f64,
+) -> Vec<(usize, usize, f64)> {
+    non_maximum_suppression(
+        find_all_doc_duplicates(doc, query, query_ngram, threshold, n),
+        iou_threshold,
+    )
+}
+
+/// A candidate span of [`top_k_spans`], ordered by similarity and then by the reverse of its start,
+/// so that the least similar span (the latest one on ties) is the minimum.
+#[derive(Clone, Copy, Debug)]
+struct ScoredSpan {
+    similarity: f64,
+    start: usize,
+}
+
+impl PartialEq for ScoredSpan {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == std::cmp::Ordering::Equal
+    }
+}
+
+impl Eq for ScoredSpan {}
+
+impl PartialOrd for ScoredSpan {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for ScoredSpan {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.similarity
+            .total_cmp(&other.similarity)
+            .then(other.start.cmp(&self.start))
+    }
+}
+
+/// Find the `k` most similar spans of the document to the query using rabin-karp method with fxhash.
+///
+/// Candidate spans are the ones sharing an n-gram with the query, as in [`best_doc_similarity`], and are kept in a
+/// min-heap bounded to `k` spans. Overlapping spans are suppressed: a candidate replaces the kept spans it overlaps
+/// if it is more similar than all of them, and is dropped otherwise, so the returned spans are disjoint.
+/// Returns `(start, end, similarity)` of at most `k` spans, from the most similar one (the earliest one on ties).
+///
+/// # Examples
+/// ```
+/// let query = vec![3, 4, 5, 6, 7];
+/// let doc = vec![3, 4, 5, 6, 0, 8, 9, 3, 4, 5, 6, 7, 10];
+/// let n = 3;
+/// let query_ngram = neardup::ngram(&query, n);
+/// assert_eq!(
+///     neardup::top_k_spans(&doc, &query, &query_ngram, n, 2),
+///     vec![(7, 12, 1.0), (0, 5, 4.0 / 6.0)]
+/// );
+/// ```
+pub fn top_k_spans<T: Hash + Eq>(
+    doc: &[T],
+    query: &[T],
+    query_ngram: &HashSet<usize>,
+    n: usize,
+    k: usize,
+) -> Vec<(usize, usize, f64)> {
+    if k == 0 || n == 0 || query.len() > doc.len() || n > doc.len() {
+        return Vec::new();
+    }
+    let profile = QueryProfile::new(query);
+    let overlaps = |a: &ScoredSpan, b: &ScoredSpan| {
+        span_iou(
+            (a.start, a.start + query.len()),
+            (b.start, b.start + query.len()),
+        ) > 0.0
+    };
+    let mut heap: BinaryHeap<Reverse<ScoredSpan>> = BinaryHeap::with_capacity(k + 1);
+    // the first span start that has not been evaluated yet
+    let mut next_s = 0;
+    for start in 0..=doc.len() - n {
+        if !query_ngram.contains(&fxhash::hash(&doc[start..start + n])) {
+            continue;
+        }
+        let inner_start = max(0, start as i32 - query.len() as i32 + n as i32) as usize;
+        for s in max(inner_start, next_s)..=min(start, doc.len() - query.len()) {
+            let span = ScoredSpan {
+                similarity: profile.similarity(&doc[s..s + query.len()]),
+                start: s,
+            };
+            if heap
+                .iter()
+                .any(|Reverse(kept)| overlaps(kept, &span) && kept.similarity >= span.similarity)
+            {
+                continue;
+            }
+            heap.retain(|Reverse(kept)| !overlaps(kept, &span));
+            heap.push(Reverse(span));
+            if heap.len() > k {
+                heap.pop();
+            }
+        }
+        next_s = start + 1;
+    }
+    heap.into_sorted_vec()
+        .into_iter()
+        .map(|Reverse(span)| (span.start, span.start + query.len(), span.similarity))
+        .collect()
+}
+
+/// How [`find_doc_duplicate_policy`] examines the candidate spans of each n-gram hit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InnerPolicy {
+    /// Return the first span above the threshold, as [`find_doc_duplicate`] does.
+    FirstMatch,
+    /// Examine every candidate span of the document and return the most similar one above the threshold (the earliest one on ties).
+    BestMatch,
+    /// Like `FirstMatch`, but evaluate at most this many candidate spans per n-gram hit, starting from the leftmost one.
+    /// Matches beyond the budget of every hit are missed, trading completeness for speed.
+    Budget(usize),
+}
+
+/// Find a span in the document whose similarity to the query is above a threshold, examining candidates according to `policy`.
+///
+/// Returns `(start, end, similarity)` of the reported span `doc[start..end]`.
+///
+/// # Examples
+/// ```
+/// use neardup::InnerPolicy;
+/// let query = vec![3, 4, 5, 6, 7];
+/// let doc = vec![3, 4, 5, 6, 0, 8, 3, 4, 5, 6, 7];
+/// let n = 3;
+/// let query_ngram = neardup::ngram(&query, n);
+/// let find = |policy| neardup::find_doc_duplicate_policy(&doc, &query, &query_ngram, 0.6, n, policy);
+/// assert_eq!(find(InnerPolicy::FirstMatch), Some((0, 5, 4.0 / 6.0)));
+/// assert_eq!(find(InnerPolicy::BestMatch), Some((6, 11, 1.0)));
+/// ```
+pub fn find_doc_duplicate_policy<T: Hash + Eq>(
+    doc: &[T],
+    query: &[T],
+    query_ngram: &HashSet<usize>,
+    threshold: f64,
+    n: usize,
+    policy: InnerPolicy,
+) -> Option<(usize, usize, f64)> {
+    if n == 0 || query.len() > doc.len() || n > doc.len() {
+        return None;
+    }
+    let profile = QueryProfile::new(query);
+    let mut best: Option<(usize, usize, f64)> = None;
+    // the first span start that has not been evaluated yet, only used by `BestMatch`
+    let mut next_s = 0;
+    for start in 0..=doc.len() - n {
+        let is_in_query_ngram = query_ngram.contains(&fxhash::hash(&doc[start..start + n]));
+        if !is_in_query_ngram {
+            continue;
+        }
+        let inner_start = max(0, start as i32 - query.len() as i32 + n as i32) as usize;
+        let inner_end = min(start, doc.len() - query.len());
+        let inner_start = match policy {
+            InnerPolicy::BestMatch => max(inner_start, next_s),
+            _ => inner_start,
+        };
+        let budget = match policy {
+            InnerPolicy::Budget(budget) => budget,
+            _ => usize::MAX,
+        };
+        for s in (inner_start..=inner_end).take(budget) {
+            let end = s + query.len();
+            let sim = profile.similarity(&doc[s..end]);
+            if sim < threshold {
+                continue;
+            }
+            if policy != InnerPolicy::BestMatch {
+                return Some((s, end, sim));
+            }
+            if best.is_none_or(|(_, _, best_sim)| sim > best_sim) {
+                best = Some((s, end, sim));
+            }
+        }
+        next_s = start + 1;
+    }
+    best
+}
+
+/// Compute the maximum similarity between the query and the spans of the document using rabin-karp method with fxhash.
+///
+/// Only candidate spans that share an n-gram with the query are compared, exactly as in [`has_doc_duplicate`].
+/// Returns 0.0 if there is no candidate span.
+///
+/// # Examples
+/// ```
+/// let query = vec![3, 4, 5, 6, 7];
+/// let doc = vec![1, 2, 3, 4, 5, 6, 0, 8, 9, 10];
+/// let n = 3;
+/// let query_ngram = neardup::ngram(&query, n);
+/// assert_eq!(neardup::best_doc_similarity(&doc, &query, &query_ngram, n), 4.0 / 6.0);
+/// ```
+pub fn best_doc_similarity<T: Hash + Eq>(
+    doc: &[T],
+    query: &[T],
+    query_ngram: &HashSet<usize>,
+    n: usize,
+) -> f64 {
+    if n == 0 || query.len() > doc.len() || n > doc.len() {
+        return 0.0;
+    }
+    let profile = QueryProfile::new(query);
+    let mut best_sim = 0.0;
+    // the first span start that has not been evaluated yet
+    let mut next_s = 0;
+    for start in 0..=doc.len() - n {
+        let is_in_query_ngram = query_ngram.contains(&fxhash::hash(&doc[start..start + n]));
+        if !is_in_query_ngram {
+            continue;
+        }
+        let inner_start = max(0, start as i32 - query.len() as i32 + n as i32) as usize;
+        for s in max(inner_start, next_s)..=min(start, doc.len() - query.len()) {
+            let end = s + query.len();
+            let sim = profile.similarity(&doc[s..end]);
+            if sim > best_sim {
+                best_sim = sim;
+            }
+        }
+        next_s = start + 1;
+    }
+    best_sim
+}
+
+/// Check whether the document contains spans whose similarity to the query is above each of the thresholds using rabin-karp method with fxhash.
+///
+/// Returns one result per threshold, equal to calling [`has_doc_duplicate`] with that threshold,
+/// but the document is scanned once and the similarity of each candidate span is computed only once.
+///
+/// # Examples
+/// ```
+/// let query = vec![3, 4, 5, 6, 7];
+/// let doc = vec![1, 2, 3, 4, 5, 6, 0, 8, 9, 10];
+/// let n = 3;
+/// let query_ngram = neardup::ngram(&query, n);
+/// assert_eq!(
+///     neardup::has_doc_duplicate_thresholds(&doc, &query, &query_ngram, &[0.5, 0.8, 1.0], n),
+///     vec![true, false, false]
+/// );
+/// ```
+pub fn has_doc_duplicate_thresholds<T: Hash + Eq>(
+    doc: &[T],
+    query: &[T],
+    query_ngram: &HashSet<usize>,
+    thresholds: &[f64],
+    n: usize,
+) -> Vec<bool> {
+    let mut results = vec![false; thresholds.len()];
+    if n == 0 || query.len() > doc.len() || n > doc.len() {
+        return results;
+    }
+    let max_threshold = thresholds.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    let profile = QueryProfile::new(query);
+    // the first span start that has not been evaluated yet
+    let mut next_s = 0;
+    for start in 0..=doc.len() - n {
+        let is_in_query_ngram = query_ngram.contains(&fxhash::hash(&doc[start..start + n]));
+        if !is_in_query_ngram {
+            continue;
+        }
+        let inner_start = max(0, start as i32 - query.len() as i32 + n as i32) as usize;
+        for s in max(inner_start, next_s)..=min(start, doc.len() - query.len()) {
+            let end = s + query.len();
+            let sim = profile.similarity(&doc[s..end]);
+            for (result, threshold) in results.iter_mut().zip(thresholds) {
+                *result |= sim >= *threshold;
+            }
+            if sim >= max_threshold {
+                return results;
+            }
+        }
+        next_s = start + 1;
+    }
+    results
+}
+
+///  Check whether the document contains spans whose similarity to the query is above a threshold using rabin-karp method with fxhash.
+///
+/// Returns false if `n == 0`. The query is not validated otherwise; an empty query or `n > query.len()` may panic or give meaningless results.
+/// Use [`try_has_doc_duplicate`] to get an error instead.
+///
+/// When `threshold >= 1.0`, only spans with exactly the same tokens and frequencies as the query can match,
+/// so a frequency vector is slid across the document once instead of computing the similarity of every candidate span.
+/// A span that is a permutation of the query is still a match, as its weighted jaccard similarity is 1.0, and a threshold above 1.0 matches nothing.
+/// Spans whose [`weighted_jaccard_upper_bound`] is below the threshold are skipped without computing their similarity.
+///
+/// # Examples
+/// ```
+/// let query = vec![1, 2, 3, 4, 5];
+/// let doc = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+/// let n = 3;
+/// let query_ngram = neardup::ngram(&query, n);
+/// let sim_threshold = 0.8;
+/// assert_eq!(neardup::has_doc_duplicate(&doc, &query, &query_ngram, sim_threshold, n), true);
+/// ```
+///
+pub fn has_doc_duplicate<T: Hash + Eq>(
+    doc: &[T],
+    query: &[T],
+    query_ngram: &HashSet<usize>,
+    threshold: f64,
+    n: usize,
+) -> bool {
+    has_doc_duplicate_profile(doc, &QueryProfile::new(query), query_ngram, threshold, n)
+}
+
+/// [`has_doc_duplicate`] with the frequency vector of the query computed by the caller, to reuse it across documents.
+fn has_doc_duplicate_profile<T: Hash + Eq>(
+    doc: &[T],
+    profile: &QueryProfile<T>,
+    query_ngram: &HashSet<usize>,
+    threshold: f64,
+    n: usize,
+) -> bool {
+    let query = profile.query();
+    if threshold >= 1.0 && 0 < n && n <= query.len() {
+        return has_doc_duplicate_exact(doc, query, query_ngram, threshold, n);
+    }
+    has_doc_duplicate_with(doc, query, query_ngram, threshold, n, |query, span| {
+        if weighted_jaccard_upper_bound(query.len(), span.len()) < threshold {
+            return 0.0;
+        }
+        profile.similarity(span)
+    })
+}
+
+/// Check whether the document contains spans with exactly the tokens of the query, i.e. whose weighted jaccard similarity is 1.0.
+///
+/// Scans the same candidate spans as [`find_doc_duplicate_with`]: a span is a candidate if one of its n-grams is in `query_ngram`.
+/// The similarity is still compared with `threshold`, so a threshold above 1.0 matches nothing.
+fn has_doc_duplicate_exact<T: Hash + Eq>(
+    doc: &[T],
+    query: &[T],
+    query_ngram: &HashSet<usize>,
+    threshold: f64,
+    n: usize,
+) -> bool {
+    if query.len() > doc.len() {
+        return false;
+    }
+    let last_start = doc.len() - query.len();
+    let is_hit = |start: usize| query_ngram.contains(&fxhash::hash(&doc[start..start + n]));
+    let mut window = SlidingFrequency::new(query.iter());
+    for token in &doc[..query.len() - 1] {
+        window.add(token);
+    }
+    // start of the latest n-gram found in the query
+    let mut last_hit = (0..query.len() - n).rev().find(|start| is_hit(*start));
+    for s in 0..=last_start {
+        if s > 0 {
+            window.remove(&doc[s - 1]);
+        }
+        window.add(&doc[s + query.len() - 1]);
+        if is_hit(s + query.len() - n) {
+            last_hit = Some(s + query.len() - n);
+        }
+        if last_hit.is_some_and(|hit| hit >= s) && window.similarity() >= threshold {
+            return true;
+        }
+    }
+    false
+}
+
+/// Check whether the document contains spans whose similarity to the query is above a threshold using rabin-karp method with fxhash and an incremental frequency vector.
+///
+/// Returns the same result as [`has_doc_duplicate`], but the frequency vector of the candidate span is updated by one add and one remove as the span advances instead of being rebuilt from scratch.
+///
+/// # Examples
+/// ```
+/// let query = vec![1, 2, 3, 4, 5];
+/// let doc = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+/// let n = 3;
+/// let query_ngram = neardup::ngram(&query, n);
+/// let sim_threshold = 0.8;
+/// assert_eq!(neardup::has_doc_duplicate_incremental(&doc, &query, &query_ngram, sim_threshold, n), true);
+/// ```
+pub fn has_doc_duplicate_incremental<T: Hash + Eq>(
+    doc: &[T],
+    query: &[T],
+    query_ngram: &HashSet<usize>,
+    threshold: f64,
+    n: usize,
+) -> bool {
+    if n == 0 || query.len() > doc.len() || n > doc.len() {
+        return false;
+    }
+    let mut window = SlidingFrequency::new(query.iter());
+    // doc[window_start..window_end] is the current window
+    let (mut window_start, mut window_end) = (0, 0);
+    // the first span start that has not been evaluated yet
+    let mut next_s = 0;
+    for start in 0..=doc.len() - n {
+        let is_in_query_ngram = query_ngram.contains(&fxhash::hash(&doc[start..start + n]));
+        if !is_in_query_ngram {
+            continue;
+        }
+        let inner_start = max(0, start as i32 - query.len() as i32 + n as i32) as usize;
+        for s in max(inner_start, next_s)..=min(start, doc.len() - query.len()) {
+            while window_start < s.min(window_end) {
+                window.remove(&doc[window_start]);
+                window_start += 1;
+            }
+            if window_end < s {
+                (window_start, window_end) = (s, s);
+            }
+            while window_end < s + query.len() {
+                window.add(&doc[window_end]);
+                window_end += 1;
+            }
+            if window.similarity() >= threshold {
+                return true;
+            }
+        }
+        next_s = start + 1;
+    }
+    false
+}
+
+/// Check whether the document contains spans whose similarity to the query is above a threshold using rabin-karp method with fxhash, ignoring the given tokens.
+///
+/// The ignored tokens are dropped from the document before computing the n-grams and the similarity,
+/// so spans are `query.len()` tokens of the document once ignored tokens are dropped.
+/// The query is expected to be filtered already with [`remove_ignored`] and `query_ngram` computed from it with [`ngram`],
+/// so that both are prepared once for all documents.
+///
+/// # Examples
+/// ```
+/// let query = vec![0, 0, 0, 0, 1, 2, 3, 4];
+/// let doc = vec![0, 0, 0, 0, 5, 6, 7, 8, 9, 9, 9, 9];
+/// let n = 2;
+/// assert_eq!(neardup::has_doc_duplicate(&doc, &query, &neardup::ngram(&query, n), 0.3, n), true);
+/// let ignore = [0].into_iter().collect();
+/// let query = neardup::remove_ignored(&query, &ignore);
+/// let query_ngram = neardup::ngram(&query, n);
+/// assert_eq!(neardup::has_doc_duplicate_filtered(&doc, &query, &query_ngram, 0.3, n, &ignore), false);
+/// ```
+pub fn has_doc_duplicate_filtered<T: Hash + Eq + Copy>(
+    doc: &[T],
+    query: &[T],
+    query_ngram: &HashSet<usize>,
+    threshold: f64,
+    n: usize,
+    ignore: &HashSet<T>,
+) -> bool {
+    let doc = remove_ignored(doc, ignore);
+    has_doc_duplicate(&doc, query, query_ngram, threshold, n)
+}
+
+/// Check whether the document contains spans of length `query.len() - len_tolerance` to `query.len() + len_tolerance` whose similarity to the query is above a threshold using rabin-karp method with fxhash.
+///
+/// This finds near-duplicates with a few tokens inserted or deleted, which [`has_doc_duplicate`] misses as it only compares spans of exactly `query.len()` tokens.
+/// Spans are never shorter than `n`, and a span is a candidate if one of its n-grams is in `query_ngram`.
+/// Span lengths whose [`max_possible_jaccard`] with the query length is below the threshold are pruned up front.
+/// A single frequency vector is slid across the document: for each candidate start, the tokens before it are removed,
+/// the window is resized to the shortest span, and it is extended one token at a time for the longer ones.
+///
+/// # Examples
+/// ```
+/// let query = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+/// // one token inserted in the middle of the query
+/// let doc = vec![0, 1, 2, 3, 4, 5, 99, 6, 7, 8, 9, 10, 0];
+/// let n = 3;
+/// let query_ngram = neardup::ngram(&query, n);
+/// assert_eq!(neardup::has_doc_duplicate(&doc, &query, &query_ngram, 0.9, n), false);
+/// assert_eq!(neardup::has_doc_duplicate_flex(&doc, &query, &query_ngram, 0.9, n, 1), true);
+/// ```
+pub fn has_doc_duplicate_flex<T: Hash + Eq>(
+    doc: &[T],
+    query: &[T],
+    query_ngram: &HashSet<usize>,
+    threshold: f64,
+    n: usize,
+    len_tolerance: usize,
+) -> bool {
+    has_doc_duplicate_flex_stats(doc, query, query_ngram, threshold, n, len_tolerance).matched
+}
+
+/// Check whether the document contains spans of length `query.len() - len_tolerance` to `query.len() + len_tolerance` whose similarity to the query is above a threshold, counting the work done.
+///
+/// Scans the same spans as [`has_doc_duplicate_flex`], where every length tried for a start counts as one `jaccard_calls`.
+///
+/// # Examples
+/// ```
+/// let query = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+/// let doc = vec![0, 1, 2, 3, 4, 5, 99, 6, 7, 8, 9, 10, 0];
+/// let n = 3;
+/// let query_ngram = neardup::ngram(&query, n);
+/// let stats = neardup::has_doc_duplicate_flex_stats(&doc, &query, &query_ngram, 0.9, n, 1);
+/// assert!(stats.matched);
+/// ```
+pub fn has_doc_duplicate_flex_stats<T: Hash + Eq>(
+    doc: &[T],
+    query: &[T],
+    query_ngram: &HashSet<usize>,
+    threshold: f64,
+    n: usize,
+    len_tolerance: usize,
+) -> MatchStats {
+    let mut stats = MatchStats::default();
+    let mut min_len = max(query.len().saturating_sub(len_tolerance), max(n, 1));
+    let mut max_len = query.len() + len_tolerance;
+    // the bound peaks at the query length, so the lengths that can reach the threshold are contiguous
+    while min_len <= max_len && max_possible_jaccard(query.len(), min_len) < threshold {
+        min_len += 1;
+    }
+    while max_len >= min_len && max_possible_jaccard(query.len(), max_len) < threshold {
+        max_len -= 1;
+    }
+    if n == 0 || min_len > max_len || min_len > doc.len() || n > doc.len() {
+        return stats;
+    }
+    // the first span start that has not been evaluated yet
+    let mut next_s = 0;
+    // the window holds doc[window_start..window_end], and the span starts only increase
+    let mut window = SlidingFrequency::new(query.iter());
+    let (mut window_start, mut window_end) = (0, 0);
+    for start in 0..=doc.len() - n {
+        let is_in_query_ngram = query_ngram.contains(&fxhash::hash(&doc[start..start + n]));
+        if !is_in_query_ngram {
+            continue;
+        }
+        stats.ngram_hits += 1;
+        // spans evaluated for an earlier n-gram covered every length tried for this one
+        for s in max((start + n).saturating_sub(max_len), next_s)..(start + 1) {
+            // the span must contain doc[start..start + n]
+            let shortest = max(min_len, start + n - s);
+            let longest = min(max_len, doc.len() - s);
+            if shortest > longest {
+                continue;
+            }
+            while window_start < s {
+                if window_start < window_end {
+                    window.remove(&doc[window_start]);
+                } else {
+                    window_end += 1;
+                }
+                window_start += 1;
+            }
+            while window_end > s + shortest {
+                window_end -= 1;
+                window.remove(&doc[window_end]);
+            }
+            for token in &doc[window_end..s + shortest] {
+                window.add(token);
+            }
+            window_end = s + shortest;
+            stats.jaccard_calls += 1;
+            if window.similarity() >= threshold {
+                stats.matched = true;
+                return stats;
+            }
+            for token in &doc[s + shortest..s + longest] {
+                window.add(token);
+                window_end += 1;
+                stats.jaccard_calls += 1;
+                if window.similarity() >= threshold {
+                    stats.matched = true;
+                    return stats;
+                }
+            }
+        }
+        next_s = start + 1;
+    }
+    stats
+}
+
+/// Check whether the document contains spans whose similarity to the query is above a threshold using rabin-karp method with fxhash, validating the inputs first.
+///
+/// # Errors
+/// Returns [`NeardupError`] if the query is empty, `n == 0`, or `n > query.len()`.
+///
+/// # Examples
+/// ```
+/// let query = vec![1, 2, 3, 4, 5];
+/// let doc = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+/// let query_ngram = neardup::ngram(&query, 3);
+/// assert_eq!(neardup::try_has_doc_duplicate(&doc, &query, &query_ngram, 0.8, 3), Ok(true));
+/// assert_eq!(
+///     neardup::try_has_doc_duplicate(&doc, &query, &query_ngram, 0.8, 0),
+///     Err(neardup::NeardupError::ZeroN)
+/// );
+/// ```
+pub fn try_has_doc_duplicate<T: Hash + Eq>(
+    doc: &[T],
+    query: &[T],
+    query_ngram: &HashSet<usize>,
+    threshold: f64,
+    n: usize,
+) -> Result<bool, NeardupError> {
+    validate_query(query, n)?;
+    Ok(has_doc_duplicate(doc, query, query_ngram, threshold, n))
+}
+
+/// Check whether the document contains spans whose similarity to the query is above a threshold using rabin-karp method with fxhash and a custom similarity function.
+///
+/// `sim` is called as `sim(query, span)` for each candidate span.
+///
+/// # Examples
+/// ```
+/// let query = vec![1, 2, 3, 4, 5];
+/// let doc = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+/// let n = 3;
+/// let query_ngram = neardup::ngram(&query, n);
+/// let sim_threshold = 0.8;
+/// assert_eq!(neardup::has_doc_duplicate_with(&doc, &query, &query_ngram, sim_threshold, n, neardup::dice_coefficient), true);
+/// ```
+pub fn has_doc_duplicate_with<T: Hash + Eq, F: Fn(&[T], &[T]) -> f64>(
+    doc: &[T],
+    query: &[T],
+    query_ngram: &HashSet<usize>,
+    threshold: f64,
+    n: usize,
+    sim: F,
+) -> bool {
+    find_doc_duplicate_with(doc, query, query_ngram, threshold, n, sim).is_some()
+}
+
+/// Counters collected by [`has_doc_duplicate_stats`] and its variants, such as [`has_doc_duplicate_rolling_stats`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MatchStats {
+    /// Number of document n-grams found in the query n-grams.
+    pub ngram_hits: usize,
+    /// Number of candidate spans whose similarity to the query was computed.
+    pub jaccard_calls: usize,
+    /// Whether a span above the threshold was found.
+    pub matched: bool,
+}
+
+/// Check whether the document contains spans whose similarity to the query is above a threshold using rabin-karp method with fxhash, counting the work done.
+///
+/// Scans the same spans as [`has_doc_duplicate_with`] with [`weighted_jaccard`] and stops at the first match.
+/// Comparing `jaccard_calls` with `ngram_hits` tells how effective the n-gram prefilter is for a given `n`.
+///
+/// # Examples
+/// ```
+/// let query = vec![1, 2, 3, 4, 5];
+/// let doc = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+/// let n = 3;
+/// let query_ngram = neardup::ngram(&query, n);
+/// let stats = neardup::has_doc_duplicate_stats(&doc, &query, &query_ngram, 0.8, n);
+/// assert_eq!(stats, neardup::MatchStats { ngram_hits: 1, jaccard_calls: 1, matched: true });
+/// ```
+pub fn has_doc_duplicate_stats<T: Hash + Eq>(
+    doc: &[T],
+    query: &[T],
+    query_ngram: &HashSet<usize>,
+    threshold: f64,
+    n: usize,
+) -> MatchStats {
+    let mut stats = MatchStats::default();
+    if n == 0 || query.len() > doc.len() || n > doc.len() {
+        return stats;
+    }
+    let profile = QueryProfile::new(query);
+    for start in 0..=doc.len() - n {
+        let is_in_query_ngram = query_ngram.contains(&fxhash::hash(&doc[start..start + n]));
+        if !is_in_query_ngram {
+            continue;
+        }
+        stats.ngram_hits += 1;
+        let inner_start = max(0, start as i32 - query.len() as i32 + n as i32) as usize;
+        for s in inner_start..=min(start, doc.len() - query.len()) {
+            stats.jaccard_calls += 1;
+            if profile.similarity(&doc[s..s + query.len()]) >= threshold {
+                stats.matched = true;
+                return stats;
+            }
+        }
+    }
+    stats
+}
+
+/// Check whether the document contains spans whose similarity to the query is above a threshold and which share a contiguous run of at least `min_match_len` tokens with the query, using rabin-karp method with fxhash.
+///
+/// Candidate spans always have the length of the query, so the length filter applies to the longest run of tokens appearing contiguously in both the query and the span.
+/// This rejects spans that only reach the threshold by sharing scattered common tokens.
+///
+/// # Examples
+/// ```
+/// let query = vec![1, 2, 3, 4, 5, 6, 7, 8];
+/// // same tokens as the query, but the longest shared run is [1, 2]
+/// let doc = vec![1, 2, 5, 6, 3, 4, 7, 8, 9, 9];
+/// let n = 2;
+/// let query_ngram = neardup::ngram(&query, n);
+/// assert_eq!(neardup::has_doc_duplicate(&doc, &query, &query_ngram, 0.8, n), true);
+/// assert_eq!(neardup::has_doc_duplicate_min_len(&doc, &query, &query_ngram, 0.8, n, 4), false);
+/// ```
+pub fn has_doc_duplicate_min_len<T: Hash + Eq>(
+    doc: &[T],
+    query: &[T],
+    query_ngram: &HashSet<usize>,
+    threshold: f64,
+    n: usize,
+    min_match_len: usize,
+) -> bool {
+    let profile = QueryProfile::new(query);
+    has_doc_duplicate_with(doc, query, query_ngram, threshold, n, |query, span| {
+        let sim = profile.similarity(span);
+        if sim < threshold || longest_common_run(query, span) < min_match_len {
+            return 0.0;
+        }
+        sim
+    })
+}
+
+/// Compute the length of the longest run of tokens appearing contiguously in both texts.
+fn longest_common_run<T: Eq>(text1: &[T], text2: &[T]) -> usize {
+    // run[j] is the length of the common run ending at text1[i] and text2[j - 1]
+    let mut run = vec![0; text2.len() + 1];
+    let mut longest = 0;
+    for x in text1 {
+        for j in (1..=text2.len()).rev() {
+            if *x == text2[j - 1] {
+                run[j] = run[j - 1] + 1;
+                longest = max(longest, run[j]);
+            } else {
+                run[j] = 0;
+            }
+        }
+    }
+    longest
+}
+
+/// Check whether the document contains spans whose [`containment`] of the query is above a threshold using rabin-karp method with fxhash.
+///
+/// Use it to check whether a short sample appears inside a document with a few tokens changed.
+/// Candidate spans have the length of the query as in [`has_doc_duplicate`].
+///
+/// # Examples
+/// ```
+/// let query = vec![1, 2, 3, 4, 5];
+/// let doc = vec![9, 1, 2, 3, 0, 5, 4, 9, 9, 9];
+/// let n = 2;
+/// let query_ngram = neardup::ngram(&query, n);
+/// assert_eq!(neardup::has_doc_duplicate_containment(&doc, &query, &query_ngram, 0.8, n), true);
+/// assert_eq!(neardup::has_doc_duplicate(&doc, &query, &query_ngram, 0.8, n), false);
+/// ```
+pub fn has_doc_duplicate_containment<T: Hash + Eq>(
+    doc: &[T],
+    query: &[T],
+    query_ngram: &HashSet<usize>,
+    threshold: f64,
+    n: usize,
+) -> bool {
+    has_doc_duplicate_with(doc, query, query_ngram, threshold, n, containment)
+}
+
+/// Check whether the document contains spans whose set-based [`jaccard`] similarity to the query is above a threshold using rabin-karp method with fxhash.
+///
+/// # Examples
+/// ```
+/// let query = vec![1, 1, 2, 2, 3];
+/// let doc = vec![9, 1, 2, 3, 3, 3, 9, 9, 9, 9];
+/// let n = 2;
+/// let query_ngram = neardup::ngram(&query, n);
+/// assert_eq!(neardup::has_doc_duplicate_set(&doc, &query, &query_ngram, 1.0, n), true);
+/// assert_eq!(neardup::has_doc_duplicate(&doc, &query, &query_ngram, 1.0, n), false);
+/// ```
+pub fn has_doc_duplicate_set<T: Hash + Eq>(
+    doc: &[T],
+    query: &[T],
+    query_ngram: &HashSet<usize>,
+    threshold: f64,
+    n: usize,
+) -> bool {
+    has_doc_duplicate_with(doc, query, query_ngram, threshold, n, jaccard)
+}
+
+/// Check whether a circular document contains spans whose similarity to the query is above a threshold using rabin-karp method with fxhash.
+///
+/// The document is treated as cyclic, e.g. a ring buffer or a circular genome, so spans wrapping around from its end to its start are also compared.
+/// It is scanned with [`has_doc_duplicate`] after appending its first `query.len() - 1` tokens.
+/// A span cannot go around the document more than once, so a query longer than the document never matches.
+///
+/// # Examples
+/// ```
+/// let query = vec![9, 10, 1, 2];
+/// let doc = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+/// let n = 2;
+/// let query_ngram = neardup::ngram(&query, n);
+/// assert_eq!(neardup::has_doc_duplicate(&doc, &query, &query_ngram, 1.0, n), false);
+/// assert_eq!(neardup::has_doc_duplicate_circular(&doc, &query, &query_ngram, 1.0, n), true);
+/// ```
+pub fn has_doc_duplicate_circular<T: Hash + Eq + Clone>(
+    doc: &[T],
+    query: &[T],
+    query_ngram: &HashSet<usize>,
+    threshold: f64,
+    n: usize,
+) -> bool {
+    if query.is_empty() || query.len() > doc.len() {
+        return false;
+    }
+    let extended_doc = [doc, &doc[..query.len() - 1]].concat();
+    has_doc_duplicate(&extended_doc, query, query_ngram, threshold, n)
+}
+
+/// Check whether the document contains spans whose [`normalized_levenshtein`] similarity to the query is above a threshold using rabin-karp method with fxhash.
+///
+/// Use it to find copied text, since a span with the tokens of the query in another order does not match.
+/// Each candidate costs O(query.len()^2), so the n-gram prefilter is what keeps this affordable on long documents.
+///
+/// # Examples
+/// ```
+/// let query = vec![1, 2, 3, 4, 5];
+/// let doc = vec![9, 5, 4, 3, 2, 1, 9, 1, 2, 3];
+/// let n = 1;
+/// let query_ngram = neardup::ngram(&query, n);
+/// assert_eq!(neardup::has_doc_duplicate(&doc, &query, &query_ngram, 1.0, n), true);
+/// assert_eq!(neardup::has_doc_duplicate_edit(&doc, &query, &query_ngram, 0.8, n), false);
+/// ```
+pub fn has_doc_duplicate_edit<T: Hash + Eq>(
+    doc: &[T],
+    query: &[T],
+    query_ngram: &HashSet<usize>,
+    threshold: f64,
+    n: usize,
+) -> bool {
+    has_doc_duplicate_with(
+        doc,
+        query,
+        query_ngram,
+        threshold,
+        n,
+        normalized_levenshtein,
+    )
+}
+
+/// Hash a token to decide whether [`has_doc_duplicate_approx`] samples it, with the splitmix64 finalizer.
+fn sample_hash(token: i32) -> u64 {
+    let mut z = (token as u64).wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Check whether the document contains spans whose similarity to the query is above a threshold using rabin-karp method with fxhash,
+/// estimating the similarity of each candidate span from a sample of the tokens.
+///
+/// A token is sampled if its hash falls below a fixed cutoff, so that about `sample_size` tokens of the query are sampled,
+/// and the estimate is the weighted jaccard similarity of the query and the span restricted to the sampled tokens.
+/// The frequency vector of the sampled tokens is updated as the candidate span advances, so a span costs O(1) instead of the O(`query.len()`) of [`has_doc_duplicate`].
+/// Spans whose estimate is clearly above or below the threshold are accepted or rejected without computing [`weighted_jaccard`];
+/// only estimates within `1 / sqrt(m)` of the threshold, where `m` is the size of the sampled multiset union and about two standard errors of the estimate, fall back to the exact similarity.
+/// The result is an approximation of [`has_doc_duplicate`], which a larger `sample_size` makes closer at the cost of more exact fallbacks;
+/// if `sample_size >= query.len()`, every token is sampled and the result is exact.
+/// The sample is deterministic.
+///
+/// This replaces an earlier per-span [`MinHash`] estimate with `num_perm` permutations: a MinHash had to be rebuilt in
+/// O(`num_perm * query.len()`) for every span, which was slower than the exact similarity, and it estimated the jaccard
+/// similarity of the token sets, which accepted spans whose weighted similarity is below the threshold.
+///
+/// # Examples
+/// ```
+/// let query = (0..50).collect::<Vec<i32>>();
+/// let doc = (0..100).collect::<Vec<i32>>();
+/// let n = 3;
+/// let query_ngram = neardup::ngram(&query, n);
+/// assert_eq!(neardup::has_doc_duplicate_approx(&doc, &query, &query_ngram, 0.8, n, 32), true);
+/// assert_eq!(neardup::has_doc_duplicate_approx(&doc[30..], &query, &query_ngram, 0.8, n, 32), false);
+/// ```
+pub fn has_doc_duplicate_approx(
+    doc: &[i32],
+    query: &[i32],
+    query_ngram: &HashSet<usize>,
+    threshold: f64,
+    n: usize,
+    sample_size: usize,
+) -> bool {
+    if n == 0 || query.len() > doc.len() || n > doc.len() {
+        return false;
+    }
+    let is_exact = sample_size >= query.len();
+    let cutoff = (sample_size as f64 / query.len() as f64 * u64::MAX as f64) as u64;
+    let is_sampled = |token: &i32| is_exact || sample_hash(*token) < cutoff;
+    let profile = QueryProfile::new(query);
+    let mut window = SlidingFrequency::new(query.iter().filter(|token| is_sampled(token)));
+    // doc[window_start..window_end] is the current window, of which only the sampled tokens are counted
+    let (mut window_start, mut window_end) = (0, 0);
+    // the first span start that has not been evaluated yet
+    let mut next_s = 0;
+    for start in 0..=doc.len() - n {
+        let is_in_query_ngram = query_ngram.contains(&fxhash::hash(&doc[start..start + n]));
+        if !is_in_query_ngram {
+            continue;
+        }
+        let inner_start = max(0, start as i32 - query.len() as i32 + n as i32) as usize;
+        for s in max(inner_start, next_s)..=min(start, doc.len() - query.len()) {
+            while window_start < s.min(window_end) {
+                if is_sampled(&doc[window_start]) {
+                    window.remove(&doc[window_start]);
+                }
+                window_start += 1;
+            }
+            if window_end < s {
+                (window_start, window_end) = (s, s);
+            }
+            while window_end < s + query.len() {
+                if is_sampled(&doc[window_end]) {
+                    window.add(&doc[window_end]);
+                }
+                window_end += 1;
+            }
+            let estimate = window.similarity();
+            if is_exact {
+                if estimate >= threshold {
+                    return true;
+                }
+                continue;
+            }
+            let epsilon = 1.0 / (window.union_frequency() as f64).sqrt();
+            if estimate - epsilon >= threshold {
+                return true;
+            }
+            if estimate + epsilon >= threshold
+                && profile.similarity(&doc[s..s + query.len()]) >= threshold
+            {
+                return true;
+            }
+        }
+        next_s = start + 1;
+    }
+    false
+}
+
+///  Check whether the document contains spans whose similarity to the query is above a threshold using naive method.
+///
+/// # Examples
+/// ```
+/// let query = vec![1, 2, 3, 4, 5];
+/// let doc = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+/// let sim_threshold = 0.8;
+/// assert_eq!(neardup::has_doc_duplicate_naive(&doc, &query, sim_threshold), true);
+/// ```
+pub fn has_doc_duplicate_naive<T: Hash + Eq>(doc: &[T], query: &[T], threshold: f64) -> bool {
+    find_doc_duplicate_naive(doc, query, threshold).is_some()
+}
+
+/// Find the first span in the document whose similarity to the query is above a threshold using naive method.
+///
+/// Returns the start of the span and its similarity, comparing every span of the document without an n-gram prefilter.
+/// Use it as an oracle: any span found by the fast methods is also above the threshold, though not necessarily the first one.
+///
+/// # Examples
+/// ```
+/// let query = vec![3, 4, 5, 6, 7];
+/// let doc = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+/// assert_eq!(neardup::find_doc_duplicate_naive(&doc, &query, 0.6), Some((1, 4.0 / 6.0)));
+/// assert_eq!(neardup::find_doc_duplicate_naive(&doc[..4], &query, 0.6), None);
+/// ```
+pub fn find_doc_duplicate_naive<T: Hash + Eq>(
+    doc: &[T],
+    query: &[T],
+    threshold: f64,
+) -> Option<(usize, f64)> {
+    if query.len() > doc.len() {
+        return None;
+    }
+    for start in 0..=doc.len() - query.len() {
+        let sim = weighted_jaccard(query, &doc[start..start + query.len()]);
+        if sim >= threshold {
+            return Some((start, sim));
+        }
+    }
+    None
+}
+
+/// Check whether the document contains spans whose similarity to the query is above a threshold using rabin-karp method with 64-bit fxhash.
+///
+/// Returns the same result as [`has_doc_duplicate`], but `query_ngram` must be computed with [`ngram_fx64`], which has fewer collisions on platforms where `usize` is 32 bits.
+///
+/// # Examples
+/// ```
+/// let query = vec![1, 2, 3, 4, 5];
+/// let doc = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+/// let n = 3;
+/// let query_ngram = neardup::ngram_fx64(&query, n);
+/// assert_eq!(neardup::has_doc_duplicate_fx64(&doc, &query, &query_ngram, 0.8, n), true);
+/// ```
+pub fn has_doc_duplicate_fx64<T: Hash + Eq>(
+    doc: &[T],
+    query: &[T],
+    query_ngram: &HashSet<u64>,
+    threshold: f64,
+    n: usize,
+) -> bool {
+    if n == 0 || query.len() > doc.len() || n > doc.len() {
+        return false;
+    }
+    let profile = QueryProfile::new(query);
+    for start in 0..=doc.len() - n {
+        let is_in_query_ngram = query_ngram.contains(&fxhash::hash64(&doc[start..start + n]));
+        if !is_in_query_ngram {
+            continue;
+        }
+        let inner_start = max(0, start as i32 - query.len() as i32 + n as i32) as usize;
+        for s in inner_start..=min(start, doc.len() - query.len()) {
+            let end = s + query.len();
+            if profile.similarity(&doc[s..end]) >= threshold {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+/// Check whether the document contains spans whose similarity to the query is above a threshold using rabin-karp method with fxhash,
+/// looking up only the n-grams of the document that start every `stride` tokens.
+///
+/// `query_ngram` must be computed with [`ngram_strided`] and the same `stride`.
+/// Since both sides are sampled from offset 0, a copy of the query is found only if it starts at a multiple of `stride` in the document,
+/// e.g. when the documents are packed at fixed boundaries; with `stride == 1` this returns the same result as [`has_doc_duplicate`].
+///
+/// # Panics
+/// Panics if `stride` is 0.
+///
+/// # Examples
+/// ```
+/// let query = vec![1, 2, 3, 4, 5, 6];
+/// let doc = vec![9, 9, 1, 2, 3, 4, 5, 6, 9, 9];
+/// let n = 2;
+/// let query_ngram = neardup::ngram_strided(&query, n, 2);
+/// assert_eq!(neardup::has_doc_duplicate_strided(&doc, &query, &query_ngram, 1.0, n, 2), true);
+/// assert_eq!(neardup::has_doc_duplicate_strided(&doc[1..], &query, &query_ngram, 1.0, n, 2), false);
+/// ```
+pub fn has_doc_duplicate_strided<T: Hash + Eq>(
+    doc: &[T],
+    query: &[T],
+    query_ngram: &HashSet<usize>,
+    threshold: f64,
+    n: usize,
+    stride: usize,
+) -> bool {
+    assert!(stride > 0, "stride must be positive");
+    if n == 0 || query.len() > doc.len() || n > doc.len() {
+        return false;
+    }
+    let profile = QueryProfile::new(query);
+    for start in (0..=doc.len() - n).step_by(stride) {
+        let is_in_query_ngram = query_ngram.contains(&fxhash::hash(&doc[start..start + n]));
+        if !is_in_query_ngram {
+            continue;
+        }
+        let inner_start = max(0, start as i32 - query.len() as i32 + n as i32) as usize;
+        for s in inner_start..=min(start, doc.len() - query.len()) {
+            let end = s + query.len();
+            if profile.similarity(&doc[s..end]) >= threshold {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+/// Check whether the document contains spans whose similarity to the query is above a threshold using rabin-karp method with fxhash,
+/// prefiltering with the n-grams of several sizes in one pass.
+///
+/// `query_ngrams` holds pairs of an n-gram size and the n-grams of the query of that size, e.g. from [`ngram`] or [`ngram_strided`].
+/// A start of the document is a hit if its n-gram of *any* size is in the set of that size, and the candidate spans of
+/// the smallest hitting size are compared to the query. Each added size hashes every position of the document once more
+/// and can only add candidates, trading speed for sensitivity. With complete sets from [`ngram`], a hit of a larger n
+/// contains a hit of a smaller n at the same start, so the candidates are those of the smallest n; combining sizes pays off
+/// when the sets are sampled, such as a sparse set of short n-grams complemented by the long n-grams of the query.
+/// Sizes of 0 are ignored.
+///
+/// # Examples
+/// ```
+/// let query = vec![1, 2, 3, 4, 5];
+/// let doc = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+/// let query_ngrams = vec![(2, neardup::ngram(&query, 2)), (4, neardup::ngram(&query, 4))];
+/// assert_eq!(neardup::has_doc_duplicate_multi_n(&doc, &query, &query_ngrams, 0.8), true);
+/// ```
+pub fn has_doc_duplicate_multi_n<T: Hash + Eq>(
+    doc: &[T],
+    query: &[T],
+    query_ngrams: &[(usize, HashSet<usize>)],
+    threshold: f64,
+) -> bool {
+    if query.is_empty() || query.len() > doc.len() {
+        return false;
+    }
+    let profile = QueryProfile::new(query);
+    // the first span start that has not been evaluated yet
+    let mut next_s = 0;
+    for start in 0..doc.len() {
+        let Some(n) = query_ngrams
+            .iter()
+            .filter(|(n, query_ngram)| {
+                *n > 0
+                    && start + n <= doc.len()
+                    && query_ngram.contains(&fxhash::hash(&doc[start..start + n]))
+            })
+            .map(|(n, _)| *n)
+            .min()
+        else {
+            continue;
+        };
+        let inner_start = max(0, start as i32 - query.len() as i32 + n as i32) as usize;
+        for s in max(inner_start, next_s)..=min(start, doc.len() - query.len()) {
+            if profile.similarity(&doc[s..s + query.len()]) >= threshold {
+                return true;
+            }
+        }
+        next_s = start + 1;
+    }
+    false
+}
+
+/// Check whether the document contains spans whose similarity to the query is above a threshold using rabin-karp method with seeded 64-bit fxhash.
+///
+/// Returns the same result as [`has_doc_duplicate`] barring hash collisions, but `query_ngram` must be computed with [`ngram_seeded`] and the same `seed`.
+///
+/// # Examples
+/// ```
+/// let query = vec![1, 2, 3, 4, 5];
+/// let doc = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+/// let n = 3;
+/// let query_ngram = neardup::ngram_seeded(&query, n, 42);
+/// assert_eq!(neardup::has_doc_duplicate_seeded(&doc, &query, &query_ngram, 0.8, n, 42), true);
+/// assert_eq!(neardup::has_doc_duplicate_seeded(&doc, &query, &query_ngram, 0.8, n, 43), false);
+/// ```
+pub fn has_doc_duplicate_seeded<T: Hash + Eq>(
+    doc: &[T],
+    query: &[T],
+    query_ngram: &HashSet<u64>,
+    threshold: f64,
+    n: usize,
+    seed: u64,
+) -> bool {
+    if n == 0 || query.len() > doc.len() || n > doc.len() {
+        return false;
+    }
+    let profile = QueryProfile::new(query);
+    for start in 0..=doc.len() - n {
+        let is_in_query_ngram = query_ngram.contains(&seeded_hash(&doc[start..start + n], seed));
+        if !is_in_query_ngram {
+            continue;
+        }
+        let inner_start = max(0, start as i32 - query.len() as i32 + n as i32) as usize;
+        for s in inner_start..=min(start, doc.len() - query.len()) {
+            let end = s + query.len();
+            if profile.similarity(&doc[s..end]) >= threshold {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+/// Check whether the document contains spans whose similarity to the query is above a threshold using rabin-karp method with rolling hash.
+///
+/// # Examples
+///
+/// ```
+/// let query = vec![1, 2, 3, 4, 5];
+/// let doc = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+/// let n = 3;
+/// let query_ngram = neardup::ngram_rolling(&query, n);
+/// let sim_threshold = 0.8;
+/// assert_eq!(neardup::has_doc_duplicate_rolling(&doc, &query, &query_ngram, sim_threshold, n), true);
+/// ```
+pub fn has_doc_duplicate_rolling<T: Hash + Eq + Copy + Into<i64>>(
+    doc: &[T],
+    query: &[T],
+    query_ngram: &HashSet<usize>,
+    threshold: f64,
+    n: usize,
+) -> bool {
+    has_doc_duplicate_rolling_verify(doc, query, query_ngram, threshold, n, false)
+}
+
+/// Check whether the document contains spans whose similarity to the query is above a threshold using rabin-karp method with rolling hash,
+/// optionally verifying the n-grams whose hash is in `query_ngram`.
+///
+/// The rolling hash collides for some n-grams, e.g. `(a, b + 1, c - 31)` and `(a, b, c)` under the default base 31,
+/// so a hash hit does not guarantee that the n-gram occurs in the query. If `verify_ngrams` is set, the n-grams of the query are
+/// kept as slices and a hit is only followed if `doc[start..start + n]` is one of them, which skips the candidate spans of collisions.
+/// With `verify_ngrams` unset, this is [`has_doc_duplicate_rolling`].
+///
+/// # Examples
+///
+/// ```
+/// let query = vec![1, 1, 40];
+/// // [1, 2, 9] has the same rolling hash as [1, 1, 40]
+/// let doc = vec![1, 2, 9];
+/// let n = 3;
+/// let query_ngram = neardup::ngram_rolling(&query, n);
+/// assert_eq!(neardup::has_doc_duplicate_rolling_verify(&doc, &query, &query_ngram, 0.2, n, false), true);
+/// assert_eq!(neardup::has_doc_duplicate_rolling_verify(&doc, &query, &query_ngram, 0.2, n, true), false);
+/// ```
+pub fn has_doc_duplicate_rolling_verify<T: Hash + Eq + Copy + Into<i64>>(
+    doc: &[T],
+    query: &[T],
+    query_ngram: &HashSet<usize>,
+    threshold: f64,
+    n: usize,
+    verify_ngrams: bool,
+) -> bool {
+    has_doc_duplicate_rolling_profile(
+        doc,
+        &QueryProfile::new(query),
+        query_ngram,
+        threshold,
+        n,
+        verify_ngrams,
+    )
+}
+
+/// [`has_doc_duplicate_rolling_verify`] with the frequency vector of the query computed by the caller, to reuse it across documents.
+fn has_doc_duplicate_rolling_profile<T: Hash + Eq + Copy + Into<i64>>(
+    doc: &[T],
+    profile: &QueryProfile<T>,
+    query_ngram: &HashSet<usize>,
+    threshold: f64,
+    n: usize,
+    verify_ngrams: bool,
+) -> bool {
+    let query = profile.query();
+    if n == 0 || query.len() > doc.len() || n > doc.len() {
+        return false;
+    }
+    let query_slices = verify_ngrams.then(|| query.windows(n).collect::<HashSet<&[T]>>());
+    let mut rollinghash = RollingHash::new();
+    for c in doc[..n].iter().map(|v| (*v).into() as u64) {
+        rollinghash.append(c);
+    }
+    for start in 0..=doc.len() - n {
+        let is_in_query_ngram = query_ngram.contains(&(rollinghash.hash as usize))
+            && query_slices
+                .as_ref()
+                .is_none_or(|slices| slices.contains(&doc[start..start + n]));
+        if is_in_query_ngram {
+            let inner_start = max(0, start as i32 - query.len() as i32 + n as i32) as usize;
+            for s in inner_start..=min(start, doc.len() - query.len()) {
+                let end = s + query.len();
+                let sim = profile.similarity(&doc[s..end]);
+                if sim >= threshold {
+                    return true;
+                }
+            }
+        }
+        // update hash_value unless doc[start..start + n] is the last n-gram
+        if start + n < doc.len() {
+            rollinghash.slide(doc[start].into() as u64, doc[start + n].into() as u64);
+        }
+    }
+    false
+}
+
+/// Check whether the document contains spans whose similarity to the query is above a threshold using rabin-karp method with rolling hash, counting the work done.
+///
+/// Scans the same spans as [`has_doc_duplicate_rolling`] and stops at the first match.
+///
+/// # Examples
+/// ```
+/// let query = vec![1, 2, 3, 4, 5];
+/// let doc = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+/// let n = 3;
+/// let query_ngram = neardup::ngram_rolling(&query, n);
+/// let stats = neardup::has_doc_duplicate_rolling_stats(&doc, &query, &query_ngram, 0.8, n);
+/// assert_eq!(stats, neardup::MatchStats { ngram_hits: 1, jaccard_calls: 1, matched: true });
+/// ```
+pub fn has_doc_duplicate_rolling_stats<T: Hash + Eq + Copy + Into<i64>>(
+    doc: &[T],
+    query: &[T],
+    query_ngram: &HashSet<usize>,
+    threshold: f64,
+    n: usize,
+) -> MatchStats {
+    let mut stats = MatchStats::default();
+    if n == 0 || query.len() > doc.len() || n > doc.len() {
+        return stats;
+    }
+    let profile = QueryProfile::new(query);
+    let mut rollinghash = RollingHash::new();
+    for c in doc[..n].iter().map(|v| (*v).into() as u64) {
+        rollinghash.append(c);
+    }
+    for start in 0..=doc.len() - n {
+        if query_ngram.contains(&(rollinghash.hash as usize)) {
+            stats.ngram_hits += 1;
+            let inner_start = max(0, start as i32 - query.len() as i32 + n as i32) as usize;
+            for s in inner_start..=min(start, doc.len() - query.len()) {
+                stats.jaccard_calls += 1;
+                if profile.similarity(&doc[s..s + query.len()]) >= threshold {
+                    stats.matched = true;
+                    return stats;
+                }
+            }
+        }
+        // update hash_value unless doc[start..start + n] is the last n-gram
+        if start + n < doc.len() {
+            rollinghash.slide(doc[start].into() as u64, doc[start + n].into() as u64);
+        }
+    }
+    stats
+}
+
+/// Check whether the document contains spans whose similarity to the query is above a threshold using rabin-karp method with rolling hash, validating the inputs first.
+///
+/// # Errors
+/// Returns [`NeardupError`] if the query is empty, `n == 0`, `n > query.len()`, or `query_ngram` was built with an n-gram size other than `n`.
+///
+/// # Examples
+/// ```
+/// let query = vec![1, 2, 3, 4, 5];
+/// let doc = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+/// let query_ngram = neardup::NgramSet::rolling(&query, 3);
+/// assert_eq!(neardup::try_has_doc_duplicate_rolling(&doc, &query, &query_ngram, 0.8, 3), Ok(true));
+/// assert_eq!(
+///     neardup::try_has_doc_duplicate_rolling(&doc, &query, &query_ngram, 0.8, 2),
+///     Err(neardup::NeardupError::NgramSizeMismatch { expected: 3, actual: 2 })
+/// );
+/// ```
+pub fn try_has_doc_duplicate_rolling<T: Hash + Eq + Copy + Into<i64>>(
+    doc: &[T],
+    query: &[T],
+    query_ngram: &NgramSet,
+    threshold: f64,
+    n: usize,
+) -> Result<bool, NeardupError> {
+    validate_query(query, n)?;
+    if query_ngram.n() != n {
+        return Err(NeardupError::NgramSizeMismatch {
+            expected: query_ngram.n(),
+            actual: n,
+        });
+    }
+    Ok(has_doc_duplicate_rolling(
+        doc,
+        query,
+        query_ngram.hashes(),
+        threshold,
+        n,
+    ))
+}
+
+/// Check whether a byte document contains spans whose similarity to the query bytes is above a threshold using rabin-karp method with rolling hash.
+///
+/// `query_ngram` must be computed with [`ngram_bytes`]. The similarity is the weighted jaccard over byte frequencies.
+///
+/// # Examples
+///
+/// ```
+/// let doc = b"the quick brown fox jumps over the lazy dog";
+/// let query = b"brown fox jumps";
+/// let n = 4;
+/// let query_ngram = neardup::ngram_bytes(query, n);
+/// assert_eq!(neardup::has_doc_duplicate_bytes(doc, query, &query_ngram, 1.0, n), true);
+/// ```
+pub fn has_doc_duplicate_bytes(
+    doc: &[u8],
+    query: &[u8],
+    query_ngram: &HashSet<usize>,
+    threshold: f64,
+    n: usize,
+) -> bool {
+    has_doc_duplicate_rolling(doc, query, query_ngram, threshold, n)
+}
+
+/// Number of start positions scanned by one task of [`has_doc_duplicate_par`].
+#[cfg(feature = "parallel")]
+const PAR_CHUNK_SIZE: usize = 1 << 14;
+
+/// Check whether the document contains spans whose similarity to the query is above a threshold using rabin-karp method with rolling hash, scanning chunks of the document in parallel.
+///
+/// Returns the same result as [`has_doc_duplicate_rolling`]; `query_ngram` must be computed with [`ngram_rolling`].
+/// The start positions are split into chunks scanned by rayon threads, each with its own rolling hash, and all threads stop once any of them finds a match.
+/// Requires the `parallel` feature.
+///
+/// # Examples
+///
+/// ```
+/// let query = vec![1, 2, 3, 4, 5];
+/// let doc = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+/// let n = 3;
+/// let query_ngram = neardup::ngram_rolling(&query, n);
+/// let sim_threshold = 0.8;
+/// assert_eq!(neardup::has_doc_duplicate_par(&doc, &query, &query_ngram, sim_threshold, n), true);
+/// ```
+#[cfg(feature = "parallel")]
+pub fn has_doc_duplicate_par<T: Hash + Eq + Copy + Into<i64> + Sync>(
+    doc: &[T],
+    query: &[T],
+    query_ngram: &HashSet<usize>,
+    threshold: f64,
+    n: usize,
+) -> bool {
+    if n == 0 || query.len() > doc.len() || n > doc.len() {
+        return false;
+    }
+    let profile = QueryProfile::new(query);
+    let num_starts = doc.len() - n + 1;
+    let found = AtomicBool::new(false);
+    (0..num_starts.div_ceil(PAR_CHUNK_SIZE))
+        .into_par_iter()
+        .any(|chunk| {
+            let chunk_start = chunk * PAR_CHUNK_SIZE;
+            let chunk_end = min(chunk_start + PAR_CHUNK_SIZE, num_starts);
+            let mut rollinghash = RollingHash::new();
+            for c in doc[chunk_start..chunk_start + n]
+                .iter()
+                .map(|v| (*v).into() as u64)
+            {
+                rollinghash.append(c);
+            }
+            for start in chunk_start..chunk_end {
+                if found.load(Ordering::Relaxed) {
+                    return false;
+                }
+                if query_ngram.contains(&(rollinghash.hash as usize)) {
+                    let inner_start = max(0, start as i32 - query.len() as i32 + n as i32) as usize;
+                    for s in inner_start..=min(start, doc.len() - query.len()) {
+                        let end = s + query.len();
+                        if profile.similarity(&doc[s..end]) >= threshold {
+                            found.store(true, Ordering::Relaxed);
+                            return true;
+                        }
+                    }
+                }
+                if start + 1 < chunk_end {
+                    // update hash_value
+                    rollinghash.slide(doc[start].into() as u64, doc[start + n].into() as u64);
+                }
+            }
+            false
+        })
+}
+
+/// Check whether the document contains spans whose similarity to the query is above a threshold using rabin-karp method with double rolling hash.
+///
+/// `query_ngram` must be computed with [`ngram_rolling_double`].
+///
+/// # Examples
+///
+/// ```
+/// let query = vec![1, 2, 3, 4, 5];
+/// let doc = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+/// let n = 3;
+/// let query_ngram = neardup::ngram_rolling_double(&query, n);
+/// let sim_threshold = 0.8;
+/// assert_eq!(neardup::has_doc_duplicate_rolling_double(&doc, &query, &query_ngram, sim_threshold, n), true);
+/// ```
+pub fn has_doc_duplicate_rolling_double<T: Hash + Eq + Copy + Into<i64>>(
+    doc: &[T],
+    query: &[T],
+    query_ngram: &HashSet<(usize, usize)>,
+    threshold: f64,
+    n: usize,
+) -> bool {
+    has_doc_duplicate_rolling_double_stats(doc, query, query_ngram, threshold, n).matched
+}
+
+/// Check whether the document contains spans whose similarity to the query is above a threshold using rabin-karp method with double rolling hash, counting the work done.
+///
+/// Scans the same spans as [`has_doc_duplicate_rolling_double`]. Comparing `jaccard_calls` with [`has_doc_duplicate_rolling_stats`]
+/// tells how many candidate spans single-hash collisions add.
+///
+/// # Examples
+/// ```
+/// let query = vec![1, 2, 3, 4, 5];
+/// let doc = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+/// let n = 3;
+/// let query_ngram = neardup::ngram_rolling_double(&query, n);
+/// let stats = neardup::has_doc_duplicate_rolling_double_stats(&doc, &query, &query_ngram, 0.8, n);
+/// assert_eq!(stats, neardup::MatchStats { ngram_hits: 1, jaccard_calls: 1, matched: true });
+/// ```
+pub fn has_doc_duplicate_rolling_double_stats<T: Hash + Eq + Copy + Into<i64>>(
+    doc: &[T],
+    query: &[T],
+    query_ngram: &HashSet<(usize, usize)>,
+    threshold: f64,
+    n: usize,
+) -> MatchStats {
+    let mut stats = MatchStats::default();
+    if n == 0 || query.len() > doc.len() || n > doc.len() {
+        return stats;
+    }
+    let profile = QueryProfile::new(query);
+    let mut rollinghash = RollingHash::new();
+    let mut second_rollinghash = RollingHash::with_params(DOUBLE_HASH_BASE, DOUBLE_HASH_MODULO);
+    for c in doc[..n].iter().map(|v| (*v).into() as u64) {
+        rollinghash.append(c);
+        second_rollinghash.append(c);
+    }
+    for start in 0..=doc.len() - n {
+        let is_in_query_ngram =
+            query_ngram.contains(&(rollinghash.hash as usize, second_rollinghash.hash as usize));
+        if is_in_query_ngram {
+            stats.ngram_hits += 1;
+            let inner_start = max(0, start as i32 - query.len() as i32 + n as i32) as usize;
+            for s in inner_start..=min(start, doc.len() - query.len()) {
+                let end = s + query.len();
+                stats.jaccard_calls += 1;
+                if profile.similarity(&doc[s..end]) >= threshold {
+                    stats.matched = true;
+                    return stats;
+                }
+            }
+        }
+        // update hash_value unless doc[start..start + n] is the last n-gram
+        if start + n < doc.len() {
+            let (old_char, new_char) = (doc[start].into() as u64, doc[start + n].into() as u64);
+            rollinghash.slide(old_char, new_char);
+            second_rollinghash.slide(old_char, new_char);
+        }
+    }
+    stats
+}
+
+/// Check whether a stream of tokens contains spans whose similarity to the query is above a threshold using rabin-karp method with rolling hash.
+///
+/// Only the last `query.len()` tokens of the stream are kept in memory, so the document never needs to be materialized.
+/// `query_ngram` must be computed with [`ngram_rolling`], and `n` must satisfy `0 < n <= query.len()`, otherwise `false` is returned.
+/// A span is compared only if it contains an n-gram of the query, as in [`has_doc_duplicate_rolling`].
+///
+/// # Examples
+///
+/// ```
+/// let query = vec![1, 2, 3, 4, 5];
+/// let n = 3;
+/// let query_ngram = neardup::ngram_rolling(&query, n);
+/// let sim_threshold = 0.8;
+/// assert_eq!(neardup::has_doc_duplicate_streaming(1..=10, &query, &query_ngram, sim_threshold, n), true);
+/// ```
+pub fn has_doc_duplicate_streaming<T, R>(
+    doc: R,
+    query: &[T],
+    query_ngram: &HashSet<usize>,
+    threshold: f64,
+    n: usize,
+) -> bool
+where
+    T: Hash + Eq + Copy + Into<i64>,
+    R: Iterator<Item = T>,
+{
+    if n == 0 || n > query.len() {
+        return false;
+    }
+    let mut buffer: VecDeque<T> = VecDeque::with_capacity(query.len());
+    let mut window = SlidingFrequency::new(query.iter().copied());
+    let mut rollinghash = RollingHash::new();
+    // start of the latest n-gram found in the query
+    let mut last_hit = None;
+    for (i, token) in doc.enumerate() {
+        // update hash_value
+        if i < n {
+            rollinghash.append(token.into() as u64);
+        } else {
+            let old_char = buffer[buffer.len() - n];
+            rollinghash.slide(old_char.into() as u64, token.into() as u64);
+        }
+        if buffer.len() == query.len() {
+            window.remove(buffer.pop_front().unwrap());
+        }
+        buffer.push_back(token);
+        window.add(token);
+
+        if i + 1 >= n && query_ngram.contains(&(rollinghash.hash as usize)) {
+            last_hit = Some(i + 1 - n);
+        }
+        if buffer.len() < query.len() {
+            continue;
+        }
+        let s = i + 1 - query.len();
+        if last_hit.is_some_and(|hit| hit >= s) && window.similarity() >= threshold {
+            return true;
+        }
+    }
+    false
+}
+
+/// Group the documents of a corpus that are near-duplicates of each other.
+///
+/// Two documents are near-duplicates if the [`weighted_jaccard`] similarity of the whole documents is at least `threshold`.
+/// Only pairs sharing an n-gram are compared, and groups are the connected components of the near-duplicate pairs,
+/// so two documents of a group may be linked only through a third one.
+/// Returns the groups of at least two documents, each sorted, ordered by their first document.
+///
+/// # Examples
+/// ```
+/// let docs = vec![vec![1, 2, 3, 4, 5], vec![6, 7, 8, 9, 10], vec![1, 2, 3, 4, 0]];
+/// assert_eq!(neardup::cluster_near_duplicates(&docs, 0.6, 2), vec![vec![0, 2]]);
+/// ```
+pub fn cluster_near_duplicates(docs: &[Vec<i32>], threshold: f64, n: usize) -> Vec<Vec<usize>> {
+    // documents containing each n-gram
+    let mut postings: HashMap<usize, Vec<usize>> = HashMap::new();
+    for (i, doc) in docs.iter().enumerate() {
+        for hash in ngram(doc, n) {
+            postings.entry(hash).or_default().push(i);
+        }
+    }
+    let mut candidates = HashSet::default();
+    for doc_ids in postings.values() {
+        for (k, &i) in doc_ids.iter().enumerate() {
+            for &j in &doc_ids[k + 1..] {
+                candidates.insert((i, j));
+            }
+        }
+    }
+    // union-find forest of the documents
+    let mut parent = (0..docs.len()).collect::<Vec<usize>>();
+    for (i, j) in candidates {
+        if weighted_jaccard(&docs[i], &docs[j]) >= threshold {
+            let (root_i, root_j) = (find_root(&mut parent, i), find_root(&mut parent, j));
+            parent[max(root_i, root_j)] = min(root_i, root_j);
+        }
+    }
+    let mut groups: HashMap<usize, Vec<usize>> = HashMap::new();
+    for i in 0..docs.len() {
+        groups.entry(find_root(&mut parent, i)).or_default().push(i);
+    }
+    let mut groups = groups
+        .into_values()
+        .filter(|group| group.len() > 1)
+        .collect::<Vec<Vec<usize>>>();
+    groups.sort();
+    groups
+}
+
+/// Find the root of a node in a union-find forest, halving the path on the way.
+fn find_root(parent: &mut [usize], mut i: usize) -> usize {
+    while parent[i] != i {
+        parent[i] = parent[parent[i]];
+        i = parent[i];
+    }
+    i
+}
+
+/// Hash function used to compute the n-grams of the query and the document.
+///
+/// [`HashKind::Fx`] is faster for small `n`, and [`HashKind::Rolling`] is faster for large `n` as it computes the hash of the next n-gram in O(1).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HashKind {
+    /// fxhash of each n-gram, see [`ngram`].
+    #[default]
+    Fx,
+    /// Rolling hash, see [`ngram_rolling`].
+    Rolling,
+}
+
+/// Compute n-grams of a text with the given hash function.
+///
+/// Use the same `kind` for [`has_doc_duplicate_with_kind`].
+///
+/// # Examples
+///
+/// ```
+/// let text = vec![1, 2, 3, 4, 5];
+/// assert_eq!(neardup::ngram_with_kind(&text, 2, neardup::HashKind::Fx), neardup::ngram(&text, 2));
+/// assert_eq!(neardup::ngram_with_kind(&text, 2, neardup::HashKind::Rolling), neardup::ngram_rolling(&text, 2));
+/// ```
+pub fn ngram_with_kind<T: Hash + Copy + Into<i64>>(
+    text: &[T],
+    n: usize,
+    kind: HashKind,
+) -> HashSet<usize> {
+    match kind {
+        HashKind::Fx => ngram(text, n),
+        HashKind::Rolling => ngram_rolling(text, n),
+    }
+}
+
+/// Check whether the document contains spans whose similarity to the query is above a threshold using rabin-karp method with the given hash function.
+///
+/// Calls [`has_doc_duplicate`] or [`has_doc_duplicate_rolling`]; `query_ngram` must be computed with [`ngram_with_kind`] and the same `kind`.
+///
+/// # Examples
+/// ```
+/// let query = vec![1, 2, 3, 4, 5];
+/// let doc = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+/// let n = 3;
+/// let kind = neardup::HashKind::Rolling;
+/// let query_ngram = neardup::ngram_with_kind(&query, n, kind);
+/// assert_eq!(neardup::has_doc_duplicate_with_kind(&doc, &query, &query_ngram, 0.8, n, kind), true);
+/// ```
+pub fn has_doc_duplicate_with_kind<T: Hash + Eq + Copy + Into<i64>>(
+    doc: &[T],
+    query: &[T],
     query_ngram: &HashSet<usize>,
     threshold: f64,
     n: usize,
+    kind: HashKind,
 ) -> bool {
-    let mut rollinghash = RollingHash::new();
-    for c in doc[..n].iter().map(|v| *v as u64) {
-        rollinghash.append(c);
+    match kind {
+        HashKind::Fx => has_doc_duplicate(doc, query, query_ngram, threshold, n),
+        HashKind::Rolling => has_doc_duplicate_rolling(doc, query, query_ngram, threshold, n),
+    }
+}
+
+/// Smallest `n` for which [`choose_hash_kind`] picks [`HashKind::Rolling`].
+///
+/// In the `scan_sweep` benchmark, which steps n by 5 from 20 to 50, fxhash is faster up to n = 40 and rolling hash from n = 45,
+/// as the cost of fxhash grows with `n`.
+pub const HASH_KIND_CROSSOVER: usize = 45;
+
+/// Choose the faster hash function for n-grams of size `n`.
+///
+/// Returns [`HashKind::Fx`] below [`HASH_KIND_CROSSOVER`] and [`HashKind::Rolling`] from it on.
+///
+/// # Examples
+///
+/// ```
+/// assert_eq!(neardup::choose_hash_kind(10), neardup::HashKind::Fx);
+/// assert_eq!(neardup::choose_hash_kind(50), neardup::HashKind::Rolling);
+/// ```
+pub fn choose_hash_kind(n: usize) -> HashKind {
+    if n < HASH_KIND_CROSSOVER {
+        HashKind::Fx
+    } else {
+        HashKind::Rolling
+    }
+}
+
+/// Compute n-grams of a text with the hash function chosen by [`choose_hash_kind`].
+///
+/// Use the result as the `query_ngram` of [`has_doc_duplicate_auto`] with the same `n`.
+///
+/// # Examples
+///
+/// ```
+/// let text = vec![1, 2, 3, 4, 5];
+/// assert_eq!(neardup::ngram_auto(&text, 2), neardup::ngram(&text, 2));
+/// ```
+pub fn ngram_auto<T: Hash + Copy + Into<i64>>(text: &[T], n: usize) -> HashSet<usize> {
+    ngram_with_kind(text, n, choose_hash_kind(n))
+}
+
+/// Check whether the document contains spans whose similarity to the query is above a threshold using rabin-karp method with the hash function chosen by [`choose_hash_kind`].
+///
+/// `query_ngram` must be computed with [`ngram_auto`] and the same `n`.
+///
+/// # Examples
+/// ```
+/// let query = vec![1, 2, 3, 4, 5];
+/// let doc = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+/// let n = 3;
+/// let query_ngram = neardup::ngram_auto(&query, n);
+/// assert_eq!(neardup::has_doc_duplicate_auto(&doc, &query, &query_ngram, 0.8, n), true);
+/// ```
+pub fn has_doc_duplicate_auto<T: Hash + Eq + Copy + Into<i64>>(
+    doc: &[T],
+    query: &[T],
+    query_ngram: &HashSet<usize>,
+    threshold: f64,
+    n: usize,
+) -> bool {
+    has_doc_duplicate_with_kind(doc, query, query_ngram, threshold, n, choose_hash_kind(n))
+}
+
+/// A custom hash function of n-grams, for bucketing n-grams other than with fxhash or rolling hash.
+///
+/// Two n-grams with the same hash are treated as the same n-gram by the prefilter, so a coarser hash only adds candidate spans,
+/// whose similarity is still computed exactly. Use it with [`ngram_with_hasher`] and [`has_doc_duplicate_with_hasher`].
+pub trait NgramHasher {
+    /// Hash an n-gram.
+    fn hash_window(&self, window: &[i32]) -> usize;
+}
+
+/// fxhash of each n-gram, the same hash as [`ngram`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FxNgramHasher;
+
+impl NgramHasher for FxNgramHasher {
+    fn hash_window(&self, window: &[i32]) -> usize {
+        fxhash::hash(window)
+    }
+}
+
+/// Rolling hash of each n-gram, the same hash as [`ngram_rolling`].
+///
+/// Each n-gram is hashed from scratch in O(n), so prefer [`ngram_rolling`] and [`has_doc_duplicate_rolling`] unless a [`NgramHasher`] is needed.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RollingNgramHasher;
+
+impl NgramHasher for RollingNgramHasher {
+    fn hash_window(&self, window: &[i32]) -> usize {
+        let mut rollinghash = RollingHash::new();
+        for &c in window {
+            rollinghash.append(i64::from(c) as u64);
+        }
+        rollinghash.hash as usize
+    }
+}
+
+/// Compute n-grams of a text with a custom hash function.
+///
+/// Use the same `hasher` for [`has_doc_duplicate_with_hasher`].
+///
+/// # Examples
+///
+/// ```
+/// let text = vec![1, 2, 3, 4, 5];
+/// assert_eq!(neardup::ngram_with_hasher(&text, 2, &neardup::FxNgramHasher), neardup::ngram(&text, 2));
+/// assert_eq!(neardup::ngram_with_hasher(&text, 2, &neardup::RollingNgramHasher), neardup::ngram_rolling(&text, 2));
+/// ```
+pub fn ngram_with_hasher(text: &[i32], n: usize, hasher: &dyn NgramHasher) -> HashSet<usize> {
+    if n == 0 {
+        return HashSet::default();
+    }
+    text.windows(n)
+        .map(|window| hasher.hash_window(window))
+        .collect()
+}
+
+/// Check whether the document contains spans whose similarity to the query is above a threshold using rabin-karp method with a custom hash function.
+///
+/// `query_ngram` must be computed with [`ngram_with_hasher`] and the same `hasher`.
+///
+/// # Examples
+/// ```
+/// let query = vec![1, 2, 3, 4, 5];
+/// let doc = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+/// let n = 3;
+/// let hasher = neardup::RollingNgramHasher;
+/// let query_ngram = neardup::ngram_with_hasher(&query, n, &hasher);
+/// assert_eq!(neardup::has_doc_duplicate_with_hasher(&doc, &query, &query_ngram, 0.8, n, &hasher), true);
+/// ```
+pub fn has_doc_duplicate_with_hasher(
+    doc: &[i32],
+    query: &[i32],
+    query_ngram: &HashSet<usize>,
+    threshold: f64,
+    n: usize,
+    hasher: &dyn NgramHasher,
+) -> bool {
+    if n == 0 || query.len() > doc.len() || n > doc.len() {
+        return false;
+    }
+    let profile = QueryProfile::new(query);
+    // the first span start that has not been evaluated yet
+    let mut next_s = 0;
+    for start in 0..=doc.len() - n {
+        if !query_ngram.contains(&hasher.hash_window(&doc[start..start + n])) {
+            continue;
+        }
+        let inner_start = max(0, start as i32 - query.len() as i32 + n as i32) as usize;
+        for s in max(inner_start, next_s)..=min(start, doc.len() - query.len()) {
+            if profile.similarity(&doc[s..s + query.len()]) >= threshold {
+                return true;
+            }
+        }
+        next_s = start + 1;
+    }
+    false
+}
+
+/// A query with its n-grams precomputed for matching many documents.
+///
+/// Built with [`Matcher::builder`], which guarantees the n-grams are computed with the same `n` and hash function used for matching.
+///
+/// # Examples
+///
+/// ```
+/// let matcher = neardup::Matcher::builder()
+///     .n(3)
+///     .threshold(0.8)
+///     .hash(neardup::HashKind::Fx)
+///     .build(vec![3, 4, 5, 6, 7]);
+/// assert_eq!(matcher.contains(&[1, 2, 3, 4, 5, 6, 7, 8, 9, 10]), true);
+/// assert_eq!(matcher.contains(&[1, 2, 3, 4, 9, 9, 9, 9, 9, 10]), false);
+/// ```
+pub struct Matcher {
+    profile: QueryProfile<'static, i32>,
+    query_ngram: HashSet<usize>,
+    threshold: f64,
+    n: usize,
+    hash: HashKind,
+}
+
+impl Matcher {
+    /// Create a builder with `n = 10`, `threshold = 0.6`, and [`HashKind::Fx`].
+    pub fn builder() -> MatcherBuilder {
+        MatcherBuilder::default()
+    }
+
+    /// Get the query.
+    pub fn query(&self) -> &[i32] {
+        self.profile.query()
+    }
+
+    /// Check whether the document contains spans whose similarity to the query is above the threshold.
+    ///
+    /// The frequency vector of the query is computed once by [`MatcherBuilder::build`] and reused for every document.
+    pub fn contains(&self, doc: &[i32]) -> bool {
+        match self.hash {
+            HashKind::Fx => has_doc_duplicate_profile(
+                doc,
+                &self.profile,
+                &self.query_ngram,
+                self.threshold,
+                self.n,
+            ),
+            HashKind::Rolling => has_doc_duplicate_rolling_profile(
+                doc,
+                &self.profile,
+                &self.query_ngram,
+                self.threshold,
+                self.n,
+                false,
+            ),
+        }
+    }
+
+    /// Check each document with [`Matcher::contains`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let matcher = neardup::Matcher::builder().n(3).threshold(0.8).build(vec![3, 4, 5, 6, 7]);
+    /// let docs = vec![vec![1, 2, 3, 4, 5, 6, 7], vec![9, 9, 9]];
+    /// assert_eq!(matcher.contains_batch(&docs), vec![true, false]);
+    /// ```
+    pub fn contains_batch(&self, docs: &[Vec<i32>]) -> Vec<bool> {
+        docs.iter().map(|doc| self.contains(doc)).collect()
+    }
+
+    /// Check each document with [`Matcher::contains`] in parallel.
+    ///
+    /// Returns the same results as [`Matcher::contains_batch`]. Requires the `parallel` feature.
+    #[cfg(feature = "parallel")]
+    pub fn contains_batch_par(&self, docs: &[Vec<i32>]) -> Vec<bool> {
+        docs.par_iter().map(|doc| self.contains(doc)).collect()
+    }
+}
+
+/// Lazily check each JSONL line of the reader for a match of the query, yielding `(line_index, matched)`.
+///
+/// Each line is decoded from its `token_field` field, e.g. `"token_ids"`, as it is read, so only one document is held in memory at a time.
+/// Blank lines, lines that cannot be decoded, and lines whose field is missing or not a list of token ids are yielded as not matched,
+/// and the iteration stops at the first read error.
+/// Requires the `serde` feature.
+///
+/// # Examples
+///
+/// ```
+/// let matcher = neardup::Matcher::builder().n(3).threshold(0.8).build(vec![3, 4, 5, 6, 7]);
+/// let reader = std::io::Cursor::new("{\"token_ids\": [1, 2, 3, 4, 5, 6, 7]}\n{\"token_ids\": [9, 9, 9]}\n");
+/// let matches = neardup::iter_matches(reader, &matcher, "token_ids").collect::<Vec<_>>();
+/// assert_eq!(matches, vec![(0, true), (1, false)]);
+/// ```
+#[cfg(feature = "serde")]
+pub fn iter_matches<'a, R: std::io::BufRead + 'a>(
+    reader: R,
+    matcher: &'a Matcher,
+    token_field: &'a str,
+) -> impl Iterator<Item = (usize, bool)> + 'a {
+    reader
+        .lines()
+        .map_while(Result::ok)
+        .enumerate()
+        .map(move |(line_index, line)| {
+            let matched = serde_json::from_str::<serde_json::Value>(&line)
+                .ok()
+                .and_then(|value| Vec::<i32>::deserialize(&value[token_field]).ok())
+                .is_some_and(|token_ids| matcher.contains(&token_ids));
+            (line_index, matched)
+        })
+}
+
+/// Builder of [`Matcher`].
+#[derive(Debug, Clone, Copy)]
+pub struct MatcherBuilder {
+    n: usize,
+    threshold: f64,
+    hash: HashKind,
+}
+
+impl Default for MatcherBuilder {
+    fn default() -> Self {
+        Self {
+            n: 10,
+            threshold: 0.6,
+            hash: HashKind::default(),
+        }
+    }
+}
+
+impl MatcherBuilder {
+    /// Set the n-gram size.
+    pub fn n(mut self, n: usize) -> Self {
+        self.n = n;
+        self
+    }
+
+    /// Set the similarity threshold.
+    pub fn threshold(mut self, threshold: f64) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Set the hash function of the n-grams.
+    pub fn hash(mut self, hash: HashKind) -> Self {
+        self.hash = hash;
+        self
+    }
+
+    /// Build a matcher for the query.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the query is empty, `n == 0`, or `n > query.len()`.
+    pub fn build(self, query: Vec<i32>) -> Matcher {
+        if let Err(err) = validate_query(&query, self.n) {
+            panic!("invalid query: {}", err);
+        }
+        let query_ngram = ngram_with_kind(&query, self.n, self.hash);
+        Matcher {
+            profile: QueryProfile::from_vec(query),
+            query_ngram,
+            threshold: self.threshold,
+            n: self.n,
+            hash: self.hash,
+        }
+    }
+}
+
+/// A query bundled with its n-grams, for persisting precomputed query n-gram sets.
+///
+/// With the `serde` feature, it implements `Serialize` and `Deserialize` and can be saved to and loaded from JSON files.
+///
+/// # Examples
+///
+/// ```
+/// let index = neardup::QueryIndex::build(vec![1, 2, 3, 4, 5], 3);
+/// assert_eq!(index.ngram(), &neardup::ngram(&[1, 2, 3, 4, 5], 3));
+/// let doc = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+/// assert_eq!(neardup::has_doc_duplicate(&doc, index.query(), index.ngram(), 0.8, index.n()), true);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct QueryIndex {
+    query: Vec<i32>,
+    ngram: HashSet<usize>,
+    n: usize,
+}
+
+impl QueryIndex {
+    /// Compute the n-grams of the query with [`ngram`].
+    pub fn build(query: Vec<i32>, n: usize) -> Self {
+        let ngram = ngram(&query, n);
+        Self { query, ngram, n }
+    }
+
+    /// Get the query.
+    pub fn query(&self) -> &[i32] {
+        &self.query
+    }
+
+    /// Get the n-grams of the query.
+    pub fn ngram(&self) -> &HashSet<usize> {
+        &self.ngram
+    }
+
+    /// Get the n-gram size.
+    pub fn n(&self) -> usize {
+        self.n
+    }
+
+    /// Write the index to a JSON file.
+    #[cfg(feature = "serde")]
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        let writer = std::io::BufWriter::new(std::fs::File::create(path)?);
+        serde_json::to_writer(writer, self)?;
+        Ok(())
+    }
+
+    /// Read an index written by [`QueryIndex::save`].
+    #[cfg(feature = "serde")]
+    pub fn load<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        let reader = std::io::BufReader::new(std::fs::File::open(path)?);
+        Ok(serde_json::from_reader(reader)?)
+    }
+}
+
+/// A positional n-gram index of a document, for matching many queries against the same document.
+///
+/// Maps the fxhash of each n-gram of the document to the positions where it occurs, so that
+/// [`DocIndex::contains`] jumps to the n-gram hits of a query instead of hashing every n-gram of the document.
+///
+/// # Examples
+///
+/// ```
+/// let doc = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+/// let n = 3;
+/// let index = neardup::DocIndex::new(&doc, n);
+/// let query = vec![1, 2, 3, 4, 5];
+/// let query_ngram = neardup::ngram(&query, n);
+/// assert_eq!(index.contains(&query, &query_ngram, 0.8), true);
+/// assert_eq!(index.contains(&query, &query_ngram, 0.8), neardup::has_doc_duplicate(&doc, &query, &query_ngram, 0.8, n));
+/// ```
+pub struct DocIndex<'a, T: Hash + Eq> {
+    doc: &'a [T],
+    n: usize,
+    positions: HashMap<usize, Vec<usize>>,
+}
+
+impl<'a, T: Hash + Eq> DocIndex<'a, T> {
+    /// Index the start positions of the n-grams of the document, in increasing order for each hash.
+    pub fn new(doc: &'a [T], n: usize) -> Self {
+        let mut positions: HashMap<usize, Vec<usize>> = HashMap::new();
+        for (position, hash) in ngram_iter(doc, n) {
+            positions.entry(hash).or_default().push(position);
+        }
+        Self { doc, n, positions }
+    }
+
+    /// Get the n-gram size.
+    pub fn n(&self) -> usize {
+        self.n
+    }
+
+    /// Check whether the document contains spans whose similarity to the query is above a threshold.
+    ///
+    /// Gives the same result as [`has_doc_duplicate`] with the n-gram size of the index:
+    /// the positions of the query n-grams are looked up in the index, and the candidate spans around them are compared to the query.
+    pub fn contains(&self, query: &[T], query_ngram: &HashSet<usize>, threshold: f64) -> bool {
+        let (doc, n) = (self.doc, self.n);
+        if n == 0 || query.len() > doc.len() || n > doc.len() {
+            return false;
+        }
+        let mut hits = query_ngram
+            .iter()
+            .filter_map(|hash| self.positions.get(hash))
+            .flatten()
+            .copied()
+            .collect::<Vec<usize>>();
+        if hits.is_empty() {
+            return false;
+        }
+        hits.sort_unstable();
+        let profile = QueryProfile::new(query);
+        // the first span start that has not been evaluated yet
+        let mut next_s = 0;
+        for start in hits {
+            let inner_start = max(0, start as i32 - query.len() as i32 + n as i32) as usize;
+            for s in max(inner_start, next_s)..=min(start, doc.len() - query.len()) {
+                if profile.similarity(&doc[s..s + query.len()]) >= threshold {
+                    return true;
+                }
+            }
+            next_s = start + 1;
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[allow(unused_variables)]
+    fn test_weighted_jaccard() {
+        let text1 = vec![1, 2, 3, 4, 5];
+        let text2 = vec![1, 2, 3, 4, 5];
+        assert_eq!(weighted_jaccard(&text1, &text2), 1.0);
+        let text1 = vec![1, 2, 2];
+        let text2 = vec![1, 1, 2];
+        assert_eq!(weighted_jaccard(&text1, &text2), (2.0) / 4.0);
+        let text1 = vec![1, 1, 2, 3];
+        let text2 = vec![1, 2, 2, 2];
+        assert_eq!(weighted_jaccard(&text1, &text2), (2.0) / 6.0);
+        let text1 = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+        let text2 = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+    }
+
+    #[test]
+    fn test_weighted_jaccard_empty() {
+        assert_eq!(weighted_jaccard::<i32>(&[], &[]), 1.0);
+        assert_eq!(weighted_jaccard(&[], &[1, 2]), 0.0);
+        assert_eq!(weighted_jaccard(&[1, 2], &[]), 0.0);
+        // the other weighted jaccard functions agree
+        let empty: HashMap<i32, usize> = HashMap::new();
+        assert_eq!(weighted_jaccard_from_freq(&empty, &empty), 1.0);
+        assert_eq!(
+            weighted_jaccard_from_freq(&empty, &HashMap::from([(1, 2)])),
+            0.0
+        );
+        for (text1, text2) in [(&[][..], &[][..]), (&[], &[1, 2]), (&[1, 2], &[])] {
+            let expected = weighted_jaccard(text1, text2);
+            assert_eq!(weighted_jaccard_sorted(text1, text2), expected);
+            assert_eq!(weighted_jaccard_dense(text1, text2, 3), expected);
+            let (bytes1, bytes2) = (
+                text1.iter().map(|token| *token as u8).collect::<Vec<u8>>(),
+                text2.iter().map(|token| *token as u8).collect::<Vec<u8>>(),
+            );
+            assert_eq!(weighted_jaccard_u8(&bytes1, &bytes2), expected);
+            assert_eq!(QueryProfile::new(text1).similarity(text2), expected);
+            assert!(weighted_jaccard_upper_bound(text1.len(), text2.len()) >= expected);
+        }
+    }
+
+    #[test]
+    fn test_weighted_jaccard_explain() {
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..100 {
+            let a = (0..rng.gen_range(0..50))
+                .map(|_| rng.gen_range(0..10))
+                .collect::<Vec<i32>>();
+            let b = (0..rng.gen_range(0..50))
+                .map(|_| rng.gen_range(0..10))
+                .collect::<Vec<i32>>();
+            let (similarity, overlap) = weighted_jaccard_explain(&a, &b);
+            assert_eq!(similarity, weighted_jaccard(&a, &b));
+            let intersection: usize = overlap.values().sum();
+            assert_eq!(
+                intersection,
+                intersection_frequency(&create_frequency_vector(&a), &create_frequency_vector(&b))
+            );
+            // the score is recovered from the explanation
+            if !a.is_empty() || !b.is_empty() {
+                assert_eq!(
+                    similarity,
+                    intersection as f64 / (a.len() + b.len() - intersection) as f64
+                );
+            }
+            assert!(overlap.values().all(|&frequency| frequency > 0));
+        }
+        let (similarity, overlap) = weighted_jaccard_explain::<i32>(&[], &[]);
+        assert_eq!(similarity, 1.0);
+        assert!(overlap.is_empty());
+    }
+
+    #[test]
+    fn test_weighted_jaccard_distance() {
+        assert_eq!(weighted_jaccard_distance(&[1, 2, 3], &[1, 2, 3]), 0.0);
+        assert_eq!(weighted_jaccard_distance(&[1, 2, 2], &[2, 1, 2]), 0.0);
+        assert_eq!(weighted_jaccard_distance(&[1, 2, 3], &[4, 5]), 1.0);
+        assert_eq!(weighted_jaccard_distance(&[1, 1, 2], &[1, 2, 2]), 0.5);
+        assert_eq!(weighted_jaccard_distance::<i32>(&[], &[]), 0.0);
+        assert_eq!(weighted_jaccard_distance(&[], &[1]), 1.0);
+        assert_eq!(weighted_jaccard_distance(&[1], &[]), 1.0);
+    }
+
+    #[test]
+    fn test_jaccard() {
+        assert_eq!(jaccard(&[1, 2, 3, 4, 5], &[1, 2, 3, 4, 5]), 1.0);
+        // weighted and set-based scores differ on repeated tokens
+        let text1 = vec![1, 1, 2];
+        let text2 = vec![1, 2, 2];
+        assert_eq!(jaccard(&text1, &text2), 1.0);
+        assert_eq!(weighted_jaccard(&text1, &text2), 2.0 / 4.0);
+        assert_eq!(jaccard(&[1, 2], &[3, 4]), 0.0);
+        assert_eq!(jaccard::<i32>(&[], &[]), 0.0);
+    }
+
+    #[test]
+    fn test_weighted_jaccard_upper_bound() {
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..100 {
+            let text1 = (0..rng.gen_range(0..20))
+                .map(|_| rng.gen_range(0..5))
+                .collect::<Vec<i32>>();
+            let text2 = (0..rng.gen_range(0..20))
+                .map(|_| rng.gen_range(0..5))
+                .collect::<Vec<i32>>();
+            assert!(
+                weighted_jaccard(&text1, &text2)
+                    <= weighted_jaccard_upper_bound(text1.len(), text2.len())
+            );
+        }
+        assert_eq!(weighted_jaccard_upper_bound(3, 3), 1.0);
+        assert_eq!(weighted_jaccard_upper_bound(0, 0), 1.0);
+        // skipping spans by the bound does not change the results
+        let doc = (0..1000)
+            .map(|_| rng.gen_range(0..100))
+            .collect::<Vec<i32>>();
+        for _ in 0..50 {
+            let start = rng.gen_range(0..950);
+            let query = doc[start..start + 50].to_vec();
+            let threshold = rng.gen_range(0.0..1.0);
+            let query_ngram = ngram(&query, 3);
+            assert_eq!(
+                has_doc_duplicate(&doc, &query, &query_ngram, threshold, 3),
+                has_doc_duplicate_with(&doc, &query, &query_ngram, threshold, 3, weighted_jaccard)
+            );
+        }
+    }
+
+    #[test]
+    fn test_containment() {
+        let query = vec![1, 2, 2, 3];
+        let span = vec![0, 1, 2, 2, 3, 4, 5, 6];
+        assert_eq!(containment(&query, &span), 1.0);
+        assert_eq!(weighted_jaccard(&query, &span), 4.0 / 8.0);
+        // containment is asymmetric
+        assert_eq!(containment(&span, &query), 4.0 / 8.0);
+        assert_eq!(containment(&[1, 1, 2], &[1, 2, 2]), 2.0 / 3.0);
+        assert_eq!(containment::<i32>(&[], &[1]), 0.0);
+
+        let doc = vec![9, 1, 2, 2, 0, 3, 9, 9];
+        let query_ngram = ngram(&query, 2);
+        assert!(has_doc_duplicate_containment(
+            &doc,
+            &query,
+            &query_ngram,
+            0.75,
+            2
+        ));
+        assert!(!has_doc_duplicate(&doc, &query, &query_ngram, 0.75, 2));
+    }
+
+    #[test]
+    fn test_weighted_jaccard_sorted() {
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..1000 {
+            let vocabulary_size = rng.gen_range(1..20);
+            let text1 = (0..rng.gen_range(0..60))
+                .map(|_| rng.gen_range(0..vocabulary_size))
+                .collect::<Vec<i32>>();
+            let text2 = (0..rng.gen_range(0..60))
+                .map(|_| rng.gen_range(0..vocabulary_size))
+                .collect::<Vec<i32>>();
+            assert_eq!(
+                weighted_jaccard_sorted(&text1, &text2),
+                weighted_jaccard(&text1, &text2)
+            );
+        }
+        assert_eq!(weighted_jaccard_sorted::<i32>(&[], &[]), 1.0);
+    }
+
+    #[test]
+    fn test_weighted_jaccard_dense() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut vocabulary = Vocabulary::new();
+        for _ in 0..100 {
+            let text1 = (0..rng.gen_range(0..50))
+                .map(|_| rng.gen_range(0..50254) / 1000 * 1000)
+                .collect::<Vec<i32>>();
+            let text2 = (0..rng.gen_range(0..50))
+                .map(|_| rng.gen_range(0..50254) / 1000 * 1000)
+                .collect::<Vec<i32>>();
+            let (dense1, dense2) = (vocabulary.encode(&text1), vocabulary.encode(&text2));
+            assert_eq!(
+                weighted_jaccard_dense(&dense1, &dense2, vocabulary.len()),
+                weighted_jaccard(&text1, &text2)
+            );
+        }
+        assert!(vocabulary.len() <= 51);
+        assert_eq!(vocabulary.get(0).map(|id| id < 51), Some(true));
+        assert_eq!(vocabulary.get(1), None);
+    }
+
+    #[test]
+    fn test_has_doc_duplicate_multi_n() {
+        // the query with its 2-grams sampled every 6 tokens, which the edits of the copy in the document break
+        let query = (0..12).collect::<Vec<i32>>();
+        let mut doc = vec![-1; 5];
+        doc.extend([0, -2, 2, 3, 4, 5, 6, -3, 8, 9, 10, 11]);
+        doc.extend([-1; 5]);
+        let sparse_ngram = ngram_strided(&query, 2, 6);
+        let threshold = 0.7;
+        assert!(!has_doc_duplicate(
+            &doc,
+            &query,
+            &sparse_ngram,
+            threshold,
+            2
+        ));
+        // only the 5-gram [2, 3, 4, 5, 6] catches the match
+        let query_ngrams = vec![(2, sparse_ngram.clone()), (5, ngram(&query, 5))];
+        assert!(has_doc_duplicate_multi_n(
+            &doc,
+            &query,
+            &query_ngrams,
+            threshold
+        ));
+        assert!(!has_doc_duplicate_multi_n(
+            &doc,
+            &query,
+            &query_ngrams[..1],
+            threshold
+        ));
+        assert!(!has_doc_duplicate_multi_n(&doc, &query, &query_ngrams, 0.8));
+
+        // with complete n-gram sets, the smallest n gives the candidates
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..100 {
+            let doc = (0..200).map(|_| rng.gen_range(0..10)).collect::<Vec<i32>>();
+            let query = (0..rng.gen_range(5..20))
+                .map(|_| rng.gen_range(0..10))
+                .collect::<Vec<i32>>();
+            let query_ngrams = [5, 3, 4]
+                .into_iter()
+                .map(|n| (n, ngram(&query, n)))
+                .collect::<Vec<_>>();
+            for threshold in [0.5, 0.8] {
+                assert_eq!(
+                    has_doc_duplicate_multi_n(&doc, &query, &query_ngrams, threshold),
+                    has_doc_duplicate(&doc, &query, &ngram(&query, 3), threshold, 3)
+                );
+            }
+        }
+        assert!(!has_doc_duplicate_multi_n(&doc, &query, &[], 0.1));
+        assert!(!has_doc_duplicate_multi_n(
+            &doc,
+            &query,
+            &[(0, HashSet::default())],
+            0.1
+        ));
+    }
+
+    #[test]
+    fn test_doc_index() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let doc = (0..500).map(|_| rng.gen_range(0..50)).collect::<Vec<i32>>();
+        for n in [1, 3, 5] {
+            let index = DocIndex::new(&doc, n);
+            assert_eq!(index.n(), n);
+            for _ in 0..20 {
+                let start = rng.gen_range(0..doc.len() - 30);
+                let mut query = doc[start..start + rng.gen_range(n..30)].to_vec();
+                for token in query.iter_mut() {
+                    if rng.gen_bool(0.3) {
+                        *token = rng.gen_range(0..40);
+                    }
+                }
+                let query_ngram = ngram(&query, n);
+                for threshold in [0.3, 0.6, 0.9, 1.0] {
+                    assert_eq!(
+                        index.contains(&query, &query_ngram, threshold),
+                        has_doc_duplicate(&doc, &query, &query_ngram, threshold, n)
+                    );
+                }
+            }
+        }
+        // a query longer than the document or sharing no n-gram never matches
+        let index = DocIndex::new(&doc[..10], 3);
+        assert!(!index.contains(&doc[..20], &ngram(&doc[..20], 3), 0.1));
+        assert!(!index.contains(&[-1, -2, -3], &ngram(&[-1, -2, -3], 3), 0.1));
+    }
+
+    #[test]
+    fn test_ngram_iter() {
+        let text = vec![1, 2, 3, 1, 2, 3, 4];
+        let ngrams = ngram_iter(&text, 3).collect::<Vec<(usize, usize)>>();
+        assert_eq!(ngrams.len(), text.len() - 3 + 1);
+        for (i, (position, hash)) in ngrams.iter().enumerate() {
+            assert_eq!(*position, i);
+            assert_eq!(*hash, fxhash::hash(&text[i..i + 3]));
+        }
+        // repeated n-grams are yielded at each position, but collapse in the set
+        assert_eq!(ngrams[0].1, ngrams[3].1);
+        assert_eq!(ngram(&text, 3).len(), ngrams.len() - 1);
+        assert_eq!(ngram_iter(&text, 7).count(), 1);
+        assert_eq!(ngram_iter(&text, 8).count(), 0);
+        assert_eq!(ngram_iter(&text, 0).count(), 0);
+    }
+
+    #[test]
+    fn test_weighted_jaccard_from_freq() {
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..100 {
+            let text1 = (0..rng.gen_range(0..50))
+                .map(|_| rng.gen_range(0..10))
+                .collect::<Vec<i32>>();
+            let text2 = (0..rng.gen_range(0..50))
+                .map(|_| rng.gen_range(0..10))
+                .collect::<Vec<i32>>();
+            // frequency vectors owning their keys, as built elsewhere
+            let owned = |text: &[i32]| {
+                create_frequency_vector(text)
+                    .into_iter()
+                    .map(|(token, frequency)| (*token, frequency))
+                    .collect::<HashMap<i32, usize>>()
+            };
+            assert_eq!(
+                weighted_jaccard_from_freq(&owned(&text1), &owned(&text2)),
+                weighted_jaccard(&text1, &text2)
+            );
+        }
+    }
+
+    #[test]
+    fn test_weighted_jaccard_u8() {
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..100 {
+            let text1 = (0..rng.gen_range(0..300))
+                .map(|_| rng.gen())
+                .collect::<Vec<u8>>();
+            let text2 = (0..rng.gen_range(0..300))
+                .map(|_| rng.gen_range(0..8))
+                .collect::<Vec<u8>>();
+            assert_eq!(
+                weighted_jaccard_u8(&text1, &text2),
+                weighted_jaccard(&text1, &text2)
+            );
+            assert_eq!(
+                weighted_jaccard_u8(&text2, &text2[..text2.len() / 2]),
+                weighted_jaccard(&text2, &text2[..text2.len() / 2])
+            );
+        }
+        assert_eq!(weighted_jaccard_u8(b"", b""), 1.0);
+        assert_eq!(weighted_jaccard_u8(b"abc", b""), 0.0);
+    }
+
+    #[test]
+    fn test_normalized_levenshtein() {
+        assert_eq!(normalized_levenshtein::<i32>(&[], &[]), 1.0);
+        assert_eq!(normalized_levenshtein(&[1, 2, 3], &[]), 0.0);
+        // one insertion
+        assert_eq!(normalized_levenshtein(&[1, 2, 3], &[1, 2, 9, 3]), 0.75);
+        // one deletion and one substitution
+        assert_eq!(normalized_levenshtein(&[1, 2, 3, 4, 5], &[2, 3, 4, 0]), 0.6);
+        assert_eq!(
+            normalized_levenshtein(&[1, 2, 3, 4], &[2, 3, 4, 5]),
+            normalized_levenshtein(&[2, 3, 4, 5], &[1, 2, 3, 4])
+        );
+    }
+
+    #[test]
+    fn test_has_doc_duplicate_circular() {
+        let doc = (0..20).collect::<Vec<i32>>();
+        let n = 3;
+        // the only matching span straddles the end-start boundary
+        let query = [&doc[16..], &doc[..4]].concat();
+        let query_ngram = ngram(&query, n);
+        assert!(!has_doc_duplicate(&doc, &query, &query_ngram, 0.9, n));
+        assert!(has_doc_duplicate_circular(
+            &doc,
+            &query,
+            &query_ngram,
+            0.9,
+            n
+        ));
+        // spans inside the document still match
+        let query = doc[5..10].to_vec();
+        let query_ngram = ngram(&query, n);
+        assert!(has_doc_duplicate_circular(
+            &doc,
+            &query,
+            &query_ngram,
+            1.0,
+            n
+        ));
+        // a query longer than the document cannot wrap around twice
+        let query = [doc.clone(), doc[..5].to_vec()].concat();
+        let query_ngram = ngram(&query, n);
+        assert!(!has_doc_duplicate_circular(
+            &doc,
+            &query,
+            &query_ngram,
+            0.9,
+            n
+        ));
+        assert!(!has_doc_duplicate_circular(
+            &[],
+            &query,
+            &query_ngram,
+            0.9,
+            n
+        ));
+    }
+
+    #[test]
+    fn test_has_doc_duplicate_edit() {
+        use rand::seq::SliceRandom;
+        let query = (0..10).collect::<Vec<i32>>();
+        let mut shuffled = query.clone();
+        shuffled.shuffle(&mut StdRng::seed_from_u64(0));
+        let doc = [vec![100; 5], shuffled, vec![100; 5]].concat();
+        let n = 1;
+        let query_ngram = ngram(&query, n);
+        assert!(has_doc_duplicate(&doc, &query, &query_ngram, 1.0, n));
+        assert!(!has_doc_duplicate_edit(&doc, &query, &query_ngram, 0.8, n));
+
+        // a copy with one token changed still matches
+        let mut copied = query.clone();
+        copied[4] = 100;
+        let doc = [vec![100; 5], copied, vec![100; 5]].concat();
+        assert!(has_doc_duplicate_edit(&doc, &query, &query_ngram, 0.9, n));
+        assert!(!has_doc_duplicate_edit(&doc, &query, &query_ngram, 0.95, n));
+    }
+
+    #[test]
+    fn test_dice_and_overlap_coefficient() {
+        // X = {1, 1, 2, 3}, Y = {1, 2, 2, 2}, |X∩Y| = 2
+        let text1 = vec![1, 1, 2, 3];
+        let text2 = vec![1, 2, 2, 2];
+        assert_eq!(dice_coefficient(&text1, &text2), 4.0 / 8.0);
+        assert_eq!(overlap_coefficient(&text1, &text2), 2.0 / 4.0);
+        // X = {1, 2}, Y = {1, 2, 2, 5, 6}, |X∩Y| = 2
+        let text1 = vec![1, 2];
+        let text2 = vec![1, 2, 2, 5, 6];
+        assert_eq!(dice_coefficient(&text1, &text2), 4.0 / 7.0);
+        assert_eq!(overlap_coefficient(&text1, &text2), 1.0);
+        assert_eq!(dice_coefficient(&[1, 2], &[3]), 0.0);
+        assert_eq!(overlap_coefficient::<i32>(&[], &[1]), 0.0);
+    }
+
+    #[test]
+    fn test_has_doc_duplicate_with() {
+        let query = vec![1, 2, 9, 9, 9];
+        let doc = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+        let n = 2;
+        let query_ngram = ngram(&query, n);
+        // the best span shares 2 of 5 tokens with the query
+        assert!(has_doc_duplicate_with(
+            &doc,
+            &query,
+            &query_ngram,
+            0.4,
+            n,
+            overlap_coefficient
+        ));
+        assert!(!has_doc_duplicate_with(
+            &doc,
+            &query,
+            &query_ngram,
+            0.4,
+            n,
+            weighted_jaccard
+        ));
+    }
+
+    #[test]
+    fn test_has_doc_duplicate_with_closure() {
+        let query = vec![1, 2, 3, 4, 5];
+        let doc = vec![9, 9, 9, 9, 9, 1, 2, 3, 9, 9, 9, 9];
+        let n = 3;
+        let query_ngram = ngram(&query, n);
+        // the candidate loop is reached only through an n-gram hit
+        assert!(has_doc_duplicate_with(
+            &doc,
+            &query,
+            &query_ngram,
+            1.0,
+            n,
+            |_: &[i32], _: &[i32]| 1.0
+        ));
+        let doc = vec![9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9];
+        assert!(!has_doc_duplicate_with(
+            &doc,
+            &query,
+            &query_ngram,
+            1.0,
+            n,
+            |_: &[i32], _: &[i32]| 1.0
+        ));
+    }
+
+    #[test]
+    fn test_query_profile() {
+        let query = vec![1, 1, 2, 3];
+        let profile = QueryProfile::new(&query);
+        assert_eq!(profile.query(), &query[..]);
+        for span in [vec![1, 2, 2, 2], vec![1, 1, 2, 3], vec![4, 5], vec![]] {
+            assert_eq!(profile.similarity(&span), weighted_jaccard(&query, &span));
+        }
+    }
+
+    #[test]
+    fn test_ngram() {
+        let text = vec![1, 2, 3, 4, 5];
+        let mut ngrams = HashSet::default();
+        ngrams.insert(fxhash::hash(&vec![1, 2]));
+        ngrams.insert(fxhash::hash(&vec![2, 3]));
+        ngrams.insert(fxhash::hash(&vec![3, 4]));
+        ngrams.insert(fxhash::hash(&vec![4, 5]));
+        assert_eq!(ngram(&text, 2), ngrams);
+    }
+
+    #[test]
+    fn test_query_contain() {
+        let query = vec![1, 2, 3, 4, 5];
+        let doc = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+
+        let query_ngram = ngram(&query, 3);
+        let threshold = 0.8;
+        let n = 3;
+        assert!(has_doc_duplicate(&doc, &query, &query_ngram, threshold, n));
+    }
+    #[test]
+    fn test_ngram_rolling() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let text = (0..500)
+            .map(|_| rng.gen_range(0..50254))
+            .collect::<Vec<i32>>();
+        for n in [1, 2, 10, 100, 500] {
+            // hash every window from scratch
+            let expected = text
+                .windows(n)
+                .map(|window| {
+                    let window = window.iter().map(|v| *v as u64).collect::<Vec<u64>>();
+                    RollingHash::from_slice(&window).get_hash() as usize
+                })
+                .collect::<HashSet<usize>>();
+            assert_eq!(ngram_rolling(&text, n), expected);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "parallel")]
+    fn test_ngram_par() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let text = (0..100_000)
+            .map(|_| rng.gen_range(0..1000))
+            .collect::<Vec<i32>>();
+        for n in [1, 10, 100] {
+            assert_eq!(ngram_par(&text, n), ngram(&text, n));
+        }
+        // a chunk boundary falls inside the only n-gram
+        assert_eq!(
+            ngram_par(&text[..PAR_CHUNK_SIZE + 5], PAR_CHUNK_SIZE + 5),
+            ngram(&text[..PAR_CHUNK_SIZE + 5], PAR_CHUNK_SIZE + 5)
+        );
+        assert!(ngram_par(&text[..5], 10).is_empty());
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn test_ngram_batch_par() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let queries = (0..1000)
+            .map(|_| {
+                (0..rng.gen_range(0..100))
+                    .map(|_| rng.gen_range(0..1000))
+                    .collect::<Vec<i32>>()
+            })
+            .collect::<Vec<_>>();
+        for n in [1, 10] {
+            let serial = queries
+                .iter()
+                .map(|query| ngram(query, n))
+                .collect::<Vec<_>>();
+            assert_eq!(ngram_batch_par(&queries, n), serial);
+        }
+        assert!(ngram_batch_par::<i32>(&[], 10).is_empty());
+    }
+
+    #[test]
+    fn test_ngram_strided() {
+        let text = (0..20).collect::<Vec<i32>>();
+        let n = 3;
+        assert_eq!(ngram_strided(&text, n, 1), ngram(&text, n));
+        assert_eq!(ngram_strided(&text, n, 1).len(), 18);
+        // starts 0, 2, ..., 16
+        assert_eq!(ngram_strided(&text, n, 2).len(), 9);
+        assert!(ngram_strided(&text, n, 2).is_subset(&ngram(&text, n)));
+        assert!(ngram_strided(&text[..2], n, 2).is_empty());
+
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..50 {
+            let doc = (0..100).map(|_| rng.gen_range(0..10)).collect::<Vec<i32>>();
+            let query = (0..10).map(|_| rng.gen_range(0..10)).collect::<Vec<i32>>();
+            let query_ngram = ngram(&query, n);
+            assert_eq!(
+                has_doc_duplicate_strided(&doc, &query, &query_ngram, 0.6, n, 1),
+                has_doc_duplicate(&doc, &query, &query_ngram, 0.6, n)
+            );
+        }
+
+        let query = (100..110).collect::<Vec<i32>>();
+        let query_ngram = ngram_strided(&query, n, 2);
+        for offset in 0..6 {
+            let doc = [vec![0; offset], query.clone(), vec![0; 5]].concat();
+            assert_eq!(
+                has_doc_duplicate_strided(&doc, &query, &query_ngram, 1.0, n, 2),
+                offset % 2 == 0
+            );
+        }
+    }
+
+    #[test]
+    fn test_ngram_fx64() {
+        let text = vec![1, 2, 3, 4, 5, 1, 2];
+        let ngrams: HashSet<u64> = ngram_fx64(&text, 2);
+        assert_eq!(ngrams.len(), 5);
+        for window in text.windows(2) {
+            assert!(ngrams.contains(&fxhash::hash64(window)));
+        }
+        let mut rng = StdRng::seed_from_u64(0);
+        let doc = (0..1000)
+            .map(|_| rng.gen_range(0..100))
+            .collect::<Vec<i32>>();
+        for _ in 0..50 {
+            let start = rng.gen_range(0..950);
+            let mut query = doc[start..start + 50].to_vec();
+            for token in query.iter_mut() {
+                if rng.gen_bool(0.2) {
+                    *token = rng.gen_range(0..100);
+                }
+            }
+            let threshold = rng.gen_range(0.3..0.9);
+            assert_eq!(
+                has_doc_duplicate_fx64(&doc, &query, &ngram_fx64(&query, 3), threshold, 3),
+                has_doc_duplicate(&doc, &query, &ngram(&query, 3), threshold, 3)
+            );
+        }
+    }
+
+    #[test]
+    fn test_ngram_seeded() {
+        let text = (0..50).collect::<Vec<i32>>();
+        let n = 5;
+        let ngrams = ngram_seeded(&text, n, 0);
+        assert_eq!(ngrams.len(), 46);
+        assert_eq!(ngrams, ngram_seeded(&text, n, 0));
+        for seed in 1..10 {
+            let other = ngram_seeded(&text, n, seed);
+            assert_eq!(other.len(), 46);
+            assert!(ngrams.is_disjoint(&other));
+        }
+
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..50 {
+            let doc = (0..100).map(|_| rng.gen_range(0..10)).collect::<Vec<i32>>();
+            let query = (0..10).map(|_| rng.gen_range(0..10)).collect::<Vec<i32>>();
+            let seed = rng.gen();
+            assert_eq!(
+                has_doc_duplicate_seeded(
+                    &doc,
+                    &query,
+                    &ngram_seeded(&query, 3, seed),
+                    0.6,
+                    3,
+                    seed
+                ),
+                has_doc_duplicate(&doc, &query, &ngram(&query, 3), 0.6, 3)
+            );
+        }
+    }
+
+    #[test]
+    fn test_ngram_jaccard() {
+        let n = 3;
+        let a = ngram(&(0..100).collect::<Vec<i32>>(), n);
+        let b = ngram(&(50..150).collect::<Vec<i32>>(), n);
+        let sim = ngram_jaccard(&a, &b);
+        assert!(0.0 < sim && sim < 1.0);
+        // 48 shared n-grams out of 98 + 98 - 48
+        assert_eq!(sim, 48.0 / 148.0);
+        assert_eq!(ngram_jaccard(&b, &a), sim);
+        assert_eq!(ngram_jaccard(&a, &a), 1.0);
+        assert_eq!(ngram_jaccard(&a, &HashSet::default()), 0.0);
+        assert_eq!(
+            ngram_jaccard::<usize>(&HashSet::default(), &HashSet::default()),
+            0.0
+        );
+    }
+
+    #[test]
+    fn test_ngram_text_shorter_than_n() {
+        let text = vec![1, 2];
+        assert!(ngram(&text, 5).is_empty());
+        assert!(ngram_rolling(&text, 5).is_empty());
+        assert!(ngram_rolling_double(&text, 5).is_empty());
+    }
+
+    #[test]
+    fn test_has_doc_duplicate_exact() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let doc = (0..2000)
+            .map(|_| rng.gen_range(0..20))
+            .collect::<Vec<i32>>();
+        let n = 2;
+        for _ in 0..100 {
+            let start = rng.gen_range(0..1900);
+            let mut query = doc[start..start + rng.gen_range(3..50)].to_vec();
+            if rng.gen_bool(0.5) {
+                query.reverse();
+            }
+            if rng.gen_bool(0.5) {
+                query[0] = 20;
+            }
+            let query_ngram = ngram(&query, n);
+            assert_eq!(
+                has_doc_duplicate(&doc, &query, &query_ngram, 1.0, n),
+                has_doc_duplicate_with(&doc, &query, &query_ngram, 1.0, n, weighted_jaccard)
+            );
+        }
+        // permutations of the query are matched
+        let query = vec![3, 2, 1, 4];
+        let doc = vec![0, 1, 2, 3, 4, 0, 0];
+        let query_ngram = ngram(&query, 1);
+        assert!(has_doc_duplicate(&doc, &query, &query_ngram, 1.0, 1));
+        assert!(!has_doc_duplicate(
+            &doc,
+            &[1, 2, 3, 5],
+            &ngram(&[1, 2, 3, 5], 1),
+            1.0,
+            1
+        ));
+        // a threshold above 1.0 is not reached even by an exact copy, as with the naive search
+        let doc = (0..20).collect::<Vec<i32>>();
+        let query = (5..10).collect::<Vec<i32>>();
+        let query_ngram = ngram(&query, 2);
+        assert!(!has_doc_duplicate(&doc, &query, &query_ngram, 1.5, 2));
+        assert!(!has_doc_duplicate_naive(&doc, &query, 1.5));
+        assert!(!has_doc_duplicate_incremental(
+            &doc,
+            &query,
+            &query_ngram,
+            1.5,
+            2
+        ));
+    }
+
+    #[test]
+    fn test_has_doc_duplicate_filtered() {
+        let ignore = [0].into_iter().collect::<HashSet<i32>>();
+        // a padded query and a padded document sharing nothing but the padding
+        let query = vec![0, 0, 0, 0, 0, 0, 1, 2, 3, 4];
+        let doc = vec![9, 0, 0, 0, 0, 0, 0, 7, 8, 9, 5, 9, 9, 9];
+        let n = 2;
+        assert!(has_doc_duplicate(&doc, &query, &ngram(&query, n), 0.4, n));
+        let filtered_query = remove_ignored(&query, &ignore);
+        assert_eq!(filtered_query, vec![1, 2, 3, 4]);
+        let filtered_ngram = ngram(&filtered_query, n);
+        assert!(!has_doc_duplicate_filtered(
+            &doc,
+            &filtered_query,
+            &filtered_ngram,
+            0.4,
+            n,
+            &ignore
+        ));
+        assert_eq!(weighted_jaccard_filtered(&query, &doc[1..11], &ignore), 0.0);
+        // padding inserted into a duplicate does not prevent the match
+        let doc = vec![9, 1, 0, 2, 0, 0, 3, 4, 9];
+        assert!(has_doc_duplicate_filtered(
+            &doc,
+            &filtered_query,
+            &filtered_ngram,
+            1.0,
+            n,
+            &ignore
+        ));
+        assert_eq!(weighted_jaccard_filtered(&query, &doc[1..8], &ignore), 1.0);
+        assert_eq!(
+            weighted_jaccard_filtered(&query, &doc, &HashSet::default()),
+            weighted_jaccard(&query, &doc)
+        );
+    }
+
+    #[test]
+    fn test_has_doc_duplicate_flex() {
+        let query = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+        let n = 3;
+        let query_ngram = ngram(&query, n);
+        // the doc span is one token longer than the query
+        let doc = vec![0, 0, 1, 2, 3, 4, 5, 0, 6, 7, 8, 9, 10, 0, 0];
+        assert!(!has_doc_duplicate(&doc, &query, &query_ngram, 0.9, n));
+        assert!(!has_doc_duplicate_flex(
+            &doc,
+            &query,
+            &query_ngram,
+            0.9,
+            n,
+            0
+        ));
+        assert!(has_doc_duplicate_flex(
+            &doc,
+            &query,
+            &query_ngram,
+            0.9,
+            n,
+            1
+        ));
+        // the doc span is one token shorter than the query, at the end of the document
+        let doc = vec![0, 0, 1, 2, 3, 4, 6, 7, 8, 9, 10];
+        assert!(!has_doc_duplicate_flex(
+            &doc,
+            &query,
+            &query_ngram,
+            0.9,
+            n,
+            0
+        ));
+        assert!(has_doc_duplicate_flex(
+            &doc,
+            &query,
+            &query_ngram,
+            0.9,
+            n,
+            1
+        ));
+        // every length is compared with the naive search
+        let mut rng = StdRng::seed_from_u64(0);
+        let doc = (0..300).map(|_| rng.gen_range(0..20)).collect::<Vec<i32>>();
+        for _ in 0..20 {
+            let start = rng.gen_range(0..250);
+            let mut query = doc[start..start + 30].to_vec();
+            query.remove(rng.gen_range(0..30));
+            let query_ngram = ngram(&query, n);
+            let threshold = rng.gen_range(0.5..1.0);
+            let tolerance = rng.gen_range(0..3);
+            let naive = (query.len() - tolerance..=query.len() + tolerance).any(|len| {
+                doc.windows(len).any(|span| {
+                    ngram(span, n).iter().any(|h| query_ngram.contains(h))
+                        && weighted_jaccard(&query, span) >= threshold
+                })
+            });
+            assert_eq!(
+                has_doc_duplicate_flex(&doc, &query, &query_ngram, threshold, n, tolerance),
+                naive
+            );
+        }
+        // unrelated queries slide the window over many candidate starts before matching, if at all
+        for _ in 0..20 {
+            let query = (0..30).map(|_| rng.gen_range(0..20)).collect::<Vec<i32>>();
+            let query_ngram = ngram(&query, n);
+            let threshold = rng.gen_range(0.2..0.5);
+            let tolerance = rng.gen_range(0..5);
+            let naive = (query.len() - tolerance..=query.len() + tolerance).any(|len| {
+                doc.windows(len).any(|span| {
+                    ngram(span, n).iter().any(|h| query_ngram.contains(h))
+                        && weighted_jaccard(&query, span) >= threshold
+                })
+            });
+            assert_eq!(
+                has_doc_duplicate_flex(&doc, &query, &query_ngram, threshold, n, tolerance),
+                naive
+            );
+        }
+    }
+
+    #[test]
+    fn test_has_doc_duplicate_stats() {
+        let query = vec![1, 2, 3, 4, 5];
+        let n = 2;
+        let query_ngram = ngram(&query, n);
+        // [1, 2] is the only hit, and both spans containing it are below the threshold
+        let doc = vec![9, 1, 2, 9, 9, 9, 9, 9, 9, 9, 9, 9];
+        assert_eq!(
+            has_doc_duplicate_stats(&doc, &query, &query_ngram, 0.8, n),
+            MatchStats {
+                ngram_hits: 1,
+                jaccard_calls: 2,
+                matched: false
+            }
+        );
+        // the spans containing [1, 2] are doc[0..5] and doc[1..6], and the second one matches
+        let doc = vec![9, 1, 2, 3, 4, 5, 9, 9, 9, 9];
+        assert_eq!(
+            has_doc_duplicate_stats(&doc, &query, &query_ngram, 0.8, n),
+            MatchStats {
+                ngram_hits: 1,
+                jaccard_calls: 2,
+                matched: true
+            }
+        );
+        assert_eq!(
+            has_doc_duplicate_stats(&doc, &query, &query_ngram, 0.8, n).matched,
+            has_doc_duplicate_with(&doc, &query, &query_ngram, 0.8, n, weighted_jaccard)
+        );
+    }
+
+    #[test]
+    fn test_max_possible_jaccard() {
+        assert_eq!(max_possible_jaccard(9, 10), 0.9);
+        assert_eq!(max_possible_jaccard(10, 9), 0.9);
+        assert_eq!(max_possible_jaccard(0, 0), 1.0);
+
+        let query = (1..=10).collect::<Vec<i32>>();
+        let n = 3;
+        let query_ngram = ngram(&query, n);
+        // [1, 2, 3] at 20 is the only hit, and no span around it matches
+        let mut doc = (100..150).collect::<Vec<i32>>();
+        doc[20..23].copy_from_slice(&[1, 2, 3]);
+        // at 0.9 only lengths 9 to 11 can match, so the starts 12 to 20 try
+        // 1 + 2 + 3 * 7 lengths, the same as with a tolerance of 1
+        let expected = MatchStats {
+            ngram_hits: 1,
+            jaccard_calls: 24,
+            matched: false,
+        };
+        assert_eq!(
+            has_doc_duplicate_flex_stats(&doc, &query, &query_ngram, 0.9, n, 5),
+            expected
+        );
+        assert_eq!(
+            has_doc_duplicate_flex_stats(&doc, &query, &query_ngram, 0.9, n, 1),
+            expected
+        );
+        // without pruning, lengths 5 to 15 are tried
+        assert!(
+            has_doc_duplicate_flex_stats(&doc, &query, &query_ngram, 0.5, n, 5).jaccard_calls
+                > expected.jaccard_calls
+        );
+        // no length can reach a threshold above 1
+        assert_eq!(
+            has_doc_duplicate_flex_stats(&doc, &query, &query_ngram, 1.1, n, 5),
+            MatchStats::default()
+        );
+    }
+
+    #[test]
+    fn test_has_doc_duplicate_min_len() {
+        let query = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+        let n = 2;
+        let query_ngram = ngram(&query, n);
+        // short accidental overlaps of at most 2 tokens
+        let doc = vec![0, 1, 2, 7, 8, 3, 4, 9, 10, 5, 6, 0];
+        assert!(has_doc_duplicate(&doc, &query, &query_ngram, 0.8, n));
+        assert!(has_doc_duplicate_min_len(
+            &doc,
+            &query,
+            &query_ngram,
+            0.8,
+            n,
+            2
+        ));
+        assert!(!has_doc_duplicate_min_len(
+            &doc,
+            &query,
+            &query_ngram,
+            0.8,
+            n,
+            3
+        ));
+        let doc = vec![0, 1, 2, 3, 4, 5, 6, 7, 0, 9, 10, 0];
+        assert!(has_doc_duplicate_min_len(
+            &doc,
+            &query,
+            &query_ngram,
+            0.8,
+            n,
+            7
+        ));
+        assert!(!has_doc_duplicate_min_len(
+            &doc,
+            &query,
+            &query_ngram,
+            0.8,
+            n,
+            8
+        ));
+        assert_eq!(longest_common_run(&[1, 2, 3, 1, 2], &[3, 1, 2, 9]), 3);
+        assert_eq!(longest_common_run::<i32>(&[], &[1]), 0);
+    }
+
+    #[test]
+    fn test_has_doc_duplicate_bytes() {
+        let doc = "Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do eiusmod tempor incididunt ut labore et dolore magna aliqua.".as_bytes();
+        let n = 5;
+        let query = &doc[28..75];
+        let query_ngram = ngram_bytes(query, n);
+        assert!(has_doc_duplicate_bytes(doc, query, &query_ngram, 1.0, n));
+        let query = "the quick brown fox jumps over the lazy dog".as_bytes();
+        let query_ngram = ngram_bytes(query, n);
+        assert!(!has_doc_duplicate_bytes(doc, query, &query_ngram, 0.8, n));
+    }
+
+    #[test]
+    fn test_find_doc_duplicate() {
+        let query = vec![5, 6, 7, 8, 9];
+        let doc = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12];
+        let n = 3;
+        let query_ngram = ngram(&query, n);
+        // the n-gram [5, 6, 7] is hit at start 4, and the span must be the one that matched
+        assert_eq!(
+            find_doc_duplicate(&doc, &query, &query_ngram, 1.0, n),
+            Some((4, 9))
+        );
+        let query = vec![20, 21, 22, 23, 24];
+        let query_ngram = ngram(&query, n);
+        assert_eq!(find_doc_duplicate(&doc, &query, &query_ngram, 0.8, n), None);
+    }
+
+    #[test]
+    fn test_find_doc_duplicate_policy() {
+        // a partial match of the query comes before an exact one
+        let query = vec![5, 6, 7, 8, 9];
+        let doc = vec![5, 6, 7, 0, 0, 1, 2, 5, 6, 7, 8, 9];
+        let n = 3;
+        let query_ngram = ngram(&query, n);
+        let find = |threshold, policy| {
+            find_doc_duplicate_policy(&doc, &query, &query_ngram, threshold, n, policy)
+        };
+        assert_eq!(
+            find(0.2, InnerPolicy::FirstMatch),
+            Some((0, 5, weighted_jaccard(&query, &doc[0..5])))
+        );
+        assert_eq!(
+            find(0.2, InnerPolicy::FirstMatch).map(|(start, end, _)| (start, end)),
+            find_doc_duplicate(&doc, &query, &query_ngram, 0.2, n)
+        );
+        assert_eq!(find(0.2, InnerPolicy::BestMatch), Some((7, 12, 1.0)));
+        assert_eq!(find(1.0, InnerPolicy::FirstMatch), Some((7, 12, 1.0)));
+        assert_eq!(find(1.0, InnerPolicy::BestMatch), Some((7, 12, 1.0)));
+        assert_eq!(find(0.2, InnerPolicy::Budget(0)), None);
+        assert_eq!(find(1.1, InnerPolicy::BestMatch), None);
+
+        // [5, 6, 7] is the only n-gram hit, and the permutation of the query is its third candidate
+        let query = vec![5, 6, 7, 1, 2];
+        let doc = vec![0, 0, 5, 6, 7, 2, 1];
+        let query_ngram = ngram(&query, n);
+        let find = |policy| find_doc_duplicate_policy(&doc, &query, &query_ngram, 1.0, n, policy);
+        assert_eq!(find(InnerPolicy::Budget(2)), None);
+        assert_eq!(find(InnerPolicy::Budget(3)), Some((2, 7, 1.0)));
+        assert_eq!(find(InnerPolicy::FirstMatch), Some((2, 7, 1.0)));
+    }
+
+    #[test]
+    fn test_match_at_doc_end() {
+        let doc = vec![9, 9, 9, 9, 9, 1, 2, 3, 4, 5];
+        let query = vec![1, 2, 3, 4, 5];
+        let n = 3;
+        let query_ngram = ngram(&query, n);
+        assert!(has_doc_duplicate(&doc, &query, &query_ngram, 0.8, n));
+        assert!(has_doc_duplicate(&doc, &query, &query_ngram, 1.0, n));
+        assert!(has_doc_duplicate_incremental(
+            &doc,
+            &query,
+            &query_ngram,
+            0.8,
+            n
+        ));
+        assert!(has_doc_duplicate_stats(&doc, &query, &query_ngram, 0.8, n).matched);
+        assert!(has_doc_duplicate_naive(&doc, &query, 1.0));
+        assert_eq!(
+            find_doc_duplicate(&doc, &query, &query_ngram, 0.8, n),
+            Some((5, 10))
+        );
+        assert_eq!(
+            find_all_doc_duplicates(&doc, &query, &query_ngram, 1.0, n),
+            vec![(5, 10, 1.0)]
+        );
+        assert_eq!(best_doc_similarity(&doc, &query, &query_ngram, n), 1.0);
+        // the query is the whole document
+        assert!(has_doc_duplicate(&query, &query, &query_ngram, 1.0, n));
+    }
+
+    #[test]
+    fn test_rolling_verify_ngrams() {
+        // (a, b + 1, c - 31) collides with (a, b, c) under base 31
+        let query = vec![7, 1, 1, 40, 8];
+        let n = 3;
+        let query_ngram = ngram_rolling(&query, n);
+        let doc = vec![0, 0, 1, 2, 9, 0, 0];
+        assert!(query_ngram.contains(&(RollingHash::from_slice(&[1, 2, 9]).get_hash() as usize)));
+        // the colliding n-gram leads to spans sharing a single token with the query
+        assert!(has_doc_duplicate_rolling_verify(
+            &doc,
+            &query,
+            &query_ngram,
+            0.1,
+            n,
+            false
+        ));
+        assert!(!has_doc_duplicate_rolling_verify(
+            &doc,
+            &query,
+            &query_ngram,
+            0.1,
+            n,
+            true
+        ));
+
+        // verification keeps the matches of n-grams that do occur in the query
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..200 {
+            let doc = (0..100).map(|_| rng.gen_range(0..10)).collect::<Vec<i32>>();
+            let query = (0..rng.gen_range(n..20))
+                .map(|_| rng.gen_range(0..10))
+                .collect::<Vec<i32>>();
+            let query_ngram = ngram_rolling(&query, n);
+            for threshold in [0.3, 0.6, 0.9] {
+                assert_eq!(
+                    has_doc_duplicate_rolling_verify(
+                        &doc,
+                        &query,
+                        &query_ngram,
+                        threshold,
+                        n,
+                        true
+                    ),
+                    has_doc_duplicate(&doc, &query, &ngram(&query, n), threshold, n)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_rolling_match_at_doc_end() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let doc = (0..100)
+            .map(|_| rng.gen_range(0..50254))
+            .collect::<Vec<i32>>();
+        // n == query.len(), so the last n-gram ends at the end of the document
+        let query = doc[95..].to_vec();
+        for n in [3, 5] {
+            let query_ngram = ngram_rolling(&query, n);
+            assert!(has_doc_duplicate_rolling(
+                &doc,
+                &query,
+                &query_ngram,
+                1.0,
+                n
+            ));
+            #[cfg(feature = "parallel")]
+            assert!(has_doc_duplicate_par(&doc, &query, &query_ngram, 1.0, n));
+            let query_ngram = ngram_rolling_double(&query, n);
+            assert!(has_doc_duplicate_rolling_double(
+                &doc,
+                &query,
+                &query_ngram,
+                1.0,
+                n
+            ));
+        }
+        let query = vec![-1; 5];
+        let query_ngram = ngram_rolling(&query, 5);
+        assert!(!has_doc_duplicate_rolling(
+            &doc,
+            &query,
+            &query_ngram,
+            0.5,
+            5
+        ));
+    }
+
+    #[test]
+    fn test_find_doc_duplicate_naive() {
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..200 {
+            let doc = (0..rng.gen_range(0..100))
+                .map(|_| rng.gen_range(0..10))
+                .collect::<Vec<i32>>();
+            let query = (0..rng.gen_range(1..20))
+                .map(|_| rng.gen_range(0..10))
+                .collect::<Vec<i32>>();
+            let threshold = rng.gen_range(0.3..1.0);
+            let naive = find_doc_duplicate_naive(&doc, &query, threshold);
+            if let Some((start, sim)) = naive {
+                assert!(sim >= threshold);
+                assert_eq!(
+                    sim,
+                    weighted_jaccard(&query, &doc[start..start + query.len()])
+                );
+            }
+            // every span above the threshold shares a unigram with the query
+            let fast = find_doc_duplicate(&doc, &query, &ngram(&query, 1), threshold, 1);
+            assert_eq!(fast.is_some(), naive.is_some());
+            if let Some((start, end)) = fast {
+                assert!(weighted_jaccard(&query, &doc[start..end]) >= threshold);
+            }
+        }
+    }
+
+    #[test]
+    fn test_find_all_doc_duplicates() {
+        let query = vec![101, 102, 103, 104, 105, 106];
+        let mut doc = (0..40).collect::<Vec<i32>>();
+        doc[5..11].copy_from_slice(&query);
+        doc[25..31].copy_from_slice(&[101, 102, 0, 104, 105, 106]);
+        let n = 3;
+        let query_ngram = ngram(&query, n);
+        let spans = find_all_doc_duplicates(&doc, &query, &query_ngram, 0.5, n);
+        assert!(spans.contains(&(5, 11, 1.0)));
+        assert!(spans.contains(&(25, 31, 5.0 / 7.0)));
+        // every reported span passes the threshold and is reported once
+        for window in spans.windows(2) {
+            assert!(window[0].0 < window[1].0);
+        }
+        for &(s, e, sim) in &spans {
+            assert!(sim >= 0.5);
+            assert_eq!(weighted_jaccard(&query, &doc[s..e]), sim);
+        }
+        assert_eq!(
+            keep_local_best(spans),
+            vec![(5, 11, 1.0), (25, 31, 5.0 / 7.0)]
+        );
+    }
+
+    #[test]
+    fn test_non_maximum_suppression() {
+        assert_eq!(span_iou((0, 6), (2, 8)), 4.0 / 8.0);
+        assert_eq!(span_iou((0, 6), (6, 12)), 0.0);
+
+        // a single matching region
+        let query = vec![101, 102, 103, 104, 105, 106];
+        let mut doc = (0..40).collect::<Vec<i32>>();
+        doc[15..21].copy_from_slice(&query);
+        let n = 1;
+        let query_ngram = ngram(&query, n);
+        let spans = find_all_doc_duplicates(&doc, &query, &query_ngram, 0.4, n);
+        assert!(spans.len() > 1);
+        assert_eq!(
+            find_distinct_doc_duplicates(&doc, &query, &query_ngram, 0.4, n, 0.3),
+            vec![(15, 21, 1.0)]
+        );
+        assert_eq!(non_maximum_suppression(spans.clone(), 1.0), spans);
+
+        // two regions sharing a boundary token are both kept
+        let query = vec![1, 2, 3, 4];
+        let doc = vec![1, 2, 3, 4, 2, 3, 4, 9];
+        let query_ngram = ngram(&query, 2);
+        assert_eq!(
+            find_distinct_doc_duplicates(&doc, &query, &query_ngram, 0.5, 2, 0.3),
+            vec![(0, 4, 1.0), (3, 7, weighted_jaccard(&query, &doc[3..7]))]
+        );
+    }
+
+    #[test]
+    fn test_top_k_spans() {
+        // two distinct similar regions, each surrounded by overlapping candidate windows
+        let query = (100..120).collect::<Vec<i32>>();
+        let mut doc = (1000..1200).collect::<Vec<i32>>();
+        doc[30..50].copy_from_slice(&query);
+        doc[120..140].copy_from_slice(&query);
+        doc[125] = -1;
+        doc[126] = -2;
+        let n = 3;
+        let query_ngram = ngram(&query, n);
+        let second = weighted_jaccard(&query, &doc[120..140]);
+        assert_eq!(
+            top_k_spans(&doc, &query, &query_ngram, n, 2),
+            vec![(30, 50, 1.0), (120, 140, second)]
+        );
+        assert_eq!(
+            top_k_spans(&doc, &query, &query_ngram, n, 1),
+            vec![(30, 50, 1.0)]
+        );
+        // the spans around the regions are less similar, and the remaining ones are disjoint
+        let spans = top_k_spans(&doc, &query, &query_ngram, n, 10);
+        assert_eq!(spans[..2], [(30, 50, 1.0), (120, 140, second)]);
+        for (i, a) in spans.iter().enumerate() {
+            assert!(spans[..i].iter().all(|b| b.2 >= a.2));
+            assert!(spans[..i].iter().all(|b| a.1 <= b.0 || b.1 <= a.0));
+        }
+        assert!(top_k_spans(&doc, &query, &query_ngram, n, 0).is_empty());
+        assert!(top_k_spans(&doc, &query, &ngram(&[-5, -6, -7], n), n, 2).is_empty());
+    }
+
+    #[test]
+    fn test_best_doc_similarity() {
+        let query = vec![1, 2, 3, 4, 5, 6];
+        let doc = vec![9, 9, 1, 2, 3, 7, 5, 6, 9, 9, 9, 9, 9, 9];
+        let n = 3;
+        let query_ngram = ngram(&query, n);
+        let naive_best = (0..=doc.len() - query.len())
+            .map(|s| weighted_jaccard(&query, &doc[s..s + query.len()]))
+            .fold(0.0, f64::max);
+        let best = best_doc_similarity(&doc, &query, &query_ngram, n);
+        assert_eq!(best, naive_best);
+        assert!(has_doc_duplicate_naive(&doc, &query, best));
+        assert!(!has_doc_duplicate_naive(&doc, &query, best + 1e-9));
+
+        let query = vec![20, 21, 22, 23, 24, 25];
+        let query_ngram = ngram(&query, n);
+        assert_eq!(best_doc_similarity(&doc, &query, &query_ngram, n), 0.0);
+    }
+
+    #[test]
+    fn test_has_doc_duplicate_thresholds() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let doc = (0..1000)
+            .map(|_| rng.gen_range(0..100))
+            .collect::<Vec<i32>>();
+        let thresholds = [0.5, 0.8, 1.0];
+        let n = 3;
+        for _ in 0..50 {
+            let start = rng.gen_range(0..950);
+            let mut query = doc[start..start + 50].to_vec();
+            for token in query.iter_mut() {
+                if rng.gen_bool(0.15) {
+                    *token = rng.gen_range(0..100);
+                }
+            }
+            let query_ngram = ngram(&query, n);
+            let results = has_doc_duplicate_thresholds(&doc, &query, &query_ngram, &thresholds, n);
+            // a higher threshold never matches when a lower one does not
+            assert!(results.windows(2).all(|w| w[0] >= w[1]));
+            for (result, threshold) in results.iter().zip(thresholds) {
+                assert_eq!(
+                    *result,
+                    has_doc_duplicate(&doc, &query, &query_ngram, threshold, n)
+                );
+            }
+        }
+        assert!(
+            has_doc_duplicate_thresholds(&doc, &doc[..10], &ngram(&doc[..10], n), &[], n)
+                .is_empty()
+        );
     }
-    for start in 0..doc.len() - query.len() {
-        let is_in_query_ngram = query_ngram.contains(&(rollinghash.hash as usize));
-        if !is_in_query_ngram {
-            // update hash_value
-            rollinghash.slide(doc[start] as u64, doc[start + n] as u64);
-            continue;
+
+    #[test]
+    fn test_generic_u32() {
+        let query: Vec<u32> = vec![3, 4, 5, 6, 7];
+        let doc: Vec<u32> = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+        let n = 3;
+        let query_ngram = ngram(&query, n);
+        assert_eq!(weighted_jaccard(&query, &doc[2..7]), 1.0);
+        assert_eq!(
+            find_doc_duplicate(&doc, &query, &query_ngram, 0.8, n),
+            Some((2, 7))
+        );
+        assert!(has_doc_duplicate(&doc, &query, &query_ngram, 0.8, n));
+        assert!(has_doc_duplicate_naive(&doc, &query, 0.8));
+        let query_ngram = ngram_rolling(&query, n);
+        assert!(has_doc_duplicate_rolling(
+            &doc,
+            &query,
+            &query_ngram,
+            0.8,
+            n
+        ));
+    }
+
+    #[test]
+    fn test_rolling_double() {
+        let n = 3;
+        // every 3-gram of the doc collides with [0, 1, 0] under a single rolling hash
+        let query = vec![0, 1, 0, 5, 6, 7];
+        let doc = vec![0, 0, 31, 0, 0, 31, 0, 0, 31, 0, 0, 31];
+        assert!(ngram_rolling(&query, n).contains(&31));
+        let query_ngram = ngram_rolling_double(&query, n);
+        assert_eq!(query_ngram.len(), 4);
+        assert!(!has_doc_duplicate_rolling_double(
+            &doc,
+            &query,
+            &query_ngram,
+            0.1,
+            n
+        ));
+        // the collisions only reach the inner loop with a single hash
+        let single =
+            has_doc_duplicate_rolling_stats(&doc, &query, &ngram_rolling(&query, n), 0.5, n);
+        assert_eq!(single.ngram_hits, 4);
+        assert!(single.jaccard_calls > 0 && !single.matched);
+        assert_eq!(
+            has_doc_duplicate_rolling_double_stats(&doc, &query, &query_ngram, 0.5, n),
+            MatchStats::default()
+        );
+
+        let query = vec![3, 4, 5, 6, 7];
+        let doc = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+        let query_ngram = ngram_rolling_double(&query, n);
+        assert!(has_doc_duplicate_rolling_double(
+            &doc,
+            &query,
+            &query_ngram,
+            1.0,
+            n
+        ));
+    }
+
+    #[test]
+    fn test_sliding_frequency() {
+        let query = vec![1, 1, 2, 3];
+        let doc = vec![1, 2, 2, 2, 1, 1, 3, 4, 1, 2];
+        let mut window = SlidingFrequency::new(query.iter());
+        for token in &doc[..4] {
+            window.add(token);
         }
-        let inner_start = max(0, start as i32 - query.len() as i32 + n as i32) as usize;
-        for s in inner_start..(start + 1) {
-            let end = s + query.len();
-            let sim = weighted_jaccard(&query, &doc[s..end]);
-            if sim >= threshold {
-                return true;
+        assert_eq!(window.similarity(), weighted_jaccard(&query, &doc[..4]));
+        for s in 1..doc.len() - query.len() + 1 {
+            window.remove(&doc[s - 1]);
+            window.add(&doc[s + query.len() - 1]);
+            assert_eq!(
+                window.similarity(),
+                weighted_jaccard(&query, &doc[s..s + query.len()])
+            );
+        }
+    }
+
+    #[test]
+    fn test_has_doc_duplicate_incremental() {
+        let n = 2;
+        let doc = vec![1, 2, 3, 1, 2, 4, 5, 1, 2, 3, 4, 6, 7, 8, 2, 3, 9, 9];
+        for query in [
+            vec![1, 2, 3, 4],
+            vec![2, 3, 9, 9],
+            vec![5, 1, 2, 6],
+            vec![7, 8, 1, 2],
+            vec![10, 11, 12, 13],
+        ] {
+            let query_ngram = ngram(&query, n);
+            for threshold in [0.3, 0.5, 0.6, 0.8, 1.0] {
+                assert_eq!(
+                    has_doc_duplicate_incremental(&doc, &query, &query_ngram, threshold, n),
+                    has_doc_duplicate(&doc, &query, &query_ngram, threshold, n)
+                );
             }
         }
-        // update hash_value
-        rollinghash.slide(doc[start] as u64, doc[start + n] as u64);
     }
-    return false;
+
+    #[test]
+    fn test_zero_n() {
+        let text = vec![1, 2, 3, 4, 5];
+        assert!(ngram(&text, 0).is_empty());
+        assert!(ngram_fx64(&text, 0).is_empty());
+        assert!(ngram_seeded(&text, 0, 0).is_empty());
+        assert!(ngram_rolling(&text, 0).is_empty());
+        assert!(ngram_rolling_double(&text, 0).is_empty());
+        assert!(ngram_strided(&text, 0, 2).is_empty());
+        assert!(ngram_bytes(b"abc", 0).is_empty());
+        #[cfg(feature = "parallel")]
+        assert!(ngram_par(&text, 0).is_empty());
+
+        // a non-empty n-gram set must not make n == 0 match either
+        let doc = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+        let query = vec![3, 4, 5];
+        let fx = [fxhash::hash::<[i32]>(&[])].into_iter().collect();
+        let fx64 = [fxhash::hash64::<[i32]>(&[])].into_iter().collect();
+        let seeded = [seeded_hash::<i32>(&[], 0)].into_iter().collect();
+        let rolling = [0].into_iter().collect();
+        let rolling_double = [(0, 0)].into_iter().collect();
+        for threshold in [0.5, 1.0] {
+            assert!(!has_doc_duplicate(&doc, &query, &fx, threshold, 0));
+            assert!(!has_doc_duplicate_incremental(
+                &doc, &query, &fx, threshold, 0
+            ));
+            assert!(!has_doc_duplicate_flex(&doc, &query, &fx, threshold, 0, 1));
+            assert!(!has_doc_duplicate_stats(&doc, &query, &fx, threshold, 0).matched);
+            assert!(!has_doc_duplicate_min_len(
+                &doc, &query, &fx, threshold, 0, 1
+            ));
+            assert!(!has_doc_duplicate_containment(
+                &doc, &query, &fx, threshold, 0
+            ));
+            assert!(!has_doc_duplicate_filtered(
+                &doc,
+                &query,
+                &fx,
+                threshold,
+                0,
+                &HashSet::default()
+            ));
+            assert!(!has_doc_duplicate_strided(
+                &doc, &query, &fx, threshold, 0, 1
+            ));
+            assert!(!has_doc_duplicate_fx64(&doc, &query, &fx64, threshold, 0));
+            assert!(!has_doc_duplicate_seeded(
+                &doc, &query, &seeded, threshold, 0, 0
+            ));
+            assert!(!has_doc_duplicate_rolling(
+                &doc, &query, &rolling, threshold, 0
+            ));
+            assert!(!has_doc_duplicate_rolling_double(
+                &doc,
+                &query,
+                &rolling_double,
+                threshold,
+                0
+            ));
+            assert!(!has_doc_duplicate_streaming(
+                doc.iter().copied(),
+                &query,
+                &rolling,
+                threshold,
+                0
+            ));
+            #[cfg(feature = "parallel")]
+            assert!(!has_doc_duplicate_par(&doc, &query, &rolling, threshold, 0));
+            assert_eq!(find_doc_duplicate(&doc, &query, &fx, threshold, 0), None);
+            assert!(find_all_doc_duplicates(&doc, &query, &fx, threshold, 0).is_empty());
+            assert_eq!(
+                find_doc_duplicate_policy(&doc, &query, &fx, threshold, 0, InnerPolicy::BestMatch),
+                None
+            );
+            assert_eq!(
+                has_doc_duplicate_thresholds(&doc, &query, &fx, &[threshold], 0),
+                vec![false]
+            );
+        }
+        assert_eq!(best_doc_similarity(&doc, &query, &fx, 0), 0.0);
+        assert_eq!(
+            try_has_doc_duplicate(&doc, &query, &fx, 0.5, 0),
+            Err(NeardupError::ZeroN)
+        );
+    }
+
+    #[test]
+    fn test_query_longer_than_doc() {
+        let query = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+        let doc = vec![1, 2, 3];
+        let n = 3;
+        let query_ngram = ngram(&query, n);
+        assert!(!has_doc_duplicate(&doc, &query, &query_ngram, 0.1, n));
+        assert!(!has_doc_duplicate_naive(&doc, &query, 0.1));
+        assert!(!has_doc_duplicate_incremental(
+            &doc,
+            &query,
+            &query_ngram,
+            0.1,
+            n
+        ));
+        assert_eq!(find_doc_duplicate(&doc, &query, &query_ngram, 0.1, n), None);
+        assert!(find_all_doc_duplicates(&doc, &query, &query_ngram, 0.1, n).is_empty());
+        assert_eq!(best_doc_similarity(&doc, &query, &query_ngram, n), 0.0);
+        let query_ngram = ngram_rolling(&query, n);
+        assert!(!has_doc_duplicate_rolling(
+            &doc,
+            &query,
+            &query_ngram,
+            0.1,
+            n
+        ));
+        let query_ngram = ngram_rolling_double(&query, n);
+        assert!(!has_doc_duplicate_rolling_double(
+            &doc,
+            &query,
+            &query_ngram,
+            0.1,
+            n
+        ));
+    }
+
+    #[test]
+    fn test_try_has_doc_duplicate() {
+        let doc = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+        let query = vec![1, 2, 3, 4, 5];
+        let query_ngram = ngram(&query, 3);
+        assert_eq!(
+            try_has_doc_duplicate(&doc, &query, &query_ngram, 0.8, 3),
+            Ok(true)
+        );
+        assert_eq!(
+            try_has_doc_duplicate(&doc, &[], &query_ngram, 0.8, 3),
+            Err(NeardupError::EmptyQuery)
+        );
+        assert_eq!(
+            try_has_doc_duplicate(&doc, &query, &query_ngram, 0.8, 0),
+            Err(NeardupError::ZeroN)
+        );
+        assert_eq!(
+            try_has_doc_duplicate(&doc, &query, &query_ngram, 0.8, 6),
+            Err(NeardupError::NLargerThanQuery)
+        );
+    }
+
+    #[test]
+    fn test_try_has_doc_duplicate_rolling() {
+        let doc = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+        let query = vec![1, 2, 3, 4, 5];
+        let query_ngram = NgramSet::rolling(&query, 3);
+        assert_eq!(
+            try_has_doc_duplicate_rolling(&doc, &query, &query_ngram, 0.8, 3),
+            Ok(true)
+        );
+        // a mismatched n would silently find nothing
+        assert!(!has_doc_duplicate_rolling(
+            &doc,
+            &query,
+            query_ngram.hashes(),
+            0.8,
+            4
+        ));
+        assert_eq!(
+            try_has_doc_duplicate_rolling(&doc, &query, &query_ngram, 0.8, 4),
+            Err(NeardupError::NgramSizeMismatch {
+                expected: 3,
+                actual: 4
+            })
+        );
+        assert_eq!(
+            try_has_doc_duplicate_rolling(&doc, &[], &query_ngram, 0.8, 3),
+            Err(NeardupError::EmptyQuery)
+        );
+        assert_eq!(
+            NeardupError::NgramSizeMismatch {
+                expected: 3,
+                actual: 4
+            }
+            .to_string(),
+            "n-gram set was built with n = 3, but n = 4 was given"
+        );
+    }
+
+    #[test]
+    fn test_minhash_jaccard_estimate() {
+        // 150 shared tokens out of 250 distinct tokens
+        let text1 = (0..200).collect::<Vec<i32>>();
+        let text2 = (50..250).collect::<Vec<i32>>();
+        let true_jaccard = jaccard(&text1, &text2);
+        assert_eq!(true_jaccard, 150.0 / 250.0);
+        let mut a = MinHash::new(512, 7);
+        a.update(&text1);
+        let mut b = MinHash::new(512, 7);
+        b.update(&text2);
+        assert_eq!(a.signature().len(), 512);
+        assert!((a.jaccard_estimate(&b) - true_jaccard).abs() < 0.1);
+
+        // updating in several calls is the same as one call for unigrams
+        let mut c = MinHash::new(512, 7);
+        c.update(&text2[..100]);
+        c.update(&text2[100..]);
+        assert_eq!(b.signature(), c.signature());
+
+        let mut a = MinHash::with_ngram_size(512, 7, 3);
+        a.update(&text1);
+        let mut b = MinHash::with_ngram_size(512, 7, 3);
+        b.update(&text2);
+        let true_jaccard = 148.0 / 248.0;
+        assert!((a.jaccard_estimate(&b) - true_jaccard).abs() < 0.1);
+    }
+
+    #[test]
+    fn test_has_doc_duplicate_approx() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let n = 3;
+        let threshold = 0.6;
+        let mut matches = 0;
+        let mut false_negatives = 0;
+        let mut false_positives = 0;
+        for _ in 0..100 {
+            let query = (0..50)
+                .map(|_| rng.gen_range(0..1000))
+                .collect::<Vec<i32>>();
+            // plant a copy of the query with up to half of its tokens replaced
+            let mut copy = query.clone();
+            for _ in 0..rng.gen_range(0..25) {
+                copy[rng.gen_range(0..50)] = rng.gen_range(0..1000);
+            }
+            let doc = [
+                (0..100).map(|_| rng.gen_range(0..1000)).collect(),
+                copy,
+                (0..100).map(|_| rng.gen_range(0..1000)).collect(),
+            ]
+            .concat();
+            let query_ngram = ngram(&query, n);
+            let exact = has_doc_duplicate(&doc, &query, &query_ngram, threshold, n);
+            let approx = has_doc_duplicate_approx(&doc, &query, &query_ngram, threshold, n, 16);
+            // sampling every token gives the exact result
+            assert_eq!(
+                has_doc_duplicate_approx(&doc, &query, &query_ngram, threshold, n, query.len()),
+                exact
+            );
+            matches += usize::from(exact);
+            false_negatives += usize::from(exact && !approx);
+            false_positives += usize::from(!exact && approx);
+        }
+        assert!(matches > 25, "{}", matches);
+        assert!(false_negatives * 20 <= matches, "{}", false_negatives);
+        assert!(false_positives * 20 <= 100 - matches, "{}", false_positives);
+
+        // exact matches are always found
+        let query = (0..50).collect::<Vec<i32>>();
+        let doc = (0..200).collect::<Vec<i32>>();
+        let query_ngram = ngram(&query, n);
+        assert!(has_doc_duplicate_approx(
+            &doc,
+            &query,
+            &query_ngram,
+            1.0,
+            n,
+            64
+        ));
+        assert!(!has_doc_duplicate_approx(
+            &doc[100..],
+            &query,
+            &query_ngram,
+            0.5,
+            n,
+            64
+        ));
+    }
+
+    #[test]
+    fn test_lsh_index() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let docs = (0..10)
+            .map(|_| {
+                (0..200)
+                    .map(|_| rng.gen_range(0..50254))
+                    .collect::<Vec<i32>>()
+            })
+            .collect::<Vec<Vec<i32>>>();
+        let mut index = LshIndex::new(16, 4, 5, 42);
+        for (doc_id, doc) in docs.iter().enumerate() {
+            index.insert(doc_id, doc);
+        }
+        let mut near_duplicate = docs[3].clone();
+        near_duplicate[50] = -1;
+        near_duplicate[150] = -1;
+        assert_eq!(index.query(&near_duplicate), vec![3]);
+        let unrelated = (0..200)
+            .map(|_| rng.gen_range(0..50254))
+            .collect::<Vec<i32>>();
+        assert!(index.query(&unrelated).is_empty());
+        assert!(index.query(&[1, 2]).is_empty());
+    }
+
+    #[test]
+    fn test_simhash() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let text = (0..100)
+            .map(|_| rng.gen_range(0..50254))
+            .collect::<Vec<i32>>();
+        let ngrams = ngram(&text, 3);
+        let fingerprint = SimHash::from_ngrams(&ngrams, 64);
+        assert_eq!(
+            hamming_distance(fingerprint, SimHash::from_ngrams(&ngram(&text, 3), 64)),
+            0
+        );
+        // replace a single n-gram
+        let mut other = ngrams.clone();
+        let removed = *other.iter().next().unwrap();
+        other.remove(&removed);
+        other.insert(removed.wrapping_add(1));
+        let distance = hamming_distance(fingerprint, SimHash::from_ngrams(&other, 64));
+        assert!(0 < distance && distance <= 8, "distance: {}", distance);
+        assert!(SimHash::from_ngrams(&ngrams, 16) < 1 << 16);
+    }
+
+    #[test]
+    fn test_has_doc_duplicate_streaming() {
+        let n = 2;
+        let mut doc = vec![1, 2, 3, 1, 2, 4, 5, 1, 2, 3, 4, 6, 7, 8, 2, 3, 9, 9];
+        doc.extend([0; 4]);
+        for query in [
+            vec![1, 2, 3, 4],
+            vec![2, 3, 9, 9],
+            vec![5, 1, 2, 6],
+            vec![7, 8, 1, 2],
+            vec![10, 11, 12, 13],
+        ] {
+            let query_ngram = ngram_rolling(&query, n);
+            for threshold in [0.3, 0.5, 0.6, 0.8, 1.0] {
+                // the document is generated lazily and never collected
+                let stream = doc.iter().copied();
+                assert_eq!(
+                    has_doc_duplicate_streaming(stream, &query, &query_ngram, threshold, n),
+                    has_doc_duplicate_rolling(&doc, &query, &query_ngram, threshold, n)
+                );
+            }
+        }
+        let query = vec![1, 2, 3];
+        let query_ngram = ngram_rolling(&query, 3);
+        assert!(has_doc_duplicate_streaming(
+            (0..1_000_000).map(|i| i % 1000),
+            &query,
+            &query_ngram,
+            1.0,
+            3
+        ));
+        assert!(!has_doc_duplicate_streaming(
+            1..3,
+            &query,
+            &query_ngram,
+            0.1,
+            3
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "parallel")]
+    fn test_has_doc_duplicate_par() {
+        let n = 3;
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut doc = (0..3 * PAR_CHUNK_SIZE + 100)
+            .map(|_| rng.gen_range(0..1000))
+            .collect::<Vec<i32>>();
+        // a match straddling the boundary of the first two chunks
+        let query = doc[PAR_CHUNK_SIZE - 5..PAR_CHUNK_SIZE + 5].to_vec();
+        let query_ngram = ngram_rolling(&query, n);
+        assert!(has_doc_duplicate_par(&doc, &query, &query_ngram, 1.0, n));
+        // a near-duplicate in the last chunk
+        let mut query = doc[3 * PAR_CHUNK_SIZE + 10..3 * PAR_CHUNK_SIZE + 30].to_vec();
+        query[19] = 1000;
+        let query_ngram = ngram_rolling(&query, n);
+        for threshold in [0.9, 1.0] {
+            assert_eq!(
+                has_doc_duplicate_par(&doc, &query, &query_ngram, threshold, n),
+                has_doc_duplicate_rolling(&doc, &query, &query_ngram, threshold, n)
+            );
+        }
+        doc.truncate(10);
+        assert!(!has_doc_duplicate_par(&doc, &query, &query_ngram, 0.1, n));
+    }
+
+    #[test]
+    fn test_cluster_near_duplicates() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let doc = (0..100)
+            .map(|_| rng.gen_range(0..50254))
+            .collect::<Vec<i32>>();
+        let mut near_duplicate = doc.clone();
+        near_duplicate[50] = -1;
+        let distinct = (0..100)
+            .map(|_| rng.gen_range(0..50254))
+            .collect::<Vec<i32>>();
+        let docs = vec![doc, distinct, near_duplicate];
+        assert_eq!(cluster_near_duplicates(&docs, 0.8, 5), vec![vec![0, 2]]);
+        assert!(cluster_near_duplicates(&docs, 1.0, 5).is_empty());
+        // groups are linked through intermediate documents
+        let docs = vec![
+            vec![1, 2, 3, 4, 5, 6],
+            vec![9, 9, 9, 9, 9, 9],
+            vec![1, 2, 3, 4, 5, 0],
+            vec![0, 2, 3, 4, 5, 0],
+        ];
+        assert_eq!(cluster_near_duplicates(&docs, 0.7, 2), vec![vec![0, 2, 3]]);
+    }
+
+    #[test]
+    fn test_choose_hash_kind() {
+        assert_eq!(choose_hash_kind(1), HashKind::Fx);
+        assert_eq!(choose_hash_kind(HASH_KIND_CROSSOVER - 1), HashKind::Fx);
+        assert_eq!(choose_hash_kind(HASH_KIND_CROSSOVER), HashKind::Rolling);
+        assert_eq!(choose_hash_kind(HASH_KIND_CROSSOVER + 1), HashKind::Rolling);
+
+        let mut rng = StdRng::seed_from_u64(0);
+        let doc = (0..1000)
+            .map(|_| rng.gen_range(0..100))
+            .collect::<Vec<i32>>();
+        let mut query = doc[200..300].to_vec();
+        query[10] = -1;
+        for n in [3, HASH_KIND_CROSSOVER + 10] {
+            assert_eq!(
+                ngram_auto(&query, n),
+                ngram_with_kind(&query, n, choose_hash_kind(n))
+            );
+            assert!(has_doc_duplicate_auto(
+                &doc,
+                &query,
+                &ngram_auto(&query, n),
+                0.9,
+                n
+            ));
+        }
+    }
+
+    #[test]
+    fn test_ngram_hasher() {
+        /// Sum of the token ids, which ignores their order.
+        struct SumHasher;
+
+        impl NgramHasher for SumHasher {
+            fn hash_window(&self, window: &[i32]) -> usize {
+                window.iter().sum::<i32>() as usize
+            }
+        }
+
+        let query = vec![1, 2, 3, 4, 5];
+        assert_eq!(
+            ngram_with_hasher(&query, 2, &SumHasher),
+            [3, 5, 7, 9].into_iter().collect()
+        );
+        assert!(ngram_with_hasher(&query, 0, &SumHasher).is_empty());
+        let query_ngram = ngram_with_hasher(&query, 2, &SumHasher);
+        assert!(has_doc_duplicate_with_hasher(
+            &[9, 1, 2, 3, 4, 5, 9],
+            &query,
+            &query_ngram,
+            0.8,
+            2,
+            &SumHasher
+        ));
+        // [2, 1] has the sum of [1, 2], so the reordered span is a candidate
+        assert!(has_doc_duplicate_with_hasher(
+            &[9, 2, 1, 4, 3, 5, 9],
+            &query,
+            &query_ngram,
+            0.8,
+            2,
+            &SumHasher
+        ));
+        assert!(!has_doc_duplicate_with_hasher(
+            &[9, 1, 2, 9, 9, 9, 9],
+            &query,
+            &query_ngram,
+            0.8,
+            2,
+            &SumHasher
+        ));
+
+        let mut rng = StdRng::seed_from_u64(0);
+        let doc = (0..1000)
+            .map(|_| rng.gen_range(0..100))
+            .collect::<Vec<i32>>();
+        let n = 3;
+        for _ in 0..50 {
+            let start = rng.gen_range(0..900);
+            let mut query = doc[start..start + 50].to_vec();
+            for token in query.iter_mut() {
+                if rng.gen_bool(0.2) {
+                    *token = rng.gen_range(0..100);
+                }
+            }
+            let threshold = rng.gen_range(0.3..0.9);
+            let expected = has_doc_duplicate(&doc, &query, &ngram(&query, n), threshold, n);
+            assert_eq!(
+                ngram_with_hasher(&query, n, &FxNgramHasher),
+                ngram(&query, n)
+            );
+            assert_eq!(
+                ngram_with_hasher(&query, n, &RollingNgramHasher),
+                ngram_rolling(&query, n)
+            );
+            for hasher in [
+                &FxNgramHasher as &dyn NgramHasher,
+                &RollingNgramHasher,
+                &SumHasher,
+            ] {
+                let query_ngram = ngram_with_hasher(&query, n, hasher);
+                assert_eq!(
+                    has_doc_duplicate_with_hasher(&doc, &query, &query_ngram, threshold, n, hasher),
+                    expected
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_has_doc_duplicate_with_kind() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let doc = (0..1000)
+            .map(|_| rng.gen_range(0..100))
+            .collect::<Vec<i32>>();
+        let n = 3;
+        for _ in 0..50 {
+            let start = rng.gen_range(0..900);
+            let mut query = doc[start..start + 50].to_vec();
+            for token in query.iter_mut() {
+                if rng.gen_bool(0.2) {
+                    *token = rng.gen_range(0..100);
+                }
+            }
+            let threshold = rng.gen_range(0.3..0.9);
+            let fx = has_doc_duplicate_with_kind(
+                &doc,
+                &query,
+                &ngram_with_kind(&query, n, HashKind::Fx),
+                threshold,
+                n,
+                HashKind::Fx,
+            );
+            let rolling = has_doc_duplicate_with_kind(
+                &doc,
+                &query,
+                &ngram_with_kind(&query, n, HashKind::Rolling),
+                threshold,
+                n,
+                HashKind::Rolling,
+            );
+            assert_eq!(fx, rolling);
+        }
+    }
+
+    #[test]
+    fn test_matcher() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let doc = (0..1000)
+            .map(|_| rng.gen_range(0..50254))
+            .collect::<Vec<i32>>();
+        let mut query = doc[300..400].to_vec();
+        for token in query.iter_mut().step_by(10) {
+            *token = -1;
+        }
+        for hash in [HashKind::Fx, HashKind::Rolling] {
+            let matcher = Matcher::builder()
+                .n(5)
+                .threshold(0.8)
+                .hash(hash)
+                .build(query.clone());
+            assert_eq!(matcher.query(), &query[..]);
+            assert!(matcher.contains(&doc));
+            assert!(!matcher.contains(&doc[..350]));
+            let matcher = Matcher::builder()
+                .n(5)
+                .threshold(0.9)
+                .hash(hash)
+                .build(query.clone());
+            assert!(!matcher.contains(&doc));
+        }
+    }
+
+    #[test]
+    fn test_matcher_contains_batch() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let query = (0..30).map(|_| rng.gen_range(0..20)).collect::<Vec<i32>>();
+        let docs = (0..50)
+            .map(|i| {
+                let mut doc = (0..rng.gen_range(0..200))
+                    .map(|_| rng.gen_range(0..50))
+                    .collect::<Vec<i32>>();
+                // every fourth document contains the query
+                if i % 4 == 0 {
+                    doc.extend(&query);
+                }
+                doc
+            })
+            .collect::<Vec<_>>();
+        for hash in [HashKind::Fx, HashKind::Rolling] {
+            for threshold in [0.5, 0.7, 1.0] {
+                let matcher = Matcher::builder()
+                    .n(3)
+                    .threshold(threshold)
+                    .hash(hash)
+                    .build(query.clone());
+                let expected = docs
+                    .iter()
+                    .map(|doc| matcher.contains(doc))
+                    .collect::<Vec<bool>>();
+                assert!(expected.contains(&true) && expected.contains(&false));
+                assert_eq!(matcher.contains_batch(&docs), expected);
+                #[cfg(feature = "parallel")]
+                assert_eq!(matcher.contains_batch_par(&docs), expected);
+            }
+        }
+        let matcher = Matcher::builder().n(3).build(query);
+        assert!(matcher.contains_batch(&[]).is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_iter_matches() {
+        let matcher = Matcher::builder()
+            .n(3)
+            .threshold(1.0)
+            .build(vec![5, 6, 7, 8]);
+        let lines = [
+            r#"{"token_ids": [1, 2, 3, 4, 5, 6, 7, 8]}"#,
+            "",
+            r#"{"token_ids": [5, 6, 7, 9], "text": "abc"}"#,
+            "not json",
+            r#"{"token_ids": [8, 7, 6, 5, 6, 7, 8]}"#,
+        ];
+        let reader = std::io::Cursor::new(lines.join("\n"));
+        let mut matches = iter_matches(reader, &matcher, "token_ids");
+        assert_eq!(matches.next(), Some((0, true)));
+        assert_eq!(
+            matches.collect::<Vec<_>>(),
+            vec![(1, false), (2, false), (3, false), (4, true)]
+        );
+        // the token ids are read from the given field, and lines without it do not match
+        let lines = [
+            r#"{"input_ids": [1, 2, 3, 4, 5, 6, 7, 8]}"#,
+            r#"{"token_ids": [5, 6, 7, 8]}"#,
+            r#"{"input_ids": "5 6 7 8"}"#,
+        ];
+        let reader = std::io::Cursor::new(lines.join("\n"));
+        assert_eq!(
+            iter_matches(reader, &matcher, "input_ids").collect::<Vec<_>>(),
+            vec![(0, true), (1, false), (2, false)]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid query")]
+    fn test_matcher_n_larger_than_query() {
+        Matcher::builder().n(10).build(vec![1, 2, 3]);
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_query_index_save_load() {
+        let index = QueryIndex::build((0..100).collect(), 10);
+        let path =
+            std::env::temp_dir().join(format!("neardup-query-index-{}.json", std::process::id()));
+        index.save(&path).unwrap();
+        let loaded = QueryIndex::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded, index);
+        assert_eq!(loaded.ngram().len(), 91);
+        assert!(QueryIndex::load(&path).is_err());
+    }
+
+    #[test]
+    #[allow(clippy::identity_op, clippy::useless_vec)]
+    fn test_rolling_hash() {
+        let text = vec![1, 2, 3, 4, 5];
+        let mut rolling_hash = RollingHash::new();
+        for c in text.iter().map(|v| *v as u64) {
+            rolling_hash.append(c);
+        }
+        assert_eq!(
+            rolling_hash.hash,
+            (1 * u64::pow(31, 4) + 2 * u64::pow(31, 3) + 3 * u64::pow(31, 2) + 4 * 31 + 5)
+                % 1_000_000_007
+        );
+    }
+
+    #[test]
+    fn test_pop_front() {
+        let mut rolling_hash = RollingHash::new();
+        for c in [1, 2, 3, 4, 5] {
+            rolling_hash.append(c);
+        }
+        rolling_hash.pop_front(1);
+        rolling_hash.pop_front(2);
+        let mut expected = RollingHash::new();
+        for c in [3, 4, 5] {
+            expected.append(c);
+        }
+        assert_eq!(rolling_hash.get_hash(), expected.get_hash());
+        assert_eq!(rolling_hash.window_size, 3);
+        assert_eq!(rolling_hash.base_power, expected.base_power);
+
+        // the window stays consistent when it grows and slides again
+        rolling_hash.append(6);
+        expected.append(6);
+        rolling_hash.slide(3, 7);
+        expected.slide(3, 7);
+        assert_eq!(rolling_hash.get_hash(), expected.get_hash());
+
+        for c in [4, 5, 6, 7] {
+            rolling_hash.pop_front(c);
+        }
+        assert_eq!(rolling_hash.get_hash(), 0);
+        assert_eq!(rolling_hash.window_size, 0);
+    }
+
+    #[test]
+    fn test_rolling_hash_clone() {
+        let mut rolling_hash = RollingHash::from_slice(&[1, 2, 3]);
+        let mut fork = rolling_hash.clone();
+        fork.slide(1, 4);
+        assert_eq!(
+            rolling_hash.get_hash(),
+            RollingHash::from_slice(&[1, 2, 3]).get_hash()
+        );
+        assert_eq!(
+            fork.get_hash(),
+            RollingHash::from_slice(&[2, 3, 4]).get_hash()
+        );
+        // both continuations keep sliding independently
+        rolling_hash.slide(1, 5);
+        fork.slide(2, 6);
+        assert_eq!(
+            rolling_hash.get_hash(),
+            RollingHash::from_slice(&[2, 3, 5]).get_hash()
+        );
+        assert_eq!(
+            fork.get_hash(),
+            RollingHash::from_slice(&[3, 4, 6]).get_hash()
+        );
+        assert_eq!(
+            RollingHash::default().get_hash(),
+            RollingHash::new().get_hash()
+        );
+        assert!(format!("{:?}", rolling_hash).starts_with("RollingHash"));
+    }
+
+    #[test]
+    fn test_bulk_append_and_slide() {
+        let text = [7, 1, 2, 3, 4, 5, 6, 8, 9, 10, 11, 12, 13];
+        let base = 1_000_000_000_039;
+        let modulo = (1 << 61) - 1;
+        let mut bulk = RollingHash::with_params(base, modulo);
+        let mut single = RollingHash::with_params(base, modulo);
+        bulk.append_slice(&text[..4]);
+        for c in &text[..4] {
+            single.append(*c);
+        }
+        assert_eq!(bulk.get_hash(), single.get_hash());
+        assert_eq!(bulk.window_size(), single.window_size());
+        // slide by fewer and more positions than the window size
+        for (start, step) in [(0, 1), (1, 3), (4, 5)] {
+            let old_chars = &text[start..start + step];
+            let new_chars = &text[start + 4..start + 4 + step];
+            bulk.slide_slice(old_chars, new_chars);
+            for (old_char, new_char) in old_chars.iter().zip(new_chars) {
+                single.slide(*old_char, *new_char);
+            }
+            assert_eq!(bulk.get_hash(), single.get_hash());
+            assert_eq!(
+                bulk.get_hash(),
+                RollingHash::from_params_slice(base, modulo, &text[start + step..start + step + 4])
+                    .get_hash()
+            );
+        }
+        bulk.slide_slice(&[], &[]);
+        assert_eq!(bulk.get_hash(), single.get_hash());
+    }
+
+    #[test]
+    #[should_panic(expected = "old_chars and new_chars must have the same length")]
+    fn test_slide_slice_length_mismatch() {
+        RollingHash::from_slice(&[1, 2, 3]).slide_slice(&[1], &[4, 5]);
+    }
+
+    #[test]
+    fn test_window_size() {
+        let mut rolling_hash = RollingHash::new();
+        assert_eq!(rolling_hash.window_size(), 0);
+        for (i, c) in [1, 2, 3].into_iter().enumerate() {
+            rolling_hash.append(c);
+            assert_eq!(rolling_hash.window_size(), i + 1);
+        }
+        rolling_hash.slide(1, 4);
+        assert_eq!(rolling_hash.window_size(), 3);
+        rolling_hash.pop_front(2);
+        assert_eq!(rolling_hash.window_size(), 2);
+        rolling_hash.reset();
+        assert_eq!(rolling_hash.window_size(), 0);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "slide on an empty window")]
+    fn test_slide_empty_window() {
+        RollingHash::new().slide(1, 2);
+    }
+
+    #[test]
+    fn test_reset() {
+        let base = 1_000_000_000_039;
+        let modulo = (1 << 61) - 1;
+        let mut rolling_hash = RollingHash::with_params(base, modulo);
+        for c in [5, 6, 7, 8] {
+            rolling_hash.append(c);
+        }
+        rolling_hash.slide(5, 9);
+        rolling_hash.reset();
+        assert_eq!(rolling_hash.get_hash(), 0);
+        for c in [1, 2, 3] {
+            rolling_hash.append(c);
+        }
+        let fresh = RollingHash::from_params_slice(base, modulo, &[1, 2, 3]);
+        assert_eq!(rolling_hash.get_hash(), fresh.get_hash());
+        // the window size and base power are reset too
+        rolling_hash.slide(1, 4);
+        rolling_hash.pop_front(2);
+        assert_eq!(
+            rolling_hash.get_hash(),
+            RollingHash::from_params_slice(base, modulo, &[3, 4]).get_hash()
+        );
+    }
+
+    #[test]
+    fn test_rolling_hash_with_params() {
+        // [0, 1, 0] and [0, 0, 31] collide under the default base 31
+        let hash = |mut rolling_hash: RollingHash, text: &[u64]| {
+            for c in text {
+                rolling_hash.append(*c);
+            }
+            rolling_hash.get_hash()
+        };
+        assert_eq!(
+            hash(RollingHash::new(), &[0, 1, 0]),
+            hash(RollingHash::new(), &[0, 0, 31])
+        );
+        let base = 1_000_000_000_039;
+        let modulo = (1 << 61) - 1;
+        assert_ne!(
+            hash(RollingHash::with_params(base, modulo), &[0, 1, 0]),
+            hash(RollingHash::with_params(base, modulo), &[0, 0, 31])
+        );
+
+        // slide and pop_front work without overflow on large parameters
+        let mut rolling_hash = RollingHash::with_params(base, modulo);
+        for c in [u64::MAX, 2, 3] {
+            rolling_hash.append(c);
+        }
+        rolling_hash.slide(u64::MAX, 4);
+        assert_eq!(
+            rolling_hash.get_hash(),
+            hash(RollingHash::with_params(base, modulo), &[2, 3, 4])
+        );
+        rolling_hash.pop_front(2);
+        assert_eq!(
+            rolling_hash.get_hash(),
+            hash(RollingHash::with_params(base, modulo), &[3, 4])
+        );
+    }
+
+    #[test]
+    #[allow(clippy::identity_op, clippy::useless_vec)]
+    fn test_update() {
+        let text = vec![1, 2, 3, 4, 5];
+        let mut rolling_hash = RollingHash::new();
+        for c in text.iter().map(|v| *v as u64) {
+            rolling_hash.append(c);
+        }
+        assert_eq!(
+            rolling_hash.hash,
+            (1 * u64::pow(31, 4) + 2 * u64::pow(31, 3) + 3 * u64::pow(31, 2) + 4 * 31 + 5)
+                % 1_000_000_007
+        );
+        println!("{:?}", rolling_hash.hash);
+        rolling_hash.slide(1, 6);
+        assert_eq!(
+            rolling_hash.hash,
+            (2 * u64::pow(31, 4) + 3 * u64::pow(31, 3) + 4 * u64::pow(31, 2) + 5 * 31 + 6)
+                % 1_000_000_007
+        );
+    }
 }
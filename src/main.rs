@@ -1,19 +1,30 @@
 use clap::Parser;
-use env_logger;
 use flate2::read::GzDecoder;
-use log::info;
-use neardup::{has_doc_duplicate, ngram};
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use log::{info, warn};
+use neardup::{
+    best_doc_similarity, find_doc_duplicate_policy, has_doc_duplicate, has_doc_duplicate_naive,
+    ngram, ngram_batch_par, InnerPolicy,
+};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 use rayon::prelude::*;
 use rustc_hash::FxHashSet as HashSet;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use std::cmp::max;
+use std::collections::BTreeMap;
 use std::env;
 use std::fs;
 use std::fs::File;
 use std::io::BufRead;
 use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::Read;
+use std::io::Write;
 use std::path::Path;
 use std::path::PathBuf;
+use std::sync::Mutex;
 
 /// Read all files in a directory recursively.
 fn read_dir_recursive(dir_path: impl AsRef<Path>) -> Vec<PathBuf> {
@@ -41,65 +52,758 @@ fn read_dir_recursive(dir_path: impl AsRef<Path>) -> Vec<PathBuf> {
     all_paths
 }
 
-/// Convert a JSON string to a list of token ids.
-fn convert_to_token_ids(line: String) -> Vec<i32> {
-    let json_data: Value = serde_json::from_str(&line).expect("Failed to parse JSON");
-    if let Some(token_ids) = json_data["token_ids"].as_array() {
+/// Format of the query and document files.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default)]
+enum InputFormat {
+    /// one JSON object per line with a `token_ids` field
+    #[default]
+    Jsonl,
+    /// whitespace-separated token ids per line
+    Plain,
+}
+
+/// Convert a line of whitespace-separated integers to a list of token ids.
+///
+/// Returns `None` if any token is not an integer.
+fn parse_plain_token_ids(line: &str) -> Option<Vec<i32>> {
+    line.split_whitespace()
+        .map(|token| token.parse().ok())
+        .collect()
+}
+
+/// Convert a JSON string to a list of token ids read from `token_field`.
+///
+/// Returns `None` if the line is not valid JSON.
+/// Returns an empty list and logs a warning if the field is missing or is not an array.
+fn convert_to_token_ids(line: String, token_field: &str) -> Option<Vec<i32>> {
+    let json_data: Value = serde_json::from_str(&line).ok()?;
+    if let Some(token_ids) = json_data[token_field].as_array() {
         let token_ids: Vec<i32> = token_ids
             .iter()
             .filter_map(|v| v.as_i64())
             .map(|v| v as i32)
             .collect();
-        return token_ids;
+        return Some(token_ids);
     }
-    Vec::new()
+    warn!("field {:?} is missing in a line", token_field);
+    Some(Vec::new())
 }
 
-/// Search for near-duplicate spans in a document.
-fn search(query: &Vec<Vec<i32>>, path: &str, threshold: f32, n: usize) -> Vec<i32> {
-    let query_list = query.clone();
-    let query_ngram_list = query_list
-        .iter()
-        .map(|query| ngram(query, n))
-        .collect::<Vec<HashSet<usize>>>();
+/// Open a file for reading lines, decompressing it according to its extension (`.gz`, `.zst`, or plain).
+fn open_reader(path: &Path) -> std::io::Result<Box<dyn BufRead>> {
+    let file = File::open(path)?;
+    Ok(
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("gz") => Box::new(BufReader::new(GzDecoder::new(file))),
+            Some("zst") => Box::new(BufReader::new(zstd::Decoder::new(file)?)),
+            _ => Box::new(BufReader::new(file)),
+        },
+    )
+}
 
-    let file = File::open(path).expect("Failed to open file");
-    //let reader = BufReader::new(MultiGzDecoder::new(file));
-    let reader = BufReader::new(GzDecoder::new(file));
-    let query_num = query_list.len();
+/// An error opening, decompressing or reading a file to search.
+#[derive(Debug)]
+struct ReadError {
+    path: PathBuf,
+    /// the 1-based line number of a malformed line rejected by `--strict`
+    line: Option<usize>,
+    source: std::io::Error,
+}
 
-    info!("path: {:?} start loading token_ids_list", path);
+impl std::fmt::Display for ReadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.line {
+            Some(line) => write!(
+                f,
+                "failed to read {:?} line {}: {}",
+                self.path, line, self.source
+            ),
+            None => write!(f, "failed to read {:?}: {}", self.path, self.source),
+        }
+    }
+}
+
+impl std::error::Error for ReadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+impl From<ReadError> for std::io::Error {
+    fn from(err: ReadError) -> Self {
+        std::io::Error::new(err.source.kind(), err)
+    }
+}
+
+/// Whether an error is a malformed line rejected by `--strict`, which aborts the search even without `--fail-fast`.
+fn is_malformed_line(err: &std::io::Error) -> bool {
+    err.get_ref()
+        .and_then(|err| err.downcast_ref::<ReadError>())
+        .is_some_and(|err| err.line.is_some())
+}
+
+/// How documents are read from the files to search.
+struct DocumentReader {
+    input_format: InputFormat,
+    token_field: String,
+    /// fail on a malformed line instead of skipping it
+    strict: bool,
+    /// directory caching the parsed token ids of each file
+    cache_dir: Option<PathBuf>,
+}
+
+impl DocumentReader {
+    /// Read the token ids of each document in a file, from the cache if it is newer than the file.
+    ///
+    /// The parsed token ids are written to the cache after reading a file that is not cached yet.
+    fn read(&self, path: &Path) -> Result<Vec<Vec<i32>>, ReadError> {
+        let Some(cache_dir) = &self.cache_dir else {
+            return self.parse(path);
+        };
+        let cache_path = self.cache_path(cache_dir, path);
+        let modified = fs::metadata(path).and_then(|metadata| metadata.modified());
+        let Ok(modified) = modified else {
+            return self.parse(path);
+        };
+        if let Ok(Some(token_ids_list)) = read_cache(&cache_path, modified) {
+            info!("path: {:?} loaded from cache {:?}", path, cache_path);
+            return Ok(token_ids_list);
+        }
+        let token_ids_list = self.parse(path)?;
+        if let Err(err) = write_cache(&cache_path, modified, &token_ids_list) {
+            warn!("failed to write cache {:?}: {}", cache_path, err);
+        }
+        Ok(token_ids_list)
+    }
+
+    /// Path of the cache of a file, keyed by the file path and the options that affect parsing.
+    ///
+    /// `strict` is part of the key, as a cache written without it has the malformed lines dropped silently.
+    fn cache_path(&self, cache_dir: &Path, path: &Path) -> PathBuf {
+        let key = fxhash::hash64(&(
+            path.canonicalize().unwrap_or_else(|_| path.to_path_buf()),
+            format!("{:?}", self.input_format),
+            &self.token_field,
+            self.strict,
+        ));
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        cache_dir.join(format!("{}-{:016x}.tokens", file_name, key))
+    }
+
+    /// Parse the token ids of each document in a file, skipping blank lines.
+    ///
+    /// Malformed lines, i.e. invalid JSON or non-integer plain tokens, are skipped and counted in a warning,
+    /// or are an `InvalidData` error with the line number if `strict` is set.
+    /// Failing to open, decompress or read the file is an error.
+    fn parse(&self, path: &Path) -> Result<Vec<Vec<i32>>, ReadError> {
+        let read_error = |source| ReadError {
+            path: path.to_path_buf(),
+            line: None,
+            source,
+        };
+        let reader = open_reader(path).map_err(read_error)?;
+        let mut token_ids_list = Vec::new();
+        let mut malformed = 0;
+        for (line_index, line) in reader.lines().enumerate() {
+            let line = line.map_err(read_error)?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let token_ids = match self.input_format {
+                InputFormat::Jsonl => convert_to_token_ids(line, &self.token_field),
+                InputFormat::Plain => parse_plain_token_ids(&line),
+            };
+            match token_ids {
+                Some(token_ids) => token_ids_list.push(token_ids),
+                None if self.strict => {
+                    return Err(ReadError {
+                        path: path.to_path_buf(),
+                        line: Some(line_index + 1),
+                        source: std::io::Error::new(
+                            std::io::ErrorKind::InvalidData,
+                            "malformed line",
+                        ),
+                    });
+                }
+                None => malformed += 1,
+            }
+        }
+        if malformed > 0 {
+            warn!("path: {:?} skipped {:?} malformed lines", path, malformed);
+        }
+        Ok(token_ids_list)
+    }
+}
+
+/// Magic bytes at the start of a cache file written by `write_cache`.
+const CACHE_MAGIC: &[u8; 8] = b"NDTOKEN1";
+
+/// Write the token ids of a file along with its modification time.
+///
+/// The cache holds the magic bytes, the modification time in nanoseconds since the epoch as a `u128`,
+/// the number of documents as a `u64`, and the length and token ids of each document, all little-endian.
+fn write_cache(
+    cache_path: &Path,
+    modified: std::time::SystemTime,
+    token_ids_list: &[Vec<i32>],
+) -> std::io::Result<()> {
+    let modified = modified
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_err(std::io::Error::other)?;
+    // write to a temporary file first so that an interrupted run does not leave a truncated cache
+    let tmp_path = cache_path.with_extension("tmp");
+    let mut writer = BufWriter::new(File::create(&tmp_path)?);
+    writer.write_all(CACHE_MAGIC)?;
+    writer.write_all(&modified.as_nanos().to_le_bytes())?;
+    writer.write_all(&(token_ids_list.len() as u64).to_le_bytes())?;
+    for token_ids in token_ids_list {
+        writer.write_all(&(token_ids.len() as u64).to_le_bytes())?;
+        for token in token_ids {
+            writer.write_all(&token.to_le_bytes())?;
+        }
+    }
+    writer.into_inner().map_err(|err| err.into_error())?;
+    fs::rename(tmp_path, cache_path)
+}
+
+/// Read the token ids written by `write_cache`, or `None` if the cache was written for another modification time.
+fn read_cache(
+    cache_path: &Path,
+    modified: std::time::SystemTime,
+) -> std::io::Result<Option<Vec<Vec<i32>>>> {
+    let modified = modified
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_err(std::io::Error::other)?;
+    let mut reader = BufReader::new(File::open(cache_path)?);
+    let mut magic = [0; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != CACHE_MAGIC {
+        return Ok(None);
+    }
+    let mut nanos = [0; 16];
+    reader.read_exact(&mut nanos)?;
+    if u128::from_le_bytes(nanos) != modified.as_nanos() {
+        return Ok(None);
+    }
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    let num_docs = u64::from_le_bytes(bytes);
     let mut token_ids_list = Vec::new();
-    for line in reader.lines() {
-        if let Ok(line) = line {
-            let token_ids = convert_to_token_ids(line);
-            token_ids_list.push(token_ids);
+    for _ in 0..num_docs {
+        reader.read_exact(&mut bytes)?;
+        let mut token_bytes = vec![0; u64::from_le_bytes(bytes) as usize * 4];
+        reader.read_exact(&mut token_bytes)?;
+        token_ids_list.push(
+            token_bytes
+                .chunks_exact(4)
+                .map(|token| i32::from_le_bytes(token.try_into().unwrap()))
+                .collect(),
+        );
+    }
+    Ok(Some(token_ids_list))
+}
+
+/// Document and token counts of the corpus, reported by `--stats-only`.
+///
+/// `length_histogram[i]` counts the documents whose length falls in the bucket `i` of `length_bucket`.
+#[derive(Debug, Default, PartialEq)]
+struct CorpusStats {
+    documents: usize,
+    tokens: usize,
+    length_histogram: Vec<usize>,
+}
+
+impl CorpusStats {
+    /// Count the documents of a file and their lengths.
+    fn from_documents(token_ids_list: &[Vec<i32>]) -> Self {
+        let mut stats = CorpusStats::default();
+        for token_ids in token_ids_list {
+            stats.documents += 1;
+            stats.tokens += token_ids.len();
+            let bucket = length_bucket(token_ids.len());
+            if stats.length_histogram.len() <= bucket {
+                stats.length_histogram.resize(bucket + 1, 0);
+            }
+            stats.length_histogram[bucket] += 1;
         }
+        stats
     }
-    info!("loaded token_ids_list");
+
+    /// Add the counts of another file.
+    fn merge(mut self, other: CorpusStats) -> Self {
+        self.documents += other.documents;
+        self.tokens += other.tokens;
+        if self.length_histogram.len() < other.length_histogram.len() {
+            self.length_histogram
+                .resize(other.length_histogram.len(), 0);
+        }
+        for (count, other_count) in self.length_histogram.iter_mut().zip(other.length_histogram) {
+            *count += other_count;
+        }
+        self
+    }
+}
+
+/// Bucket of a document length: 0 for empty documents, otherwise `i` for lengths in `2^(i-1)..2^i`.
+fn length_bucket(len: usize) -> usize {
+    (usize::BITS - len.leading_zeros()) as usize
+}
+
+/// Range of document lengths in a bucket of `length_bucket`, such as `4-7`.
+fn bucket_label(bucket: usize) -> String {
+    match bucket {
+        0 => "0".to_string(),
+        1 => "1".to_string(),
+        _ => format!("{}-{}", 1usize << (bucket - 1), (1usize << bucket) - 1),
+    }
+}
+
+/// A near-duplicate span of a query found in a document.
+#[derive(Serialize, Deserialize)]
+struct MatchRecord {
+    file: String,
+    doc_index: usize,
+    query_index: usize,
+    start: usize,
+    end: usize,
+    similarity: f64,
+}
+
+/// Format the token ids of a span, printing at most `max_len` of them.
+fn format_span(span: &[i32], max_len: usize) -> String {
+    let mut items = span
+        .iter()
+        .take(max_len)
+        .map(|token| token.to_string())
+        .collect::<Vec<String>>();
+    if span.len() > max_len {
+        items.push(format!("... ({} more)", span.len() - max_len));
+    }
+    format!("[{}]", items.join(", "))
+}
+
+/// A near-duplicate span of a query found in a document by `search`.
+struct DocMatch {
+    doc_index: usize,
+    start: usize,
+    end: usize,
+    similarity: f64,
+}
+
+/// Search for near-duplicate spans in a document.
+///
+/// Returns the matches of each query, at most one per document.
+/// `query_ngram_list` holds the n-grams of each query, built once by the caller for all the files.
+/// Each query is matched with its own threshold in `query_thresholds` if set, otherwise with `threshold`.
+/// If `max_span_print` is set, the token ids of each matching span are logged, truncated to that length.
+fn search(
+    query_list: &[Vec<i32>],
+    query_ngram_list: &[HashSet<usize>],
+    token_ids_list: &[Vec<i32>],
+    threshold: f64,
+    query_thresholds: &[Option<f64>],
+    n: usize,
+    max_span_print: Option<usize>,
+) -> Vec<Vec<DocMatch>> {
+    let query_num = query_list.len();
 
     // multi thread per query
-    let count_list = (0..query_num)
+    let match_list = (0..query_num)
         .into_par_iter()
         .map(|i| {
             let query = &query_list[i];
             let query_ngram = &query_ngram_list[i];
-            let mut count = 0;
+            let threshold = query_thresholds[i].unwrap_or(threshold);
+            let mut matches = Vec::new();
 
-            for token_ids in &token_ids_list {
-                if has_doc_duplicate(token_ids.clone(), &query, &query_ngram, threshold as f64, n) {
-                    count += 1;
+            for (doc_index, token_ids) in token_ids_list.iter().enumerate() {
+                if let Some((start, end, similarity)) = find_doc_duplicate_policy(
+                    token_ids,
+                    query,
+                    query_ngram,
+                    threshold,
+                    n,
+                    InnerPolicy::FirstMatch,
+                ) {
+                    if let Some(max_len) = max_span_print {
+                        info!(
+                            "query: {:?} doc: {:?} span: {:?}..{:?} {}",
+                            i,
+                            doc_index,
+                            start,
+                            end,
+                            format_span(&token_ids[start..end], max_len)
+                        );
+                    }
+                    matches.push(DocMatch {
+                        doc_index,
+                        start,
+                        end,
+                        similarity,
+                    });
                 }
             }
-            info!("query: {:?} count: {:?}", i, count);
-            count
+            info!("query: {:?} count: {:?}", i, matches.len());
+            matches
         })
-        .collect::<Vec<i32>>()
-        .try_into()
-        .unwrap();
-    count_list
+        .collect::<Vec<Vec<DocMatch>>>();
+    match_list
+}
+
+/// The maximum similarity of a document against a query.
+#[derive(Serialize, Deserialize)]
+struct ScoreRecord {
+    file: String,
+    doc_index: usize,
+    query_index: usize,
+    similarity: f64,
+}
+
+/// Compute the maximum similarity of each document against each query, in the order of the documents.
+fn score(
+    query_list: &[Vec<i32>],
+    path: &str,
+    token_ids_list: &[Vec<i32>],
+    n: usize,
+) -> Vec<Vec<ScoreRecord>> {
+    query_list
+        .par_iter()
+        .enumerate()
+        .map(|(query_index, query)| {
+            let query_ngram = ngram(query, n);
+            token_ids_list
+                .iter()
+                .enumerate()
+                .map(|(doc_index, token_ids)| ScoreRecord {
+                    file: path.to_string(),
+                    doc_index,
+                    query_index,
+                    similarity: best_doc_similarity(token_ids, query, &query_ngram, n),
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Counts of similarities in equal-width bins over `[0, 1]`, written by `--histogram`.
+#[derive(Debug, PartialEq)]
+struct SimilarityHistogram {
+    counts: Vec<usize>,
+}
+
+impl SimilarityHistogram {
+    fn new(bins: usize) -> Self {
+        assert!(bins > 0, "the number of bins must be positive");
+        SimilarityHistogram {
+            counts: vec![0; bins],
+        }
+    }
+
+    /// Count a similarity in its bin; a similarity of 1.0 falls in the last bin.
+    fn add(&mut self, similarity: f64) {
+        let bins = self.counts.len();
+        let bin = ((similarity * bins as f64) as usize).min(bins - 1);
+        self.counts[bin] += 1;
+    }
+
+    /// Add the counts of another histogram with the same bins.
+    fn merge(mut self, other: SimilarityHistogram) -> Self {
+        for (count, other_count) in self.counts.iter_mut().zip(other.counts) {
+            *count += other_count;
+        }
+        self
+    }
+
+    /// Write the bins as CSV with the columns `bin_start,bin_end,count`.
+    fn write_csv(&self, path: &Path) -> std::io::Result<()> {
+        let bins = self.counts.len();
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "bin_start,bin_end,count")?;
+        for (bin, count) in self.counts.iter().enumerate() {
+            writeln!(
+                writer,
+                "{},{},{}",
+                bin as f64 / bins as f64,
+                (bin + 1) as f64 / bins as f64,
+                count
+            )?;
+        }
+        writer.flush()
+    }
+}
+
+/// Bin the maximum similarity of every document against every query, as computed by `score`.
+///
+/// A file that cannot be read is logged and skipped, or aborts if `--fail-fast` is set.
+fn similarity_histogram(
+    query_list: &[Vec<i32>],
+    search_path_list: &[&PathBuf],
+    args: &Args,
+    document_reader: &DocumentReader,
+) -> std::io::Result<SimilarityHistogram> {
+    search_path_list
+        .par_iter()
+        .map(|path| -> std::io::Result<SimilarityHistogram> {
+            let mut histogram = SimilarityHistogram::new(args.bins as usize);
+            let token_ids_list = match document_reader.read(path) {
+                Ok(token_ids_list) => token_ids_list,
+                Err(err) if args.fail_fast => return Err(err.into()),
+                Err(err) => {
+                    warn!("{}", err);
+                    return Ok(histogram);
+                }
+            };
+            let path = path.to_str().unwrap();
+            for record in score(query_list, path, &token_ids_list, args.n)
+                .iter()
+                .flatten()
+            {
+                histogram.add(record.similarity);
+            }
+            info!("path: {:?} finished", path);
+            Ok(histogram)
+        })
+        .try_reduce(
+            || SimilarityHistogram::new(args.bins as usize),
+            |a, b| Ok(a.merge(b)),
+        )
+}
+
+/// Write records to the writer as JSONL.
+fn write_records<T: Serialize>(writer: &mut impl Write, records: &[Vec<T>]) -> std::io::Result<()> {
+    for record in records.iter().flatten() {
+        serde_json::to_writer(&mut *writer, record)?;
+        writeln!(writer)?;
+    }
+    Ok(())
+}
+
+/// The file a query was read from and the index of the query within that file.
+#[derive(Clone, Debug, PartialEq)]
+struct QuerySource {
+    file: String,
+    index: usize,
+    /// threshold of the query overriding `--threshold`, read from the `threshold` field of a JSONL query
+    threshold: Option<f64>,
+}
+
+/// Read the queries of a file, one per non-blank line, along with their own threshold if any.
+fn read_queries(
+    path: &Path,
+    input_format: InputFormat,
+    token_field: &str,
+) -> std::io::Result<Vec<(Vec<i32>, Option<f64>)>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut query_list = Vec::new();
+    for (line_index, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let query = match input_format {
+            InputFormat::Jsonl => {
+                let v: Value = serde_json::from_str(&line).unwrap();
+                (
+                    serde_json::from_value(v[token_field].clone()).unwrap(),
+                    v.get("threshold").and_then(Value::as_f64),
+                )
+            }
+            InputFormat::Plain => {
+                let query = parse_plain_token_ids(&line).ok_or_else(|| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!("invalid token id in {:?} line {}", path, line_index + 1),
+                    )
+                })?;
+                (query, None)
+            }
+        };
+        query_list.push(query);
+    }
+    Ok(query_list)
+}
+
+/// Read the queries of all files into one list, along with the source of each query.
+fn load_queries(
+    paths: &[String],
+    input_format: InputFormat,
+    token_field: &str,
+) -> std::io::Result<(Vec<Vec<i32>>, Vec<QuerySource>)> {
+    let mut query_list = Vec::new();
+    let mut query_sources = Vec::new();
+    for path in paths {
+        let queries = read_queries(Path::new(path), input_format, token_field)?;
+        info!("query path: {:?} queries: {:?}", path, queries.len());
+        for (index, (query, threshold)) in queries.into_iter().enumerate() {
+            query_list.push(query);
+            query_sources.push(QuerySource {
+                file: path.clone(),
+                index,
+                threshold,
+            });
+        }
+    }
+    Ok((query_list, query_sources))
+}
+
+/// The matches of each query and the number of tokens searched, summed over files.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+struct MatchCounts {
+    /// The number of documents matching each query.
+    count: Vec<usize>,
+    /// The total length of the matching spans of each query.
+    matched_tokens: Vec<usize>,
+    /// The number of tokens of the searched documents.
+    total_tokens: usize,
+}
+
+impl MatchCounts {
+    /// Create the counts of `query_num` queries over no files.
+    fn new(query_num: usize) -> Self {
+        Self {
+            count: vec![0; query_num],
+            matched_tokens: vec![0; query_num],
+            total_tokens: 0,
+        }
+    }
+
+    /// Add the counts of other files.
+    fn add(&mut self, other: &MatchCounts) {
+        for (c, c_other) in self.count.iter_mut().zip(&other.count) {
+            *c += c_other;
+        }
+        for (m, m_other) in self.matched_tokens.iter_mut().zip(&other.matched_tokens) {
+            *m += m_other;
+        }
+        self.total_tokens += other.total_tokens;
+    }
+
+    /// The fraction of the searched tokens covered by the matching spans of each query.
+    ///
+    /// A query matches at most one span per document, so the density is at most 1.
+    fn density(&self) -> Vec<f64> {
+        self.matched_tokens
+            .iter()
+            .map(|&matched_tokens| {
+                if self.total_tokens > 0 {
+                    matched_tokens as f64 / self.total_tokens as f64
+                } else {
+                    0.0
+                }
+            })
+            .collect()
+    }
+}
+
+/// The number of documents matching a query and the density of its matches, written by `--summary`.
+///
+/// `source_file` and `source_index` locate the query in the query files.
+#[derive(Serialize, Deserialize)]
+struct QuerySummary {
+    query_index: usize,
+    source_file: String,
+    source_index: usize,
+    count: usize,
+    matched_tokens: usize,
+    density: f64,
+}
+
+/// Write the per-query counts as JSON if the path ends with `.json`, otherwise as CSV.
+fn write_summary(
+    path: &Path,
+    counts: &MatchCounts,
+    sources: &[QuerySource],
+) -> std::io::Result<()> {
+    let summary = counts
+        .count
+        .iter()
+        .zip(&counts.matched_tokens)
+        .zip(counts.density())
+        .zip(sources)
+        .enumerate()
+        .map(
+            |(query_index, (((&count, &matched_tokens), density), source))| QuerySummary {
+                query_index,
+                source_file: source.file.clone(),
+                source_index: source.index,
+                count,
+                matched_tokens,
+                density,
+            },
+        )
+        .collect::<Vec<QuerySummary>>();
+    let mut writer = BufWriter::new(File::create(path)?);
+    if path
+        .extension()
+        .is_some_and(|extension| extension == "json")
+    {
+        serde_json::to_writer_pretty(&mut writer, &summary)?;
+    } else {
+        writeln!(
+            writer,
+            "query_index,source_file,source_index,count,matched_tokens,density"
+        )?;
+        for row in &summary {
+            writeln!(
+                writer,
+                "{},{},{},{},{},{}",
+                row.query_index,
+                row.source_file,
+                row.source_index,
+                row.count,
+                row.matched_tokens,
+                row.density
+            )?;
+        }
+    }
+    writer.flush()
+}
+
+/// A random input on which `has_doc_duplicate` and `has_doc_duplicate_naive` disagree, reported by `--verify`.
+#[derive(Debug)]
+struct Disagreement {
+    doc: Vec<i32>,
+    query: Vec<i32>,
+    threshold: f64,
+    n: usize,
+    fast: bool,
+    naive: bool,
+}
+
+/// Compare `has_doc_duplicate` with `has_doc_duplicate_naive` on `num_cases` random inputs generated from `seed`.
+///
+/// The fast path only compares spans sharing an n-gram with the query, so it may miss a match of the naive method when `n > 1`.
+/// A disagreement is reported when the fast path finds a match the naive method does not, or when they differ with `n == 1`.
+fn verify(num_cases: usize, seed: u64) -> Vec<Disagreement> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut disagreements = Vec::new();
+    for _ in 0..num_cases {
+        // a small vocabulary so that near-duplicates are common
+        let doc = (0..rng.gen_range(0..100))
+            .map(|_| rng.gen_range(0..10))
+            .collect::<Vec<i32>>();
+        let query = (0..rng.gen_range(1..20))
+            .map(|_| rng.gen_range(0..10))
+            .collect::<Vec<i32>>();
+        let threshold = [0.3, 0.5, 0.6, 0.8, 1.0][rng.gen_range(0..5)];
+        let n = rng.gen_range(1..=4);
+        let fast = has_doc_duplicate(&doc, &query, &ngram(&query, n), threshold, n);
+        let naive = has_doc_duplicate_naive(&doc, &query, threshold);
+        if (fast && !naive) || (n == 1 && fast != naive) {
+            disagreements.push(Disagreement {
+                doc,
+                query,
+                threshold,
+                n,
+                fast,
+                naive,
+            });
+        }
+    }
+    disagreements
 }
 
+#[allow(dead_code)]
 #[derive(Serialize, Deserialize)]
 struct MyData {
     iteration: u32,
@@ -112,6 +816,7 @@ struct MyData {
     metrics: Vec<f64>,
 }
 
+#[allow(dead_code)]
 #[derive(Serialize, Deserialize)]
 struct CompletionStats {
     count: u32,
@@ -125,13 +830,18 @@ struct Args {
     #[arg(long, default_value = "path/to/sample_data")]
     search_dir: String,
 
-    /// query path
-    #[arg(short, long, default_value = "path/to/sample_data/query.jsonl")]
-    query_path: String,
+    /// query paths, repeated or comma-separated
+    #[arg(
+        short,
+        long,
+        value_delimiter = ',',
+        default_value = "path/to/sample_data/query.jsonl"
+    )]
+    query_path: Vec<String>,
 
-    /// similarity threshold
+    /// similarity threshold, parsed as f64 like the similarity so that boundary values such as 0.6 compare exactly
     #[arg(short, long, default_value_t = 0.6)]
-    threshold: f32,
+    threshold: f64,
 
     /// ngram size
     #[arg(short, long, default_value_t = 10)]
@@ -144,25 +854,484 @@ struct Args {
     /// end file idx
     #[arg(long, default_value_t = 142)]
     end_file_idx: usize,
+
+    /// format of the query and document files
+    #[arg(long, value_enum, default_value_t = InputFormat::Jsonl)]
+    input_format: InputFormat,
+
+    /// JSON field holding the token ids in the query and document files
+    #[arg(long, default_value = "token_ids")]
+    token_field: String,
+
+    /// output path to write the matching spans as JSONL
+    #[arg(long)]
+    output: Option<String>,
+
+    /// output path to write the per-query counts as CSV, or JSON if it ends with `.json`
+    #[arg(long)]
+    summary: Option<String>,
+
+    /// number of files to process concurrently
+    #[arg(long, default_value_t = 1, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    file_parallelism: usize,
+
+    /// total number of threads matching the queries, divided among the files processed concurrently; all cores if unset
+    #[arg(long)]
+    threads: Option<usize>,
+
+    /// path of a JSON file saving the counts after each file, from which an interrupted run resumes
+    #[arg(long)]
+    checkpoint: Option<PathBuf>,
+
+    /// disable the progress bar
+    #[arg(long)]
+    quiet: bool,
+
+    /// log the token ids of each matching span
+    #[arg(long)]
+    show_spans: bool,
+
+    /// maximum number of token ids logged per span with `--show-spans`
+    #[arg(long, default_value_t = 32)]
+    max_span_print: usize,
+
+    /// report the number of documents and tokens of the files to search, without matching
+    #[arg(long)]
+    stats_only: bool,
+
+    /// abort on the first malformed line, reporting its file and line number, instead of skipping it
+    #[arg(long)]
+    strict: bool,
+
+    /// compare the fast and naive matching on this many random inputs instead of searching
+    #[arg(long)]
+    verify: Option<usize>,
+
+    /// random seed of `--verify`
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+
+    /// directory caching the parsed token ids of each file, reused while the file is unchanged
+    #[arg(long)]
+    cache_dir: Option<PathBuf>,
+
+    /// abort on the first file that cannot be read instead of logging it and continuing
+    #[arg(long)]
+    fail_fast: bool,
+
+    /// write the maximum similarity of every document against every query to the output instead of the matching spans
+    #[arg(long)]
+    score_mode: bool,
+
+    /// output path to write a histogram of the maximum similarity of every document against every query as CSV, without matching
+    #[arg(long)]
+    histogram: Option<PathBuf>,
+
+    /// number of bins of `--histogram` over [0, 1]
+    #[arg(long, default_value_t = 20, value_parser = clap::value_parser!(u32).range(1..))]
+    bins: u32,
+}
+
+/// The state of a search written by `--checkpoint`: the counts of each query over the completed files.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+struct Checkpoint {
+    counts: MatchCounts,
+    completed_files: Vec<String>,
+}
+
+impl Checkpoint {
+    /// Read a checkpoint written by `save`.
+    ///
+    /// A checkpoint that cannot be decoded, e.g. one saved by an older version with another format, is an `InvalidData` error.
+    fn load(path: &Path) -> std::io::Result<Self> {
+        let reader = BufReader::new(File::open(path)?);
+        serde_json::from_reader(reader).map_err(|err| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "incompatible checkpoint {:?}, delete it to start over: {}",
+                    path, err
+                ),
+            )
+        })
+    }
+
+    /// Write the checkpoint as JSON, replacing the previous one only once it is fully written.
+    fn save(&self, path: &Path) -> std::io::Result<()> {
+        let tmp_path = path.with_extension("tmp");
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        serde_json::to_writer(&mut writer, self)?;
+        writer.flush()?;
+        drop(writer);
+        fs::rename(tmp_path, path)
+    }
+
+    /// Add the counts of a completed file.
+    fn complete(&mut self, path: &str, counts_per_path: &MatchCounts) {
+        self.counts.add(counts_per_path);
+        self.completed_files.push(path.to_string());
+    }
+}
+
+/// A file searched by `count_matches`, waiting for the files before it to be written.
+struct SearchedFile {
+    path: String,
+    /// the counts of the file, or `None` if it could not be read
+    counts: Option<MatchCounts>,
+    /// the records of the file as JSONL
+    records: Vec<u8>,
+}
+
+/// The files searched by `count_matches` that are not written yet.
+///
+/// Files finish in any order when several are processed concurrently, so each one waits here until the files
+/// before it are written. This keeps the output in the order of the file list, and the checkpoint only completes
+/// files whose records are written.
+struct WriteQueue {
+    /// the index of the next file to write
+    next: usize,
+    pending: BTreeMap<usize, SearchedFile>,
+    checkpoint: Checkpoint,
+}
+
+impl WriteQueue {
+    /// Add a searched file, and write it and the files after it that are ready.
+    fn push(
+        &mut self,
+        index: usize,
+        file: SearchedFile,
+        output: &Mutex<Option<BufWriter<File>>>,
+        checkpoint_path: Option<&Path>,
+    ) -> std::io::Result<()> {
+        self.pending.insert(index, file);
+        while let Some(file) = self.pending.remove(&self.next) {
+            self.next += 1;
+            let mut output = output.lock().unwrap();
+            if let Some(output) = output.as_mut() {
+                output.write_all(&file.records)?;
+            }
+            // an unreadable file is not completed, so that a resumed run reads it again
+            if let (Some(checkpoint_path), Some(counts)) = (checkpoint_path, &file.counts) {
+                // the records of a completed file must be in the output file before the checkpoint is,
+                // as a resumed run appends to it without searching the file again
+                if let Some(output) = output.as_mut() {
+                    output.flush()?;
+                }
+                self.checkpoint.complete(&file.path, counts);
+                self.checkpoint.save(checkpoint_path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Search every file for the queries, writing the matches to `output`, and count the matches and matched tokens of each query.
+///
+/// The `--threads` threads are divided among the `--file-parallelism` files processed concurrently, each of which
+/// matches its queries in its own pool. The records are written in the order of `search_path_list`.
+/// A file that cannot be read is logged and skipped, or aborts the search if `--fail-fast` is set.
+/// With `--checkpoint`, the counts are saved after each file, and the files completed by a previous run are skipped
+/// and their counts added to the result.
+fn count_matches(
+    query_list: &[Vec<i32>],
+    query_thresholds: &[Option<f64>],
+    search_path_list: &[&PathBuf],
+    args: &Args,
+    document_reader: &DocumentReader,
+    output: &Mutex<Option<BufWriter<File>>>,
+    progress_bar: &ProgressBar,
+) -> std::io::Result<MatchCounts> {
+    // files are processed concurrently in `file_pool`, and the queries of each file in the pool of its worker
+    let file_pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(args.file_parallelism)
+        .build()
+        .expect("Failed to build file thread pool");
+    let threads = args.threads.unwrap_or_else(|| {
+        std::thread::available_parallelism().map_or(1, std::num::NonZeroUsize::get)
+    });
+    let query_pools = (0..args.file_parallelism)
+        .map(|_| {
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(max(threads / args.file_parallelism, 1))
+                .build()
+                .expect("Failed to build query thread pool")
+        })
+        .collect::<Vec<_>>();
+    let query_num = query_list.len();
+    // the n-grams of the queries are shared by all the files, and only needed outside of score mode
+    let query_ngram_list = if args.score_mode {
+        Vec::new()
+    } else {
+        query_pools[0].install(|| ngram_batch_par(query_list, args.n))
+    };
+    let checkpoint = match &args.checkpoint {
+        Some(checkpoint_path) if checkpoint_path.exists() => Checkpoint::load(checkpoint_path)?,
+        _ => Checkpoint {
+            counts: MatchCounts::new(query_num),
+            completed_files: Vec::new(),
+        },
+    };
+    if checkpoint.counts.count.len() != query_num {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!(
+                "checkpoint has {} queries, but {} are given",
+                checkpoint.counts.count.len(),
+                query_num
+            ),
+        ));
+    }
+    let completed_files = checkpoint
+        .completed_files
+        .iter()
+        .cloned()
+        .collect::<HashSet<String>>();
+    if !completed_files.is_empty() {
+        info!(
+            "resuming from checkpoint: {:?} files completed",
+            completed_files.len()
+        );
+    }
+    let search_paths = search_path_list
+        .iter()
+        .enumerate()
+        .filter(|(_, path)| {
+            let completed = completed_files.contains(path.to_str().unwrap());
+            if completed {
+                progress_bar.inc(1);
+            }
+            !completed
+        })
+        .collect::<Vec<_>>();
+    let resumed_counts = checkpoint.counts.clone();
+    let write_queue = Mutex::new(WriteQueue {
+        next: 0,
+        pending: BTreeMap::new(),
+        checkpoint,
+    });
+    let mut counts = file_pool.install(|| {
+        search_paths
+            .par_iter()
+            .enumerate()
+            .map(|(order, &(i, path))| -> std::io::Result<MatchCounts> {
+                let query_pool = &query_pools[rayon::current_thread_index().unwrap_or(0)];
+                let searched_file = search_file(
+                    query_list,
+                    &query_ngram_list,
+                    query_thresholds,
+                    path,
+                    args,
+                    document_reader,
+                    query_pool,
+                );
+                let searched_file = match searched_file {
+                    Ok(searched_file) => searched_file,
+                    Err(err) if args.fail_fast || is_malformed_line(&err) => return Err(err),
+                    Err(err) => {
+                        warn!("path idx: {:?} skipped: {}", i, err);
+                        SearchedFile {
+                            path: path.to_str().unwrap().to_string(),
+                            counts: None,
+                            records: Vec::new(),
+                        }
+                    }
+                };
+                let counts_per_path = searched_file
+                    .counts
+                    .clone()
+                    .unwrap_or_else(|| MatchCounts::new(query_num));
+                write_queue.lock().unwrap().push(
+                    order,
+                    searched_file,
+                    output,
+                    args.checkpoint.as_deref(),
+                )?;
+                info!("path idx: {:?} finished", i);
+                progress_bar.inc(1);
+                Ok(counts_per_path)
+            })
+            .try_reduce(
+                || MatchCounts::new(query_num),
+                |mut counts, counts_per_path| {
+                    counts.add(&counts_per_path);
+                    Ok(counts)
+                },
+            )
+    })?;
+    counts.add(&resumed_counts);
+    Ok(counts)
+}
+
+/// Read a file and match the queries against its documents in `query_pool`, serializing the records if `--output` is set.
+fn search_file(
+    query_list: &[Vec<i32>],
+    query_ngram_list: &[HashSet<usize>],
+    query_thresholds: &[Option<f64>],
+    path: &Path,
+    args: &Args,
+    document_reader: &DocumentReader,
+    query_pool: &rayon::ThreadPool,
+) -> std::io::Result<SearchedFile> {
+    info!("path: {:?} start loading token_ids_list", path);
+    let token_ids_list = document_reader.read(path)?;
+    info!("loaded token_ids_list");
+    let path = path.to_str().unwrap();
+    let total_tokens = token_ids_list.iter().map(Vec::len).sum();
+    let mut records = Vec::new();
+    let counts = query_pool.install(|| -> std::io::Result<MatchCounts> {
+        if args.score_mode {
+            let score_per_path = score(query_list, path, &token_ids_list, args.n);
+            if args.output.is_some() {
+                write_records(&mut records, &score_per_path)?;
+            }
+            // a document matches a query if its maximum similarity reaches the threshold,
+            // and a document shorter than the query has no span to match even with a threshold of 0
+            let count = score_per_path
+                .iter()
+                .zip(query_thresholds)
+                .zip(query_list)
+                .map(|((scores, query_threshold), query)| {
+                    let threshold = query_threshold.unwrap_or(args.threshold);
+                    scores
+                        .iter()
+                        .filter(|record| {
+                            token_ids_list[record.doc_index].len() >= query.len()
+                                && record.similarity >= threshold
+                        })
+                        .count()
+                })
+                .collect::<Vec<usize>>();
+            // the best span of a document has the length of the query
+            let matched_tokens = count
+                .iter()
+                .zip(query_list)
+                .map(|(count, query)| count * query.len())
+                .collect();
+            return Ok(MatchCounts {
+                count,
+                matched_tokens,
+                total_tokens,
+            });
+        }
+        let match_per_path = search(
+            query_list,
+            query_ngram_list,
+            &token_ids_list,
+            args.threshold,
+            query_thresholds,
+            args.n,
+            args.show_spans.then_some(args.max_span_print),
+        );
+        // the records are only built if they are written
+        if args.output.is_some() {
+            let match_records = match_per_path
+                .iter()
+                .enumerate()
+                .map(|(query_index, matches)| {
+                    matches
+                        .iter()
+                        .map(|m| MatchRecord {
+                            file: path.to_string(),
+                            doc_index: m.doc_index,
+                            query_index,
+                            start: m.start,
+                            end: m.end,
+                            similarity: m.similarity,
+                        })
+                        .collect()
+                })
+                .collect::<Vec<Vec<MatchRecord>>>();
+            write_records(&mut records, &match_records)?;
+        }
+        Ok(MatchCounts {
+            count: match_per_path.iter().map(|matches| matches.len()).collect(),
+            matched_tokens: match_per_path
+                .iter()
+                .map(|matches| matches.iter().map(|m| m.end - m.start).sum())
+                .collect(),
+            total_tokens,
+        })
+    })?;
+    Ok(SearchedFile {
+        path: path.to_string(),
+        counts: Some(counts),
+        records,
+    })
+}
+
+/// A logger printing each record while the progress bars are hidden, so that logging from worker threads does not garble them.
+struct ProgressLogger {
+    logger: env_logger::Logger,
+    multi_progress: MultiProgress,
+}
+
+impl log::Log for ProgressLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.logger.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.logger.matches(record) {
+            self.multi_progress.suspend(|| self.logger.log(record));
+        }
+    }
+
+    fn flush(&self) {
+        self.logger.flush();
+    }
+}
+
+/// Install a `ProgressLogger` configured from `RUST_LOG`, returning the `MultiProgress` to add progress bars to.
+fn init_logger() -> MultiProgress {
+    let logger = env_logger::Builder::from_default_env().build();
+    let max_level = logger.filter();
+    let multi_progress = MultiProgress::new();
+    log::set_boxed_logger(Box::new(ProgressLogger {
+        logger,
+        multi_progress: multi_progress.clone(),
+    }))
+    .expect("Failed to set logger");
+    log::set_max_level(max_level);
+    multi_progress
 }
 
 fn main() -> std::io::Result<()> {
     let args = Args::parse();
 
     env::set_var("RUST_LOG", "info");
-    env_logger::init();
+    let multi_progress = init_logger();
 
-    // read query
-    let file = File::open(&args.query_path)?;
-    let reader = BufReader::new(file);
-    let mut query_list_all = Vec::new();
-    for line in reader.lines() {
-        let line = line.unwrap();
-        let v: Value = serde_json::from_str(&line).unwrap();
-        let query: Vec<i32> = serde_json::from_value(v["token_ids"].clone()).unwrap();
-        query_list_all.push(query);
+    if let Some(num_cases) = args.verify {
+        let disagreements = verify(num_cases, args.seed);
+        for disagreement in &disagreements {
+            warn!(
+                "disagreement: fast: {:?} naive: {:?} threshold: {:?} n: {:?} query: {:?} doc: {:?}",
+                disagreement.fast,
+                disagreement.naive,
+                disagreement.threshold,
+                disagreement.n,
+                disagreement.query,
+                disagreement.doc
+            );
+        }
+        info!(
+            "verified {:?} cases with seed {:?}: {:?} disagreements",
+            num_cases,
+            args.seed,
+            disagreements.len()
+        );
+        if !disagreements.is_empty() {
+            return Err(std::io::Error::other(
+                "has_doc_duplicate disagrees with has_doc_duplicate_naive",
+            ));
+        }
+        return Ok(());
     }
 
+    // read query
+    let (query_list_all, query_sources) =
+        load_queries(&args.query_path, args.input_format, &args.token_field)?;
+
     info!("query_list_all: {:?}", query_list_all.len());
 
     // search path list
@@ -172,7 +1341,7 @@ fn main() -> std::io::Result<()> {
         .filter(|path| {
             let parts: Vec<&str> = path.to_str().unwrap().split("/").collect();
             let extracted_part = parts[parts.len() - 1];
-            if extracted_part.contains("-") == false {
+            if !extracted_part.contains("-") {
                 return false;
             }
             let file_idx: usize = extracted_part.split("-").collect::<Vec<&str>>()[1]
@@ -184,21 +1353,977 @@ fn main() -> std::io::Result<()> {
         })
         .collect();
     info!("search_path_list len: {:?}", search_path_list.len());
-    let mut count = vec![0; query_list_all.len()];
-    for (i, path) in search_path_list.iter().enumerate() {
-        let count_per_path = search(
-            &query_list_all,
-            path.to_str().unwrap(),
-            args.threshold,
-            args.n,
+    let document_reader = DocumentReader {
+        input_format: args.input_format,
+        token_field: args.token_field.clone(),
+        strict: args.strict,
+        cache_dir: args.cache_dir.clone(),
+    };
+    if let Some(cache_dir) = &args.cache_dir {
+        fs::create_dir_all(cache_dir)?;
+    }
+    // the modes without matching read the files in a pool of `--threads` threads
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(args.threads.unwrap_or(0))
+        .build()
+        .expect("Failed to build thread pool");
+    if args.stats_only {
+        let stats = pool.install(|| {
+            search_path_list
+                .par_iter()
+                .map(|path| -> std::io::Result<CorpusStats> {
+                    let token_ids_list = match document_reader.read(path) {
+                        Ok(token_ids_list) => token_ids_list,
+                        Err(err) if args.fail_fast => return Err(err.into()),
+                        Err(err) => {
+                            warn!("{}", err);
+                            return Ok(CorpusStats::default());
+                        }
+                    };
+                    let stats = CorpusStats::from_documents(&token_ids_list);
+                    info!(
+                        "path: {:?} documents: {:?} tokens: {:?}",
+                        path, stats.documents, stats.tokens
+                    );
+                    Ok(stats)
+                })
+                .try_reduce(CorpusStats::default, |a, b| Ok(a.merge(b)))
+        })?;
+        info!(
+            "documents: {:?} tokens: {:?}",
+            stats.documents, stats.tokens
         );
-        for (j, c) in count_per_path.iter().enumerate() {
-            count[j] += c;
+        for (bucket, count) in stats.length_histogram.iter().enumerate() {
+            info!("length {}: {:?}", bucket_label(bucket), count);
         }
-        info!("path idx: {:?} finished", i);
+        return Ok(());
+    }
+    if let Some(histogram_path) = &args.histogram {
+        let histogram = pool.install(|| {
+            similarity_histogram(&query_list_all, &search_path_list, &args, &document_reader)
+        })?;
+        info!("histogram: {:?}", histogram.counts);
+        histogram.write_csv(histogram_path)?;
+        return Ok(());
+    }
+    // a resumed run appends to the matches written before the interruption
+    let resumed = args
+        .checkpoint
+        .as_ref()
+        .is_some_and(|checkpoint_path| checkpoint_path.exists());
+    let output = match &args.output {
+        Some(output_path) if resumed => Some(BufWriter::new(
+            File::options()
+                .append(true)
+                .create(true)
+                .open(output_path)?,
+        )),
+        Some(output_path) => Some(BufWriter::new(File::create(output_path)?)),
+        None => None,
+    };
+    let progress_bar = if args.quiet {
+        ProgressBar::hidden()
+    } else {
+        multi_progress.add(
+            ProgressBar::new(search_path_list.len() as u64).with_style(
+                ProgressStyle::with_template(
+                    "[{elapsed_precise}] {wide_bar} {pos}/{len} files (ETA {eta_precise})",
+                )
+                .unwrap(),
+            ),
+        )
+    };
+    let output = Mutex::new(output);
+    let query_thresholds = query_sources
+        .iter()
+        .map(|source| source.threshold)
+        .collect::<Vec<_>>();
+    let counts = count_matches(
+        &query_list_all,
+        &query_thresholds,
+        &search_path_list,
+        &args,
+        &document_reader,
+        &output,
+        &progress_bar,
+    )?;
+    progress_bar.finish();
+    if let Some(output) = output.into_inner().unwrap().as_mut() {
+        output.flush()?;
     }
 
-    info!("count: {:?}", count);
+    info!("count: {:?}", counts.count);
+    info!("density: {:?}", counts.density());
+    if let Some(summary_path) = &args.summary {
+        write_summary(Path::new(summary_path), &counts, &query_sources)?;
+    }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use neardup::weighted_jaccard;
+
+    /// Create an empty directory for the test files.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("neardup-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn document_reader(input_format: InputFormat) -> DocumentReader {
+        DocumentReader {
+            input_format,
+            token_field: "token_ids".to_string(),
+            strict: false,
+            cache_dir: None,
+        }
+    }
+
+    fn sample_lines() -> String {
+        let docs = [
+            (0..100).collect::<Vec<i32>>(),
+            (50..150).collect::<Vec<i32>>(),
+            (1000..1100).collect::<Vec<i32>>(),
+        ];
+        docs.iter()
+            .map(|token_ids| serde_json::json!({ "token_ids": token_ids }).to_string() + "\n")
+            .collect()
+    }
+
+    #[test]
+    fn test_zstd_matches_gzip() {
+        let dir = test_dir("zstd");
+        let lines = sample_lines();
+        let gz_path = dir.join("sample-00000.jsonl.gz");
+        let mut encoder = GzEncoder::new(File::create(&gz_path).unwrap(), Compression::default());
+        encoder.write_all(lines.as_bytes()).unwrap();
+        encoder.finish().unwrap();
+        let zst_path = dir.join("sample-00000.jsonl.zst");
+        fs::write(&zst_path, zstd::encode_all(lines.as_bytes(), 0).unwrap()).unwrap();
+        let plain_path = dir.join("sample-00000.jsonl");
+        fs::write(&plain_path, &lines).unwrap();
+
+        let queries = vec![(60..90).collect::<Vec<i32>>(), (2000..2030).collect()];
+        let spans = |path: &Path| {
+            let token_ids_list = document_reader(InputFormat::Jsonl).read(path).unwrap();
+            search(
+                &queries,
+                &ngram_batch_par(&queries, 10),
+                &token_ids_list,
+                0.6,
+                &[None, None],
+                10,
+                None,
+            )
+            .iter()
+            .map(|matches| {
+                matches
+                    .iter()
+                    .map(|record| (record.doc_index, record.start, record.end))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>()
+        };
+        let gz_spans = spans(&gz_path);
+        assert_eq!(gz_spans[0].len(), 2);
+        assert!(gz_spans[1].is_empty());
+        assert_eq!(spans(&zst_path), gz_spans);
+        assert_eq!(spans(&plain_path), gz_spans);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_plain_input_format() {
+        assert_eq!(parse_plain_token_ids(" 1 2\t3  "), Some(vec![1, 2, 3]));
+        assert_eq!(parse_plain_token_ids("-5 10"), Some(vec![-5, 10]));
+        assert_eq!(parse_plain_token_ids("1 two 3"), None);
+
+        let dir = test_dir("plain");
+        let path = dir.join("sample-00000.txt");
+        let doc = (0..100)
+            .map(|i| i.to_string())
+            .collect::<Vec<_>>()
+            .join(" ");
+        fs::write(&path, format!("\n1 x 2\n{}\n   \n1000 1001 1002\n", doc)).unwrap();
+        let queries = vec![(60..90).collect::<Vec<i32>>()];
+        let token_ids_list = document_reader(InputFormat::Plain).read(&path).unwrap();
+        let matches = search(
+            &queries,
+            &ngram_batch_par(&queries, 10),
+            &token_ids_list,
+            1.0,
+            &[None],
+            10,
+            None,
+        );
+        // blank lines and the malformed line are skipped, so the first document has index 0
+        assert_eq!(token_ids_list.len(), 2);
+        assert_eq!(matches[0].len(), 1);
+        assert_eq!(matches[0][0].doc_index, 0);
+        assert_eq!((matches[0][0].start, matches[0][0].end), (60, 90));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_format_span() {
+        assert_eq!(format_span(&[1, 2, 3], 3), "[1, 2, 3]");
+        assert_eq!(format_span(&[1, 2, 3, 4, 5], 2), "[1, 2, ... (3 more)]");
+        assert_eq!(format_span(&[1, 2], 0), "[... (2 more)]");
+        assert_eq!(format_span(&[], 2), "[]");
+    }
+
+    #[test]
+    fn test_token_field() {
+        let line = r#"{"input_ids": [1, 2, 3], "text": "abc"}"#.to_string();
+        assert_eq!(
+            convert_to_token_ids(line.clone(), "input_ids"),
+            Some(vec![1, 2, 3])
+        );
+        assert_eq!(convert_to_token_ids(line, "token_ids"), Some(vec![]));
+        assert_eq!(convert_to_token_ids("{".to_string(), "token_ids"), None);
+    }
+
+    #[test]
+    fn test_malformed_lines() {
+        let dir = test_dir("malformed");
+        let path = dir.join("sample-00000.jsonl");
+        fs::write(
+            &path,
+            "{\"token_ids\": [1, 2]}\n{\"token_ids\": [3,\nnot json\n{\"token_ids\": [4]}\n",
+        )
+        .unwrap();
+        assert_eq!(
+            document_reader(InputFormat::Jsonl).read(&path).unwrap(),
+            vec![vec![1, 2], vec![4]]
+        );
+        let strict_reader = DocumentReader {
+            strict: true,
+            ..document_reader(InputFormat::Jsonl)
+        };
+        let err = strict_reader.read(&path).unwrap_err();
+        assert_eq!(err.line, Some(2));
+        assert_eq!(err.source.kind(), std::io::ErrorKind::InvalidData);
+        assert!(is_malformed_line(&err.into()));
+
+        // a strict search fails instead of skipping the file, even without `--fail-fast`
+        let args = Args::parse_from(["neardup", "--strict"]);
+        let result = count_matches(
+            &[vec![1, 2]],
+            &[None],
+            &[&path],
+            &args,
+            &strict_reader,
+            &Mutex::new(None),
+            &ProgressBar::hidden(),
+        );
+        assert!(result.is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_summary() {
+        let (queries, sources) = load_queries(
+            &["sample_data/query.jsonl".to_string()],
+            InputFormat::Jsonl,
+            "token_ids",
+        )
+        .unwrap();
+        let path = "sample_data/pythia-00000-00999.jsonl.gz";
+        let token_ids_list = document_reader(InputFormat::Jsonl)
+            .read(Path::new(path))
+            .unwrap();
+        let count = search(
+            &queries,
+            &ngram_batch_par(&queries, 10),
+            &token_ids_list,
+            0.6,
+            &[None, None],
+            10,
+            Some(8),
+        )
+        .iter()
+        .map(|matches| matches.len())
+        .collect::<Vec<_>>();
+        assert_eq!(count, vec![1, 1]);
+        let counts = MatchCounts {
+            count,
+            matched_tokens: vec![10, 20],
+            total_tokens: 100,
+        };
+
+        let dir = test_dir("summary");
+        let csv_path = dir.join("summary.csv");
+        write_summary(&csv_path, &counts, &sources).unwrap();
+        let csv = fs::read_to_string(&csv_path).unwrap();
+        let mut rows = csv.lines();
+        assert_eq!(
+            rows.next(),
+            Some("query_index,source_file,source_index,count,matched_tokens,density")
+        );
+        assert_eq!(
+            rows.collect::<Vec<_>>(),
+            vec![
+                "0,sample_data/query.jsonl,0,1,10,0.1",
+                "1,sample_data/query.jsonl,1,1,20,0.2"
+            ]
+        );
+
+        let json_path = dir.join("summary.json");
+        write_summary(&json_path, &counts, &sources).unwrap();
+        let summary: Vec<QuerySummary> =
+            serde_json::from_str(&fs::read_to_string(&json_path).unwrap()).unwrap();
+        assert_eq!(summary.len(), queries.len());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_multiple_query_files() {
+        let dir = test_dir("multi-query");
+        let first_path = dir.join("first.txt");
+        fs::write(&first_path, "60 61 62 63 64 65 66 67 68 69 70\n").unwrap();
+        let second_path = dir.join("second.txt");
+        fs::write(&second_path, "1000 1001 1002\n\n2000 2001 2002\n").unwrap();
+        let paths = [
+            first_path.to_str().unwrap().to_string(),
+            second_path.to_str().unwrap().to_string(),
+        ];
+        let (queries, sources) = load_queries(&paths, InputFormat::Plain, "token_ids").unwrap();
+        assert_eq!(queries.len(), 3);
+        assert_eq!(queries[2], vec![2000, 2001, 2002]);
+        assert_eq!(
+            sources,
+            vec![
+                QuerySource {
+                    file: paths[0].clone(),
+                    index: 0,
+                    threshold: None
+                },
+                QuerySource {
+                    file: paths[1].clone(),
+                    index: 0,
+                    threshold: None
+                },
+                QuerySource {
+                    file: paths[1].clone(),
+                    index: 1,
+                    threshold: None
+                },
+            ]
+        );
+
+        let args = Args::parse_from([
+            "neardup",
+            "--query-path",
+            &format!("{},{}", paths[0], paths[1]),
+            "--query-path",
+            &paths[0],
+        ]);
+        assert_eq!(
+            args.query_path,
+            vec![paths[0].clone(), paths[1].clone(), paths[0].clone()]
+        );
+
+        let summary_path = dir.join("summary.csv");
+        let counts = MatchCounts {
+            count: vec![1, 0, 2],
+            matched_tokens: vec![11, 0, 6],
+            total_tokens: 0,
+        };
+        write_summary(&summary_path, &counts, &sources).unwrap();
+        let csv = fs::read_to_string(&summary_path).unwrap();
+        assert_eq!(
+            csv.lines().nth(3),
+            Some(format!("2,{},1,2,6,0", paths[1]).as_str())
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_corpus_stats() {
+        assert_eq!(length_bucket(0), 0);
+        assert_eq!(length_bucket(1), 1);
+        assert_eq!(length_bucket(7), 3);
+        assert_eq!(length_bucket(8), 4);
+        assert_eq!(bucket_label(0), "0");
+        assert_eq!(bucket_label(3), "4-7");
+
+        let dir = test_dir("stats");
+        let path = dir.join("sample-00000.jsonl.gz");
+        let mut encoder = GzEncoder::new(File::create(&path).unwrap(), Compression::default());
+        encoder.write_all(sample_lines().as_bytes()).unwrap();
+        encoder
+            .write_all(b"\n{\"token_ids\": [1, 2, 3]}\n")
+            .unwrap();
+        encoder.finish().unwrap();
+
+        let stats =
+            CorpusStats::from_documents(&document_reader(InputFormat::Jsonl).read(&path).unwrap());
+        assert_eq!(stats.documents, 4);
+        assert_eq!(stats.tokens, 303);
+        assert_eq!(stats.length_histogram, vec![0, 0, 1, 0, 0, 0, 0, 3]);
+
+        let merged = stats.merge(CorpusStats::from_documents(&[vec![]]));
+        assert_eq!(merged.documents, 5);
+        assert_eq!(merged.length_histogram[0], 1);
+        assert_eq!(merged.length_histogram.iter().sum::<usize>(), 5);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_verify() {
+        for seed in 0..3 {
+            let disagreements = verify(300, seed);
+            assert!(disagreements.is_empty(), "{:?}", disagreements);
+        }
+    }
+
+    #[test]
+    fn test_cache() {
+        let dir = test_dir("cache");
+        let path = dir.join("sample-00000.jsonl");
+        fs::write(&path, sample_lines()).unwrap();
+        let cache_dir = dir.join("cache");
+        fs::create_dir_all(&cache_dir).unwrap();
+        let reader = DocumentReader {
+            cache_dir: Some(cache_dir.clone()),
+            ..document_reader(InputFormat::Jsonl)
+        };
+        let token_ids_list = reader.read(&path).unwrap();
+        assert_eq!(token_ids_list.len(), 3);
+        let cache_path = reader.cache_path(&cache_dir, &path);
+        assert!(cache_path.exists());
+        let modified = fs::metadata(&path).unwrap().modified().unwrap();
+        assert_eq!(
+            read_cache(&cache_path, modified).unwrap(),
+            Some(token_ids_list.clone())
+        );
+
+        // the cache is used while the file is unchanged
+        write_cache(&cache_path, modified, &[vec![-1, 7]]).unwrap();
+        assert_eq!(reader.read(&path).unwrap(), vec![vec![-1, 7]]);
+        // and is rebuilt once the file is modified
+        let file = File::options().write(true).open(&path).unwrap();
+        file.set_modified(modified + std::time::Duration::from_secs(1))
+            .unwrap();
+        assert_eq!(reader.read(&path).unwrap(), token_ids_list);
+        assert_eq!(reader.read(&path).unwrap(), token_ids_list);
+        // another token field is cached separately
+        let other_reader = DocumentReader {
+            token_field: "input_ids".to_string(),
+            cache_dir: Some(cache_dir.clone()),
+            ..document_reader(InputFormat::Jsonl)
+        };
+        assert_ne!(other_reader.cache_path(&cache_dir, &path), cache_path);
+
+        // a file cached with its malformed lines skipped still fails a strict read
+        fs::write(&path, "{\"token_ids\": [1, 2]}\nnot json\n").unwrap();
+        assert_eq!(reader.read(&path).unwrap(), vec![vec![1, 2]]);
+        let strict_reader = DocumentReader {
+            strict: true,
+            ..reader
+        };
+        assert_eq!(strict_reader.read(&path).unwrap_err().line, Some(2));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_unreadable_file() {
+        let dir = test_dir("unreadable");
+        let path = dir.join("sample-00000.jsonl");
+        fs::write(&path, sample_lines()).unwrap();
+        let missing_path = dir.join("sample-00001.jsonl");
+        let corrupt_path = dir.join("sample-00002.jsonl.gz");
+        fs::write(&corrupt_path, "not gzip").unwrap();
+        let reader = document_reader(InputFormat::Jsonl);
+        let err = reader.read(&missing_path).unwrap_err();
+        assert_eq!(err.source.kind(), std::io::ErrorKind::NotFound);
+        assert!(err.to_string().contains("sample-00001.jsonl"));
+        assert!(reader.read(&corrupt_path).is_err());
+
+        // the run continues past the unreadable files
+        let queries = vec![(60..90).collect::<Vec<i32>>()];
+        let search_path_list = vec![&missing_path, &path, &corrupt_path];
+        let count = |args: &[&str]| {
+            let args = Args::parse_from([&["neardup"], args].concat());
+            count_matches(
+                &queries,
+                &[None],
+                &search_path_list,
+                &args,
+                &reader,
+                &Mutex::new(None),
+                &ProgressBar::hidden(),
+            )
+        };
+        assert_eq!(count(&[]).unwrap().count, vec![2]);
+        // or aborts with `--fail-fast`
+        let err = count(&["--fail-fast"]).unwrap_err();
+        assert!(err.to_string().contains("sample-0000"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_score_mode() {
+        let queries = vec![(60..90).collect::<Vec<i32>>(), (2000..2030).collect()];
+        let token_ids_list = vec![(0..100).collect::<Vec<i32>>(), vec![5; 10], vec![]];
+        let scores = score(&queries, "sample-00000.jsonl", &token_ids_list, 10);
+        assert_eq!(scores.len(), queries.len());
+        for (query_index, records) in scores.iter().enumerate() {
+            assert_eq!(records.len(), token_ids_list.len());
+            for (doc_index, record) in records.iter().enumerate() {
+                assert_eq!(
+                    (record.query_index, record.doc_index),
+                    (query_index, doc_index)
+                );
+                assert!((0.0..=1.0).contains(&record.similarity));
+            }
+        }
+        assert_eq!(scores[0][0].similarity, 1.0);
+        assert_eq!(scores[1][0].similarity, 0.0);
+
+        // the output holds one score per document and query
+        let dir = test_dir("score");
+        let path = dir.join("sample-00000.jsonl");
+        fs::write(&path, sample_lines()).unwrap();
+        let output_path = dir.join("scores.jsonl");
+        let args = Args::parse_from([
+            "neardup",
+            "--score-mode",
+            "--output",
+            output_path.to_str().unwrap(),
+        ]);
+        let output = Mutex::new(Some(BufWriter::new(File::create(&output_path).unwrap())));
+        let count = count_matches(
+            &queries,
+            &[None, None],
+            &[&path],
+            &args,
+            &document_reader(InputFormat::Jsonl),
+            &output,
+            &ProgressBar::hidden(),
+        )
+        .unwrap();
+        assert_eq!(count.count, vec![2, 0]);
+        assert_eq!(count.matched_tokens, vec![60, 0]);
+        output.into_inner().unwrap().unwrap().flush().unwrap();
+        let records = fs::read_to_string(&output_path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<ScoreRecord>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(records.len(), 2 * 3);
+        assert!(records
+            .iter()
+            .all(|record| (0.0..=1.0).contains(&record.similarity)));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_query_threshold() {
+        let dir = test_dir("query-threshold");
+        let query_path = dir.join("query.jsonl");
+        fs::write(
+            &query_path,
+            "{\"token_ids\": [1, 2, 3, 4, 5, 6, 7, 8, 9, 10], \"threshold\": 0.9}\n\
+             {\"token_ids\": [1, 2, 3, 4, 5, 6, 7, 8, 9, 10]}\n\
+             {\"token_ids\": [1, 2, 3, 4, 5, 6, 7, 8, 9, 10], \"threshold\": 0.5}\n",
+        )
+        .unwrap();
+        let (queries, sources) = load_queries(
+            &[query_path.to_str().unwrap().to_string()],
+            InputFormat::Jsonl,
+            "token_ids",
+        )
+        .unwrap();
+        let query_thresholds = sources
+            .iter()
+            .map(|source| source.threshold)
+            .collect::<Vec<_>>();
+        assert_eq!(query_thresholds, vec![Some(0.9), None, Some(0.5)]);
+
+        // the document shares 7 of the 10 query tokens: 7 / 13 ~ 0.54
+        let token_ids_list = vec![vec![1, 2, 3, 4, 5, 6, 7, 0, 0, 0]];
+        let count = |threshold| {
+            search(
+                &queries,
+                &ngram_batch_par(&queries, 3),
+                &token_ids_list,
+                threshold,
+                &query_thresholds,
+                3,
+                None,
+            )
+            .iter()
+            .map(|matches| matches.len())
+            .collect::<Vec<_>>()
+        };
+        assert_eq!(count(0.6), vec![0, 0, 1]);
+        assert_eq!(count(0.5), vec![0, 1, 1]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_threshold_precision() {
+        // the span shares 6 of the 8 query tokens: 6 / 10 = 0.6 exactly in f64
+        let query = vec![1, 2, 3, 4, 5, 6, 7, 8];
+        let token_ids_list = vec![vec![0, 1, 2, 3, 4, 5, 6, -7, -8, 0]];
+        let n = 2;
+        assert_eq!(weighted_jaccard(&query, &token_ids_list[0][1..9]), 0.6);
+        // a threshold parsed as f32 is slightly above 0.6 once widened, and would miss the span
+        assert!(0.6f32 as f64 > 0.6);
+        let query_ngram = ngram(&query, n);
+        assert!(!has_doc_duplicate(
+            &token_ids_list[0],
+            &query,
+            &query_ngram,
+            0.6f32 as f64,
+            n
+        ));
+
+        let args = Args::parse_from(["neardup", "--threshold", "0.6"]);
+        assert_eq!(args.threshold, 0.6);
+        let matches = search(
+            &[query],
+            &[query_ngram],
+            &token_ids_list,
+            args.threshold,
+            &[None],
+            n,
+            None,
+        );
+        assert_eq!(matches[0].len(), 1);
+        assert_eq!(matches[0][0].similarity, 0.6);
+    }
+
+    #[test]
+    fn test_histogram() {
+        let mut histogram = SimilarityHistogram::new(4);
+        for similarity in [0.0, 0.1, 0.25, 0.6, 1.0, 0.99] {
+            histogram.add(similarity);
+        }
+        assert_eq!(histogram.counts, vec![2, 1, 1, 2]);
+
+        let dir = test_dir("histogram");
+        let paths = [
+            dir.join("sample-00000.jsonl"),
+            dir.join("sample-00001.jsonl"),
+        ];
+        for path in &paths {
+            fs::write(path, sample_lines()).unwrap();
+        }
+        let queries = vec![(60..90).collect::<Vec<i32>>(), (2000..2030).collect()];
+        let args = Args::parse_from(["neardup", "--bins", "10"]);
+        let histogram = similarity_histogram(
+            &queries,
+            &[&paths[0], &paths[1]],
+            &args,
+            &document_reader(InputFormat::Jsonl),
+        )
+        .unwrap();
+        // 2 files of 3 documents, each scored against 2 queries
+        assert_eq!(histogram.counts.len(), 10);
+        assert_eq!(histogram.counts.iter().sum::<usize>(), 2 * 3 * 2);
+        assert_eq!(histogram.counts[9], 2 * 2);
+
+        let csv_path = dir.join("histogram.csv");
+        histogram.write_csv(&csv_path).unwrap();
+        let csv = fs::read_to_string(&csv_path).unwrap();
+        let mut rows = csv.lines();
+        assert_eq!(rows.next(), Some("bin_start,bin_end,count"));
+        assert_eq!(
+            rows.next(),
+            Some(&*format!("0,0.1,{}", histogram.counts[0]))
+        );
+        assert_eq!(rows.count(), 9);
+        assert!(Args::try_parse_from(["neardup", "--bins", "0"]).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_threads() {
+        let dir = test_dir("threads");
+        let paths = [
+            dir.join("sample-00000.jsonl"),
+            dir.join("sample-00001.jsonl"),
+        ];
+        for path in &paths {
+            fs::write(path, sample_lines()).unwrap();
+        }
+        let queries = vec![
+            (60..90).collect::<Vec<i32>>(),
+            (2000..2030).collect(),
+            (10..40).collect(),
+        ];
+        let count = |args: &[&str]| {
+            let args = Args::parse_from([&["neardup"], args].concat());
+            count_matches(
+                &queries,
+                &[None, None, None],
+                &[&paths[0], &paths[1]],
+                &args,
+                &document_reader(InputFormat::Jsonl),
+                &Mutex::new(None),
+                &ProgressBar::hidden(),
+            )
+            .unwrap()
+            .count
+        };
+        assert_eq!(
+            Args::parse_from(["neardup", "--threads", "1"]).threads,
+            Some(1)
+        );
+        assert_eq!(Args::parse_from(["neardup"]).threads, None);
+        let default_count = count(&[]);
+        assert_eq!(default_count[1], 0);
+        assert!(default_count[0] > 0);
+        assert_eq!(count(&["--threads", "1"]), default_count);
+        assert_eq!(
+            count(&["--threads", "1", "--file-parallelism", "2"]),
+            default_count
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_file_parallelism() {
+        assert!(Args::try_parse_from(["neardup", "--file-parallelism", "0"]).is_err());
+
+        let dir = test_dir("file-parallelism");
+        let paths = (0..8)
+            .map(|i| dir.join(format!("sample-{:05}.jsonl", i)))
+            .collect::<Vec<_>>();
+        for path in &paths {
+            fs::write(path, sample_lines()).unwrap();
+        }
+        let search_path_list = paths.iter().collect::<Vec<_>>();
+        let queries = vec![(60..90).collect::<Vec<i32>>(), (10..40).collect()];
+        let output_path = dir.join("matches.jsonl");
+        let run = |file_parallelism: &str| {
+            let args = Args::parse_from([
+                "neardup",
+                "--file-parallelism",
+                file_parallelism,
+                "--threads",
+                "4",
+                "--output",
+                output_path.to_str().unwrap(),
+            ]);
+            let output = Mutex::new(Some(BufWriter::new(File::create(&output_path).unwrap())));
+            let counts = count_matches(
+                &queries,
+                &[None, None],
+                &search_path_list,
+                &args,
+                &document_reader(InputFormat::Jsonl),
+                &output,
+                &ProgressBar::hidden(),
+            )
+            .unwrap();
+            output.into_inner().unwrap().unwrap().flush().unwrap();
+            (counts, fs::read_to_string(&output_path).unwrap())
+        };
+        // the records follow the order of the files whichever file finishes first
+        let (counts, records) = run("1");
+        assert_eq!(run("4"), (counts, records.clone()));
+        let files = records
+            .lines()
+            .map(|line| serde_json::from_str::<MatchRecord>(line).unwrap().file)
+            .collect::<Vec<_>>();
+        assert_eq!(files.len(), paths.len() * 3);
+        assert!(files.windows(2).all(|pair| pair[0] <= pair[1]));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_checkpoint() {
+        let dir = test_dir("checkpoint");
+        let paths = [
+            dir.join("sample-00000.jsonl"),
+            dir.join("sample-00001.jsonl"),
+        ];
+        for path in &paths {
+            fs::write(path, sample_lines()).unwrap();
+        }
+        let queries = vec![(60..90).collect::<Vec<i32>>(), (2000..2030).collect()];
+        let checkpoint_path = dir.join("checkpoint.json");
+        let count = |paths: &[&PathBuf]| {
+            let args = Args::parse_from([
+                "neardup",
+                "--fail-fast",
+                "--checkpoint",
+                checkpoint_path.to_str().unwrap(),
+            ]);
+            count_matches(
+                &queries,
+                &[None, None],
+                paths,
+                &args,
+                &document_reader(InputFormat::Jsonl),
+                &Mutex::new(None),
+                &ProgressBar::hidden(),
+            )
+        };
+        // the checkpoint holds the counts of the completed files
+        assert_eq!(count(&[&paths[0]]).unwrap().count, vec![2, 0]);
+        let checkpoint = Checkpoint::load(&checkpoint_path).unwrap();
+        assert_eq!(
+            checkpoint,
+            Checkpoint {
+                counts: MatchCounts {
+                    count: vec![2, 0],
+                    matched_tokens: vec![60, 0],
+                    total_tokens: 300,
+                },
+                completed_files: vec![paths[0].to_str().unwrap().to_string()],
+            }
+        );
+
+        // a resumed run skips the completed file, which would fail to read with `--fail-fast`
+        fs::remove_file(&paths[0]).unwrap();
+        let resumed = count(&[&paths[0], &paths[1]]).unwrap();
+        assert_eq!(resumed.count, vec![4, 0]);
+        assert_eq!(resumed.total_tokens, 600);
+        let checkpoint = Checkpoint::load(&checkpoint_path).unwrap();
+        assert_eq!(checkpoint.counts, resumed);
+        assert_eq!(checkpoint.completed_files.len(), 2);
+        // and all files completed gives the saved counts
+        assert_eq!(count(&[&paths[0], &paths[1]]).unwrap(), resumed);
+
+        // a checkpoint of other queries is rejected
+        Checkpoint {
+            counts: MatchCounts::new(1),
+            completed_files: Vec::new(),
+        }
+        .save(&checkpoint_path)
+        .unwrap();
+        assert!(count(&[&paths[1]]).is_err());
+
+        // a checkpoint saved in an older format is reported as incompatible
+        fs::write(
+            &checkpoint_path,
+            r#"{"count": [2, 0], "completed_files": []}"#,
+        )
+        .unwrap();
+        let err = count(&[&paths[1]]).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("incompatible checkpoint"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_checkpoint_output() {
+        let dir = test_dir("checkpoint-output");
+        let paths = [
+            dir.join("sample-00000.jsonl"),
+            dir.join("sample-00001.jsonl"),
+        ];
+        for path in &paths {
+            fs::write(path, sample_lines()).unwrap();
+        }
+        let queries = vec![(60..90).collect::<Vec<i32>>()];
+        let checkpoint_path = dir.join("checkpoint.json");
+        let output_path = dir.join("matches.jsonl");
+        let args = Args::parse_from([
+            "neardup",
+            "--checkpoint",
+            checkpoint_path.to_str().unwrap(),
+            "--output",
+            output_path.to_str().unwrap(),
+        ]);
+        let count = |paths: &[&PathBuf], output: &Mutex<Option<BufWriter<File>>>| {
+            count_matches(
+                &queries,
+                &[None],
+                paths,
+                &args,
+                &document_reader(InputFormat::Jsonl),
+                output,
+                &ProgressBar::hidden(),
+            )
+            .unwrap()
+        };
+        let read_files = || {
+            fs::read_to_string(&output_path)
+                .unwrap()
+                .lines()
+                .map(|line| serde_json::from_str::<MatchRecord>(line).unwrap().file)
+                .collect::<Vec<_>>()
+        };
+        // the process is killed after the first file, so the buffered writer is never dropped
+        let output = Mutex::new(Some(BufWriter::new(File::create(&output_path).unwrap())));
+        count(&[&paths[0]], &output);
+        std::mem::forget(output);
+        let first_file = paths[0].to_str().unwrap().to_string();
+        assert_eq!(read_files(), vec![first_file.clone(); 2]);
+
+        // the resumed run appends the records of the second file only
+        let output = Mutex::new(Some(BufWriter::new(
+            File::options().append(true).open(&output_path).unwrap(),
+        )));
+        assert_eq!(count(&[&paths[0], &paths[1]], &output).count, vec![4]);
+        output.into_inner().unwrap().unwrap().flush().unwrap();
+        let second_file = paths[1].to_str().unwrap().to_string();
+        assert_eq!(
+            read_files(),
+            vec![
+                first_file.clone(),
+                first_file,
+                second_file.clone(),
+                second_file
+            ]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_density() {
+        let dir = test_dir("density");
+        let path = dir.join("sample-00000.jsonl");
+        fs::write(&path, sample_lines()).unwrap();
+        // the first query matches in the first two documents, the second one nowhere,
+        // and the third one is a whole document
+        let queries = vec![
+            (60..90).collect::<Vec<i32>>(),
+            (2000..2030).collect(),
+            (1000..1100).collect(),
+        ];
+        let count = |args: &[&str]| {
+            let args = Args::parse_from([&["neardup"], args].concat());
+            count_matches(
+                &queries,
+                &[None, None, None],
+                &[&path],
+                &args,
+                &document_reader(InputFormat::Jsonl),
+                &Mutex::new(None),
+                &ProgressBar::hidden(),
+            )
+            .unwrap()
+        };
+        let counts = count(&[]);
+        assert_eq!(counts.count, vec![2, 0, 1]);
+        assert_eq!(counts.total_tokens, 300);
+        let density = counts.density();
+        assert_eq!(density, vec![60.0 / 300.0, 0.0, 100.0 / 300.0]);
+        assert!(density.iter().all(|d| (0.0..=1.0).contains(d)));
+        assert_eq!(count(&["--score-mode"]), counts);
+        assert_eq!(MatchCounts::new(3).density(), vec![0.0; 3]);
+
+        // with a threshold of 0, every document matches in score mode except the ones shorter than the query
+        let short_lines = (0..10)
+            .map(|i| serde_json::json!({ "token_ids": [i, i + 1] }).to_string() + "\n")
+            .collect::<String>();
+        fs::write(&path, sample_lines() + &short_lines).unwrap();
+        let counts = count(&["--score-mode", "--threshold", "0"]);
+        assert_eq!(counts.count, vec![3, 3, 3]);
+        assert_eq!(counts.total_tokens, 320);
+        assert!(counts.density().iter().all(|d| (0.0..=1.0).contains(d)));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
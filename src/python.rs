@@ -0,0 +1,35 @@
+//! Python bindings, enabled by the `python` feature.
+
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+
+/// Check whether the document contains spans whose similarity to the query is above a threshold using rabin-karp method with fxhash.
+///
+/// The query n-grams are computed internally. Raises `ValueError` if the query is empty, `n == 0`, or `n > len(query)`.
+#[pyfunction]
+#[pyo3(name = "has_doc_duplicate")]
+fn py_has_doc_duplicate(
+    py: Python<'_>,
+    doc: Vec<i32>,
+    query: Vec<i32>,
+    threshold: f64,
+    n: usize,
+) -> PyResult<bool> {
+    let query_ngram = crate::ngram(&query, n);
+    py.allow_threads(|| crate::try_has_doc_duplicate(&doc, &query, &query_ngram, threshold, n))
+        .map_err(|err| PyValueError::new_err(err.to_string()))
+}
+
+/// Compute weighted jaccard similarity between two texts.
+#[pyfunction]
+#[pyo3(name = "weighted_jaccard")]
+fn py_weighted_jaccard(text1: Vec<i32>, text2: Vec<i32>) -> f64 {
+    crate::weighted_jaccard(&text1, &text2)
+}
+
+#[pymodule]
+fn neardup(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(py_has_doc_duplicate, m)?)?;
+    m.add_function(wrap_pyfunction!(py_weighted_jaccard, m)?)?;
+    Ok(())
+}
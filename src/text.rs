@@ -0,0 +1,71 @@
+//! Matching on raw strings, enabled by the `text` feature.
+//!
+//! Strings are tokenized into their Unicode scalar values (`char`s), so `n` counts characters
+//! and no pretokenized corpus is needed.
+
+use rustc_hash::FxHashSet as HashSet;
+
+/// Compute the n-grams of the characters of a string using fxhash.
+///
+/// Returns the same set as [`crate::ngram`] over the `char`s of the string.
+///
+/// # Examples
+///
+/// ```
+/// let ngrams = neardup::text::ngram_str("abcab", 2);
+/// assert_eq!(ngrams.len(), 3);
+/// assert_eq!(ngrams.contains(&fxhash::hash(&['a', 'b'][..])), true);
+/// ```
+pub fn ngram_str(text: &str, n: usize) -> HashSet<usize> {
+    crate::ngram(&text.chars().collect::<Vec<char>>(), n)
+}
+
+/// Check whether the document contains spans whose similarity to the query is above a threshold using rabin-karp method with fxhash,
+/// comparing the characters of the strings.
+///
+/// The query n-grams are computed internally. Returns false if the query is empty, `n == 0`, or `n` is larger than the number of characters of the query.
+///
+/// # Examples
+///
+/// ```
+/// let doc = "the quick brown fox jumps over the lazy dog";
+/// assert_eq!(neardup::text::has_doc_duplicate_str(doc, "quick brown fax", 0.8, 3), true);
+/// assert_eq!(neardup::text::has_doc_duplicate_str(doc, "slow green turtle", 0.8, 3), false);
+/// ```
+pub fn has_doc_duplicate_str(doc: &str, query: &str, threshold: f64, n: usize) -> bool {
+    let query = query.chars().collect::<Vec<char>>();
+    if crate::validate_query(&query, n).is_err() {
+        return false;
+    }
+    let doc = doc.chars().collect::<Vec<char>>();
+    let query_ngram = crate::ngram(&query, n);
+    crate::has_doc_duplicate(&doc, &query, &query_ngram, threshold, n)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_has_doc_duplicate_str() {
+        let paragraph = "Near-duplicate detection finds copies of a text. \
+                         Large language models may memorize their training data. \
+                         Counting near-duplicates of a query helps to measure it.";
+        let sentence = "Large language models may memorize their training data.";
+        assert!(has_doc_duplicate_str(paragraph, sentence, 1.0, 10));
+        // a sentence with a few edits is still a near-duplicate
+        let edited = "Large language models can memorise their training data.";
+        assert!(has_doc_duplicate_str(paragraph, edited, 0.8, 10));
+        assert!(!has_doc_duplicate_str(
+            paragraph,
+            "An unrelated sentence.",
+            0.8,
+            10
+        ));
+        // characters are compared, not bytes
+        assert!(has_doc_duplicate_str("über straße", "straße", 1.0, 3));
+        assert_eq!(ngram_str("straße", 3).len(), 4);
+        assert!(!has_doc_duplicate_str(paragraph, "", 0.8, 10));
+        assert!(!has_doc_duplicate_str(paragraph, "short", 0.8, 10));
+    }
+}
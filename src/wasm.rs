@@ -0,0 +1,29 @@
+//! WebAssembly bindings, enabled by the `wasm` feature.
+
+use wasm_bindgen::prelude::*;
+
+/// Check whether the document contains spans whose similarity to the query is above a threshold using rabin-karp method with fxhash.
+///
+/// The query n-grams are computed internally. Returns false if the query is empty, `n == 0`, or `n > query.len()`.
+#[wasm_bindgen]
+pub fn wasm_has_duplicate(doc: &[i32], query: &[i32], threshold: f64, n: usize) -> bool {
+    if crate::validate_query(query, n).is_err() {
+        return false;
+    }
+    let query_ngram = crate::ngram(query, n);
+    crate::has_doc_duplicate(doc, query, &query_ngram, threshold, n)
+}
+
+#[cfg(all(test, target_arch = "wasm32"))]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    #[wasm_bindgen_test]
+    fn test_wasm_has_duplicate() {
+        let doc = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+        assert!(wasm_has_duplicate(&doc, &[3, 4, 5, 6, 7], 0.8, 3));
+        assert!(!wasm_has_duplicate(&doc, &[11, 12, 13, 14, 15], 0.8, 3));
+        assert!(!wasm_has_duplicate(&doc, &[3, 4], 0.8, 3));
+    }
+}